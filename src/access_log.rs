@@ -0,0 +1,137 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use axum::http::Response;
+use tower_http::trace::OnResponse;
+use tracing::Span;
+
+// `on_response` of the trace layer logging one in `rate` of the successful
+// responses, 0 logs none of them. responses with an error status are always
+// logged, so sampling never hides a failure
+#[derive(Debug, Clone)]
+pub struct SampledOnResponse {
+    rate: u64,
+    seen: Arc<AtomicU64>,
+}
+
+impl SampledOnResponse {
+    pub fn new(rate: u64) -> Self {
+        Self {
+            rate,
+            seen: Arc::default(),
+        }
+    }
+
+    fn sampled(&self) -> bool {
+        self.rate > 0
+            && self
+                .seen
+                .fetch_add(1, Ordering::Relaxed)
+                .is_multiple_of(self.rate)
+    }
+}
+
+impl<B> OnResponse<B> for SampledOnResponse {
+    fn on_response(self, res: &Response<B>, latency: Duration, _span: &Span) {
+        let status = res.status();
+        let latency_ms = latency.as_millis() as u64;
+        if status.is_server_error() {
+            tracing::error!(
+                status = status.as_u16(),
+                latency_ms,
+                "finished processing request"
+            );
+        } else if status.is_client_error() {
+            tracing::info!(
+                status = status.as_u16(),
+                latency_ms,
+                "finished processing request"
+            );
+        } else if self.sampled() {
+            tracing::debug!(
+                status = status.as_u16(),
+                latency_ms,
+                "finished processing request"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::models::User;
+    use crate::test_support::{mongo_error, test_state};
+    use crate::{build_app, AppDatabase, AppState};
+    use axum::{body::Body, http::Request, http::StatusCode};
+    use tower::ServiceExt;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    // the statuses of the access log events
+    #[derive(Clone, Default)]
+    struct LoggedStatuses(Arc<std::sync::Mutex<Vec<u64>>>);
+
+    impl tracing::field::Visit for LoggedStatuses {
+        fn record_u64(&mut self, field: &tracing::field::Field, value: u64) {
+            if field.name() == "status" {
+                self.0.lock().unwrap().push(value);
+            }
+        }
+
+        fn record_debug(&mut self, _field: &tracing::field::Field, _value: &dyn std::fmt::Debug) {}
+    }
+
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for LoggedStatuses {
+        fn on_event(
+            &self,
+            event: &tracing::Event<'_>,
+            _ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            event.record(&mut self.clone());
+        }
+    }
+
+    #[test]
+    fn test_sampled_on_response_rate() {
+        let every_third = SampledOnResponse::new(3);
+        let sampled: Vec<bool> = (0..6).map(|_| every_third.sampled()).collect();
+        assert_eq!(sampled, vec![true, false, false, true, false, false]);
+        assert!(!SampledOnResponse::new(0).sampled());
+    }
+
+    #[tokio::test]
+    async fn test_sample_rate_zero_still_logs_errors() {
+        let logged = LoggedStatuses::default();
+        let subscriber = tracing_subscriber::registry().with(logged.clone());
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_find_one::<User>()
+            .times(1)
+            .returning(|_, _, _, _| Err(mongo_error("boom")));
+        let state = AppState {
+            config: Arc::new(Config {
+                log_sample_rate: 0,
+                ..Default::default()
+            }),
+            ..test_state(mock_db)
+        };
+        let app = build_app(state);
+        for uri in ["/schema/user", "/user"] {
+            let req = Request::builder().uri(uri).body(Body::empty()).unwrap();
+            app.clone().oneshot(req).await.unwrap();
+        }
+        let statuses = logged.0.lock().unwrap().clone();
+        assert_eq!(
+            statuses,
+            vec![StatusCode::INTERNAL_SERVER_ERROR.as_u16() as u64]
+        );
+    }
+}
@@ -0,0 +1,20 @@
+use mockall_double::double;
+
+use crate::models::AuditEntry;
+
+#[double]
+use crate::database::AppDatabase;
+
+pub const AUDIT_COLLECTION: &str = "audit";
+
+// store the entry in the audit collection, failures are only logged
+// because the audited write has already gone through at this point
+pub async fn record(database: &AppDatabase, db: &str, entry: &AuditEntry) {
+    if let Err(err) = database.insert_one(db, AUDIT_COLLECTION, entry, None).await {
+        tracing::error!(
+            "failed to write audit entry for user {}: {:?}",
+            entry.user_id,
+            err
+        );
+    }
+}
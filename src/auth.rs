@@ -0,0 +1,40 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::State,
+    http::{header, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::{config::Config, error::AppError};
+
+// compare without returning early so the time taken does not leak the token
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// middleware only letting through requests carrying `Authorization: Bearer <ADMIN_TOKEN>`,
+// when no admin token is configured every request is rejected
+pub async fn require_admin_token<B>(
+    State(config): State<Arc<Config>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let provided = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let authorized = match (&config.admin_token, provided) {
+        (Some(expected), Some(provided)) => {
+            constant_time_eq(expected.as_bytes(), provided.as_bytes())
+        }
+        _ => false,
+    };
+    if !authorized {
+        return AppError::Unauthorized("missing or invalid admin token".to_string())
+            .into_response();
+    }
+    next.run(req).await
+}
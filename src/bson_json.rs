@@ -0,0 +1,210 @@
+use mongodb::bson::{
+    ser::{Result as SerResult, SerializerOptions},
+    Bson, Decimal128, Document,
+};
+use serde::Serialize;
+use serde_json::Value;
+
+// exponent bias and largest coefficient of the IEEE 754-2008 decimal128 format
+pub const DECIMAL_EXPONENT_BIAS: i32 = 6176;
+const DECIMAL_MAX_COEFFICIENT: u128 = 10u128.pow(34) - 1;
+const DECIMAL_MAX_BIASED_EXPONENT: i32 = 0x2fff;
+
+// parse a decimal string like `-12.50` or `1.25E-8`, `None` when it is not
+// a finite decimal fitting in decimal128 without rounding
+pub fn parse_decimal(text: &str) -> Option<Decimal128> {
+    let (negative, unsigned) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let (mantissa, exponent) = match unsigned.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (mantissa, exponent.parse::<i32>().ok()?),
+        None => (unsigned, 0),
+    };
+    let (integer, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let digits = format!("{integer}{fraction}");
+    if digits.is_empty() || !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    let coefficient: u128 = digits.parse().ok()?;
+    if coefficient > DECIMAL_MAX_COEFFICIENT {
+        return None;
+    }
+    let biased = exponent.checked_sub(fraction.len() as i32)? + DECIMAL_EXPONENT_BIAS;
+    if !(0..=DECIMAL_MAX_BIASED_EXPONENT).contains(&biased) {
+        return None;
+    }
+    let sign = if negative { 1 << 127 } else { 0 };
+    let bits = sign | (biased as u128) << 113 | coefficient;
+    Some(Decimal128::from_bytes(bits.to_le_bytes()))
+}
+
+// `#[serde(with)]` module for an optional decimal which clients see as a
+// string. the driver writes through a serializer which is not human
+// readable, there the value stays a BSON decimal; see `to_stored_document`
+pub mod decimal_string {
+    use mongodb::bson::{Bson, Decimal128};
+    use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::{decimal_to_string, parse_decimal};
+
+    pub fn serialize<S: Serializer>(
+        value: &Option<Decimal128>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(decimal) if serializer.is_human_readable() => {
+                serializer.serialize_str(&decimal_to_string(decimal))
+            }
+            value => value.serialize(serializer),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Decimal128>, D::Error> {
+        match Option::<Bson>::deserialize(deserializer)? {
+            None | Some(Bson::Null) => Ok(None),
+            Some(Bson::Decimal128(decimal)) => Ok(Some(decimal)),
+            Some(Bson::String(text)) => parse_decimal(&text)
+                .map(Some)
+                .ok_or_else(|| D::Error::custom(format!("invalid decimal number `{text}`"))),
+            Some(other) => Err(D::Error::custom(format!(
+                "expected a decimal number as a string, got {other}"
+            ))),
+        }
+    }
+}
+
+// the document written to the database for `value`, its decimals stay BSON
+// decimals rather than the strings of the JSON API
+pub fn to_stored_document<T: Serialize + ?Sized>(value: &T) -> SerResult<Document> {
+    let options = SerializerOptions::builder().human_readable(false).build();
+    mongodb::bson::to_document_with_options(value, options)
+}
+
+// the decimal as a string, the way MongoDB prints it; this version of the
+// bson crate only gives access to the raw bytes
+pub fn decimal_to_string(decimal: &Decimal128) -> String {
+    let bits = u128::from_le_bytes(decimal.bytes());
+    let sign = if bits >> 127 == 1 { "-" } else { "" };
+    match (bits >> 122) & 0x1f {
+        0x1f => return "NaN".to_string(),
+        0x1e => return format!("{sign}Infinity"),
+        _ => {}
+    }
+    // with the two high combination bits set the coefficient would not
+    // fit in 34 digits, such values are read as zero
+    let (biased_exponent, coefficient) = if (bits >> 125) & 0b11 == 0b11 {
+        ((bits >> 111) & 0x3fff, 0)
+    } else {
+        ((bits >> 113) & 0x3fff, bits & ((1 << 113) - 1))
+    };
+    let coefficient = if coefficient > DECIMAL_MAX_COEFFICIENT {
+        0
+    } else {
+        coefficient
+    };
+    let exponent = biased_exponent as i32 - DECIMAL_EXPONENT_BIAS;
+    let digits = coefficient.to_string();
+    let adjusted = exponent + digits.len() as i32 - 1;
+    if exponent > 0 || adjusted < -6 {
+        let (first, rest) = digits.split_at(1);
+        let point = if rest.is_empty() { "" } else { "." };
+        return format!("{sign}{first}{point}{rest}E{adjusted:+}");
+    }
+    if exponent == 0 {
+        return format!("{sign}{digits}");
+    }
+    let integer_len = digits.len() as i32 + exponent;
+    if integer_len > 0 {
+        let (integer, fraction) = digits.split_at(integer_len as usize);
+        format!("{sign}{integer}.{fraction}")
+    } else {
+        let zeros = "0".repeat(-integer_len as usize);
+        format!("{sign}0.{zeros}{digits}")
+    }
+}
+
+// convert a BSON value to the JSON a client expects: decimals become
+// strings, so no precision is lost, and object ids their hex form. dates
+// keep the shape serde gives them elsewhere in the API
+pub fn to_plain_json(value: Bson) -> Value {
+    match value {
+        Bson::Decimal128(decimal) => Value::String(decimal_to_string(&decimal)),
+        Bson::ObjectId(oid) => Value::String(oid.to_hex()),
+        Bson::DateTime(date) => serde_json::to_value(date).unwrap_or(Value::Null),
+        Bson::Array(items) => Value::Array(items.into_iter().map(to_plain_json).collect()),
+        Bson::Document(document) => Value::Object(
+            document
+                .into_iter()
+                .map(|(key, value)| (key, to_plain_json(value)))
+                .collect(),
+        ),
+        other => other.into_relaxed_extjson(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::decimal;
+    use mongodb::bson::doc;
+    use serde_json::json;
+
+    #[test]
+    fn test_decimal_to_string() {
+        assert_eq!(decimal_to_string(&decimal(false, 1250, -2)), "12.50");
+        assert_eq!(decimal_to_string(&decimal(true, 1250, -2)), "-12.50");
+        assert_eq!(decimal_to_string(&decimal(false, 1, -3)), "0.001");
+        assert_eq!(decimal_to_string(&decimal(false, 42, 0)), "42");
+        assert_eq!(decimal_to_string(&decimal(false, 1, 3)), "1E+3");
+        assert_eq!(decimal_to_string(&decimal(false, 125, -10)), "1.25E-8");
+    }
+
+    #[test]
+    fn test_parse_decimal() {
+        assert_eq!(parse_decimal("12.50"), Some(decimal(false, 1250, -2)));
+        assert_eq!(parse_decimal("-0.001"), Some(decimal(true, 1, -3)));
+        assert_eq!(parse_decimal("+42"), Some(decimal(false, 42, 0)));
+        assert_eq!(parse_decimal("1.25E-8"), Some(decimal(false, 125, -10)));
+        for invalid in ["", ".", "12,5", "1e", "NaN", "1.2.3", "-"] {
+            assert_eq!(parse_decimal(invalid), None, "{invalid}");
+        }
+        let too_long = "1".repeat(35);
+        assert_eq!(parse_decimal(&too_long), None);
+        for text in ["12.50", "-0.001", "42", "1.25E-8"] {
+            let parsed = parse_decimal(text).unwrap();
+            assert_eq!(parse_decimal(&decimal_to_string(&parsed)), Some(parsed));
+        }
+    }
+
+    #[test]
+    fn test_to_stored_document_keeps_decimals() {
+        let user = crate::models::User {
+            balance: Some(decimal(false, 1250, -2)),
+            ..Default::default()
+        };
+        let stored = to_stored_document(&user).unwrap();
+        assert_eq!(
+            stored.get("balance"),
+            Some(&Bson::Decimal128(decimal(false, 1250, -2)))
+        );
+        let json = serde_json::to_value(&user).unwrap();
+        assert_eq!(json["balance"], "12.50");
+    }
+
+    #[test]
+    fn test_to_plain_json() {
+        let document = doc! {
+            "balance": decimal(false, 1999, -2),
+            "history": [decimal(false, 5, -1)],
+            "name": "Sibu",
+            "count": 3_i64,
+        };
+        assert_eq!(
+            to_plain_json(Bson::Document(document)),
+            json!({"balance": "19.99", "history": ["0.5"], "name": "Sibu", "count": 3})
+        );
+    }
+}
@@ -0,0 +1,54 @@
+use axum::{
+    body::{Body, Bytes},
+    http::{response::Parts, StatusCode},
+    response::{IntoResponse, Response},
+};
+use http_body::{LengthLimitError, Limited};
+
+use crate::error::AppError;
+
+// the same cap as `DefaultBodyLimit`, for a middleware reading a request
+// body before any extractor gets to limit it
+pub const MAX_REQUEST_BODY_BYTES: usize = 2 * 1024 * 1024;
+
+// read a whole request body, refusing it with a 413 once it grows past
+// `limit` instead of buffering it all first
+pub async fn read_request_body(body: Body, limit: usize) -> Result<Bytes, AppError> {
+    hyper::body::to_bytes(Limited::new(body, limit))
+        .await
+        .map_err(|err| {
+            if err.is::<LengthLimitError>() {
+                AppError::PayloadTooLarge(format!("request body must not exceed {limit} bytes"))
+            } else {
+                AppError::BadRequest(format!("failed to read request body: {err}"))
+            }
+        })
+}
+
+// split a response into its head and its whole body, for the middleware
+// rewriting bodies. a body failing to read is answered with a 500
+pub async fn buffer_response(res: Response) -> Result<(Parts, Bytes), Response> {
+    let (parts, body) = res.into_parts();
+    match hyper::body::to_bytes(body).await {
+        Ok(bytes) => Ok((parts, bytes)),
+        Err(err) => {
+            tracing::error!("failed to read response body: {:?}", err);
+            Err(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_read_request_body_limit() {
+        let bytes = read_request_body(Body::from("12345"), 5).await.unwrap();
+        assert_eq!(bytes, "12345");
+        let err = read_request_body(Body::from("123456"), 5)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::PayloadTooLarge(_)), "{err:?}");
+    }
+}
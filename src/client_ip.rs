@@ -0,0 +1,64 @@
+use std::net::{IpAddr, SocketAddr};
+
+use axum::{extract::ConnectInfo, http::Request};
+
+pub const FORWARDED_FOR_HEADER: &str = "x-forwarded-for";
+
+// address of the client behind the request. `X-Forwarded-For` can be set
+// by anyone, so it is only read when a trusted proxy sits in front of the
+// app; the last entry is the one that proxy appended itself, the earlier
+// ones came from the client and cannot be relied on
+pub fn client_ip<B>(req: &Request<B>, trust_proxy: bool) -> Option<IpAddr> {
+    let peer = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    if !trust_proxy {
+        return peer;
+    }
+    let forwarded = req
+        .headers()
+        .get_all(FORWARDED_FOR_HEADER)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .last()
+        .and_then(|entry| entry.trim().parse().ok());
+    forwarded.or(peer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+
+    fn request(forwarded_for: Option<&str>) -> Request<Body> {
+        let mut builder = Request::builder().uri("/");
+        if let Some(value) = forwarded_for {
+            builder = builder.header(FORWARDED_FOR_HEADER, value);
+        }
+        let mut req = builder.body(Body::empty()).unwrap();
+        req.extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([10, 0, 0, 1], 4000))));
+        req
+    }
+
+    #[test]
+    fn test_client_ip_from_trusted_proxy() {
+        let req = request(Some("1.2.3.4, 203.0.113.9"));
+        assert_eq!(client_ip(&req, true), "203.0.113.9".parse().ok());
+    }
+
+    #[test]
+    fn test_client_ip_ignores_header_when_untrusted() {
+        let req = request(Some("203.0.113.9"));
+        assert_eq!(client_ip(&req, false), "10.0.0.1".parse().ok());
+    }
+
+    #[test]
+    fn test_client_ip_falls_back_to_peer() {
+        assert_eq!(client_ip(&request(None), true), "10.0.0.1".parse().ok());
+        let req = request(Some("not-an-ip"));
+        assert_eq!(client_ip(&req, true), "10.0.0.1".parse().ok());
+    }
+}
@@ -0,0 +1,31 @@
+use std::fmt::Debug;
+
+use mongodb::bson::DateTime;
+
+// source of the current time, injected into the handlers so tests can
+// control which timestamps get generated
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> DateTime;
+}
+
+// clock backed by the system time
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime {
+        DateTime::now()
+    }
+}
+
+// clock always returning the same instant
+#[cfg(test)]
+#[derive(Debug, Clone, Copy)]
+pub struct FixedClock(pub DateTime);
+
+#[cfg(test)]
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime {
+        self.0
+    }
+}
@@ -0,0 +1,120 @@
+use axum::{
+    http::{header, HeaderMap, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::error::AppError;
+
+// the pieces of a `type/subtype; name=value` header, the names lowercased
+// and the values unquoted
+#[derive(Debug, PartialEq, Eq)]
+pub struct MediaType {
+    pub essence: String,
+    pub params: Vec<(String, String)>,
+}
+
+impl MediaType {
+    pub fn parse(value: &str) -> Self {
+        let mut parts = value.split(';');
+        let essence = parts.next().unwrap_or("").trim().to_ascii_lowercase();
+        let params = parts
+            .filter_map(|param| param.split_once('='))
+            .map(|(name, value)| {
+                let value = value.trim();
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|value| value.strip_suffix('"'))
+                    .unwrap_or(value);
+                (name.trim().to_ascii_lowercase(), value.to_string())
+            })
+            .collect();
+        Self { essence, params }
+    }
+
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let value = headers.get(header::CONTENT_TYPE)?.to_str().ok()?;
+        Some(Self::parse(value))
+    }
+
+    // `application/json`, or an `application/*+json` type
+    pub fn is_json(&self) -> bool {
+        self.essence == "application/json"
+            || (self.essence.starts_with("application/") && self.essence.ends_with("+json"))
+    }
+
+    pub fn charset(&self) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(name, _)| name == "charset")
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+// middleware rejecting JSON bodies declared in another charset than UTF-8
+// with 415, the payloads are always decoded as UTF-8. JSON without a charset
+// is UTF-8 by definition
+pub async fn require_utf8_json<B>(req: Request<B>, next: Next<B>) -> Response {
+    if let Some(media_type) = MediaType::from_headers(req.headers()) {
+        match media_type.charset() {
+            Some(charset) if media_type.is_json() && !charset.eq_ignore_ascii_case("utf-8") => {
+                let message = format!("unsupported charset `{charset}`, only utf-8 is accepted");
+                return AppError::UnsupportedMediaType(message).into_response();
+            }
+            _ => {}
+        }
+    }
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::StatusCode, routing::post, Router};
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_parse_media_type() {
+        assert_eq!(
+            MediaType::parse(r#"Application/JSON ; Charset="UTF-8"; q=1"#),
+            MediaType {
+                essence: "application/json".to_string(),
+                params: vec![
+                    ("charset".to_string(), "UTF-8".to_string()),
+                    ("q".to_string(), "1".to_string()),
+                ],
+            }
+        );
+        assert!(MediaType::parse("application/merge-patch+json").is_json());
+        assert!(!MediaType::parse("text/json").is_json());
+    }
+
+    async fn post_with_content_type(content_type: &str) -> StatusCode {
+        let app = Router::new()
+            .route("/", post(|| async {}))
+            .layer(axum::middleware::from_fn(require_utf8_json));
+        let req = Request::builder()
+            .method("POST")
+            .uri("/")
+            .header(header::CONTENT_TYPE, content_type)
+            .body(Body::from("{}"))
+            .unwrap();
+        app.oneshot(req).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn test_require_utf8_json() {
+        assert_eq!(
+            post_with_content_type("application/json; charset=utf-8").await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            post_with_content_type("application/json").await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            post_with_content_type("application/json; charset=iso-8859-1").await,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+    }
+}
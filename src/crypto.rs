@@ -0,0 +1,137 @@
+use std::{fmt, str::FromStr};
+
+use rand::Rng;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+
+// marks a value encrypted by FieldCipher, values without it are
+// plaintext written before encryption got enabled
+const ENCRYPTED_PREFIX: &str = "enc:v1:";
+
+// 256 bit AES key given base64 encoded, `Debug` never prints the key
+#[derive(Clone, PartialEq, Eq)]
+pub struct EncryptionKey([u8; 32]);
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EncryptionKey(..)")
+    }
+}
+
+impl FromStr for EncryptionKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = base64::decode(s.trim()).map_err(|_| "key is not valid base64".to_string())?;
+        let key = bytes
+            .try_into()
+            .map_err(|_| "key must be 32 bytes long".to_string())?;
+        Ok(Self(key))
+    }
+}
+
+#[derive(Debug)]
+pub struct DecryptError;
+
+impl fmt::Display for DecryptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("encrypted value is malformed or was tampered with")
+    }
+}
+
+impl std::error::Error for DecryptError {}
+
+// AES-256-GCM encryption of single fields, every value gets a random
+// nonce which is stored with it as `enc:v1:base64(nonce | ciphertext | tag)`
+#[derive(Debug)]
+pub struct FieldCipher {
+    key: LessSafeKey,
+}
+
+impl FieldCipher {
+    pub fn new(key: &EncryptionKey) -> Self {
+        let key = UnboundKey::new(&AES_256_GCM, &key.0).expect("key has the AES-256 length");
+        Self {
+            key: LessSafeKey::new(key),
+        }
+    }
+
+    pub fn encrypt(&self, plaintext: &str) -> String {
+        let nonce: [u8; NONCE_LEN] = rand::thread_rng().gen();
+        let mut in_out = plaintext.as_bytes().to_vec();
+        self.key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::empty(),
+                &mut in_out,
+            )
+            .expect("field is small enough to encrypt");
+        let mut sealed = nonce.to_vec();
+        sealed.extend(in_out);
+        format!("{ENCRYPTED_PREFIX}{}", base64::encode(sealed))
+    }
+
+    // plaintext values are returned as they are
+    pub fn decrypt(&self, stored: &str) -> Result<String, DecryptError> {
+        let Some(encoded) = stored.strip_prefix(ENCRYPTED_PREFIX) else {
+            return Ok(stored.to_string());
+        };
+        let sealed = base64::decode(encoded).map_err(|_| DecryptError)?;
+        if sealed.len() < NONCE_LEN {
+            return Err(DecryptError);
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| DecryptError)?;
+        let mut in_out = ciphertext.to_vec();
+        let plaintext = self
+            .key
+            .open_in_place(nonce, Aad::empty(), &mut in_out)
+            .map_err(|_| DecryptError)?;
+        String::from_utf8(plaintext.to_vec()).map_err(|_| DecryptError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cipher() -> FieldCipher {
+        FieldCipher::new(&EncryptionKey([7; 32]))
+    }
+
+    #[test]
+    fn test_encrypt_decrypt_round_trip() {
+        let cipher = cipher();
+        let encrypted = cipher.encrypt("+9156565656");
+        assert!(encrypted.starts_with(ENCRYPTED_PREFIX));
+        assert!(!encrypted.contains("56565656"));
+        assert_ne!(encrypted, cipher.encrypt("+9156565656"));
+        assert_eq!(cipher.decrypt(&encrypted).unwrap(), "+9156565656");
+    }
+
+    #[test]
+    fn test_decrypt_detects_tampering() {
+        let cipher = cipher();
+        let encrypted = cipher.encrypt("56565656");
+        let mut sealed = base64::decode(&encrypted[ENCRYPTED_PREFIX.len()..]).unwrap();
+        let last = sealed.len() - 1;
+        sealed[last] ^= 1;
+        let tampered = format!("{ENCRYPTED_PREFIX}{}", base64::encode(sealed));
+        assert!(cipher.decrypt(&tampered).is_err());
+        let other_key = FieldCipher::new(&EncryptionKey([8; 32]));
+        assert!(other_key.decrypt(&encrypted).is_err());
+    }
+
+    #[test]
+    fn test_decrypt_passes_plaintext_through() {
+        assert_eq!(cipher().decrypt("56565656").unwrap(), "56565656");
+    }
+
+    #[test]
+    fn test_encryption_key_from_str() {
+        let key: EncryptionKey = base64::encode([1; 32]).parse().unwrap();
+        assert_eq!(key, EncryptionKey([1; 32]));
+        assert!(base64::encode([1; 16]).parse::<EncryptionKey>().is_err());
+        assert!("not base64!".parse::<EncryptionKey>().is_err());
+        assert_eq!(format!("{key:?}"), "EncryptionKey(..)");
+    }
+}
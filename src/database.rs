@@ -1,30 +1,389 @@
+use std::{
+    fmt::Debug,
+    future::Future,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use futures::{stream::BoxStream, StreamExt, TryStreamExt};
 use mockall::automock;
 use mongodb::{
-    bson::{Bson, Document},
-    error::Result as MongoResult,
-    options::{ClientOptions, FindOneOptions, InsertOneOptions},
-    Client,
+    bson::{doc, Bson, Document},
+    error::{ErrorKind, Result as MongoResult},
+    options::{
+        AggregateOptions, ClientOptions, CountOptions, DeleteOptions, FindOneOptions, FindOptions,
+        InsertManyOptions, InsertOneOptions, Tls, TlsOptions, TransactionOptions, UpdateOptions,
+        WriteConcern,
+    },
+    Client, ClientSession, IndexModel,
 };
 use serde::{de::DeserializeOwned, Serialize};
 
+use crate::bson_json::to_stored_document;
+
 pub const DB_NAME: &str = "myDB";
+// name the app connects under unless MONGODB_APP_NAME is set
+pub const DEFAULT_APP_NAME: &str = "axum_testing";
 
 #[derive(Debug, Clone)]
 pub struct InsertOneResult {
     pub inserted_id: String,
 }
 
-// struct to hold the dabatabse client that can be used in the application
+// matched and modified counts of an update
+#[derive(Debug, Clone, PartialEq)]
+pub struct UpdateResult {
+    pub matched_count: u64,
+    pub modified_count: u64,
+}
+
+// write options which carry a write concern
+trait WriteOptions: Default {
+    fn write_concern_mut(&mut self) -> &mut Option<WriteConcern>;
+}
+
+impl WriteOptions for InsertOneOptions {
+    fn write_concern_mut(&mut self) -> &mut Option<WriteConcern> {
+        &mut self.write_concern
+    }
+}
+
+impl WriteOptions for InsertManyOptions {
+    fn write_concern_mut(&mut self) -> &mut Option<WriteConcern> {
+        &mut self.write_concern
+    }
+}
+
+impl WriteOptions for UpdateOptions {
+    fn write_concern_mut(&mut self) -> &mut Option<WriteConcern> {
+        &mut self.write_concern
+    }
+}
+
+impl WriteOptions for DeleteOptions {
+    fn write_concern_mut(&mut self) -> &mut Option<WriteConcern> {
+        &mut self.write_concern
+    }
+}
+
+// apply the configured write concern unless the caller picked one already
+fn with_write_concern<O: WriteOptions>(
+    options: Option<O>,
+    write_concern: &Option<WriteConcern>,
+) -> Option<O> {
+    let Some(write_concern) = write_concern else {
+        return options;
+    };
+    let mut options = options.unwrap_or_default();
+    options
+        .write_concern_mut()
+        .get_or_insert_with(|| write_concern.clone());
+    Some(options)
+}
+
+// TLS settings to apply on top of the connection string, `None` leaves
+// whatever the connection string asks for untouched
+pub fn tls_options(ca_file: Option<&Path>, allow_invalid_certificates: bool) -> Option<Tls> {
+    if ca_file.is_none() && !allow_invalid_certificates {
+        return None;
+    }
+    let mut options = TlsOptions::default();
+    options.ca_file_path = ca_file.map(Path::to_path_buf);
+    if allow_invalid_certificates {
+        options.allow_invalid_certificates = Some(true);
+    }
+    Some(Tls::Enabled(options))
+}
+
+// try the connection strings in order and return the first connection,
+// or the last error when none works. uris are only referred to by their
+// position in the logs since they may carry credentials
+pub async fn connect_with_fallback<T, E, F, Fut>(uris: &[&str], connect: F) -> Result<T, E>
+where
+    E: Debug,
+    F: Fn(&str) -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut last_err = None;
+    for (i, uri) in uris.iter().enumerate() {
+        let name = if i == 0 { "primary" } else { "fallback" };
+        match connect(uri).await {
+            Ok(conn) => {
+                tracing::info!("connected to MongoDB using the {name} uri #{i}");
+                return Ok(conn);
+            }
+            Err(err) => {
+                tracing::warn!("failed to connect to MongoDB using the {name} uri #{i}: {err:?}");
+                last_err = Some(err);
+            }
+        }
+    }
+    Err(last_err.expect("at least one uri to connect to"))
+}
+
+// run a database operation and warn when it took longer than the threshold
+async fn log_if_slow<F: Future>(
+    threshold: Duration,
+    operation: &str,
+    coll: &str,
+    query: F,
+) -> F::Output {
+    let started = Instant::now();
+    let output = query.await;
+    let elapsed = started.elapsed();
+    if elapsed > threshold {
+        tracing::warn!(
+            operation,
+            collection = coll,
+            elapsed_ms = elapsed.as_millis() as u64,
+            "slow query"
+        );
+    }
+    output
+}
+
+// fail the operation with a `TimedOut` io error once it runs past the
+// timeout, rather than waiting on a hung server indefinitely
+async fn with_timeout<T, F>(
+    timeout: Option<Duration>,
+    operation: &str,
+    coll: &str,
+    query: F,
+) -> MongoResult<T>
+where
+    F: Future<Output = MongoResult<T>>,
+{
+    let Some(timeout) = timeout else {
+        return query.await;
+    };
+    match tokio::time::timeout(timeout, query).await {
+        Ok(output) => output,
+        Err(_) => {
+            let message = format!(
+                "{operation} on `{coll}` timed out after {} ms",
+                timeout.as_millis()
+            );
+            Err(std::io::Error::new(std::io::ErrorKind::TimedOut, message).into())
+        }
+    }
+}
+
+// refuse writes to the collections outside the allowlist, an empty
+// allowlist lets every write through
+fn check_writable(writable_collections: &[String], coll: &str) -> MongoResult<()> {
+    if writable_collections.is_empty() || writable_collections.iter().any(|c| c == coll) {
+        return Ok(());
+    }
+    let message = format!("collection `{coll}` is not writable");
+    Err(std::io::Error::new(std::io::ErrorKind::PermissionDenied, message).into())
+}
+
+// roll a transaction back, the error is only logged since the one which
+// made it fail is what the caller needs to see
+async fn abort(session: &mut ClientSession) {
+    if let Err(err) = session.abort_transaction().await {
+        tracing::warn!("failed to abort the transaction: {:?}", err);
+    }
+}
+
+// read-only diagnostic commands `run_command` accepts
+pub const SAFE_COMMANDS: [&str; 3] = ["ping", "serverStatus", "dbStats"];
+
+// refuse every command outside `SAFE_COMMANDS`, the name of a command is its first key
+fn check_safe_command(command: &Document) -> MongoResult<()> {
+    let name = command.keys().next().map_or("", String::as_str);
+    if SAFE_COMMANDS.contains(&name) {
+        return Ok(());
+    }
+    let message = format!("command `{name}` is not allowed");
+    Err(std::io::Error::new(std::io::ErrorKind::PermissionDenied, message).into())
+}
+
+// true for a connection lost to the server, which the driver recovers from
+// by reconnecting, so running the operation again is expected to work. the
+// io errors raised by this module, like a timeout, are not among them
+pub fn is_reconnecting(err: &mongodb::error::Error) -> bool {
+    use std::io::ErrorKind as Io;
+    match err.kind.as_ref() {
+        ErrorKind::ConnectionPoolCleared { .. } => true,
+        ErrorKind::Io(io) => matches!(
+            io.kind(),
+            Io::ConnectionReset
+                | Io::ConnectionAborted
+                | Io::ConnectionRefused
+                | Io::BrokenPipe
+                | Io::NotConnected
+                | Io::UnexpectedEof
+        ),
+        _ => false,
+    }
+}
+
+// true when no server can be reached at all, which the client is told as
+// a 503, see `AppError::from`
+pub fn is_unavailable(err: &mongodb::error::Error) -> bool {
+    matches!(err.kind.as_ref(), ErrorKind::ServerSelection { .. }) || is_reconnecting(err)
+}
+
+// name the app shows up under in the server logs and in `currentOp`,
+// it replaces an `appName` given in the connection string
+fn set_app_name(options: &mut ClientOptions, app_name: &str) {
+    options.app_name = Some(app_name.to_string());
+}
+
+// options of a client for `uri`, with the TLS and app name settings applied
+async fn client_options(uri: &str, tls: Option<Tls>, app_name: &str) -> MongoResult<ClientOptions> {
+    let mut client_options = ClientOptions::parse(uri).await?;
+    set_app_name(&mut client_options, app_name);
+    if tls.is_some() {
+        client_options.tls = tls;
+    }
+    Ok(client_options)
+}
+
+// struct to hold the database client that can be used in the application,
+// the real implementation is swapped out by the mock in tests
 #[derive(Debug, Clone)]
-pub struct AppDatabase(Client);
+#[cfg_attr(test, allow(dead_code))]
+pub struct AppDatabase {
+    // the writes, and the reads too unless `read_client` is set
+    client: Client,
+    // the finds, counts and aggregations when reads have a uri of their own
+    read_client: Option<Client>,
+    // default write concern of the insert and update methods
+    write_concern: Option<WriteConcern>,
+    slow_query_threshold: Duration,
+    // the `/dbname` of the connection string
+    default_db: Option<String>,
+    // the `minPoolSize` of the connection string
+    min_pool_size: Option<u32>,
+    // see `check_writable`
+    writable_collections: Vec<String>,
+    // longest a single operation may take, unbounded when unset
+    operation_timeout: Option<Duration>,
+}
+
+#[cfg_attr(test, allow(dead_code))]
+impl AppDatabase {
+    // the client the reads go to
+    fn reader(&self) -> &Client {
+        self.read_client.as_ref().unwrap_or(&self.client)
+    }
+
+    // every operation goes through here to get the timeout and the slow query log
+    async fn run<T, F>(&self, operation: &str, coll: &str, query: F) -> MongoResult<T>
+    where
+        F: Future<Output = MongoResult<T>>,
+    {
+        let query = with_timeout(self.operation_timeout, operation, coll, query);
+        log_if_slow(self.slow_query_threshold, operation, coll, query).await
+    }
+}
 
 #[automock]
+#[cfg_attr(test, allow(dead_code))]
 impl AppDatabase {
     // create new Mongo DB client and instantiate AppDatabase
-    pub async fn new(uri: &str) -> MongoResult<Self> {
-        let client_options = ClientOptions::parse(uri).await?;
-        let client = Client::with_options(client_options)?;
-        Ok(Self(client))
+    pub async fn new(
+        uri: &str,
+        read_uri: Option<String>,
+        write_concern: Option<WriteConcern>,
+        tls: Option<Tls>,
+        slow_query_threshold: Duration,
+        app_name: &str,
+    ) -> MongoResult<Self> {
+        let options = client_options(uri, tls.clone(), app_name).await?;
+        let default_db = options.default_database.clone();
+        let min_pool_size = options.min_pool_size;
+        let client = Client::with_options(options)?;
+        let read_client = match read_uri {
+            Some(read_uri) => Some(Client::with_options(
+                client_options(&read_uri, tls, app_name).await?,
+            )?),
+            None => None,
+        };
+        Ok(Self {
+            client,
+            read_client,
+            write_concern,
+            slow_query_threshold,
+            default_db,
+            min_pool_size,
+            writable_collections: Vec::new(),
+            operation_timeout: None,
+        })
+    }
+
+    // only let the insert and update methods write to these collections
+    pub fn with_writable_collections(mut self, collections: Vec<String>) -> Self {
+        self.writable_collections = collections;
+        self
+    }
+
+    // bound every operation, see `with_timeout`
+    pub fn with_operation_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.operation_timeout = timeout;
+        self
+    }
+
+    // database named in the connection string, if any
+    pub fn default_db_name(&self) -> Option<String> {
+        self.default_db.clone()
+    }
+
+    // connections the driver keeps open, unset when the connection string
+    // does not ask for any
+    pub fn min_pool_size(&self) -> Option<u32> {
+        self.min_pool_size
+    }
+
+    // check that the server is reachable, both of them when reads are split off.
+    // the commands are not run against a collection, the database stands in
+    // for it in the timeout and slow query logs
+    pub async fn ping(&self) -> MongoResult<()> {
+        let admin = self.client.database("admin");
+        let query = admin.run_command(doc! {"ping": 1}, None);
+        self.run("ping", "admin", query).await?;
+        if let Some(read_client) = &self.read_client {
+            let admin = read_client.database("admin");
+            let query = admin.run_command(doc! {"ping": 1}, None);
+            self.run("ping", "admin", query).await?;
+        }
+        Ok(())
+    }
+
+    // run one of the `SAFE_COMMANDS`, for diagnostics
+    pub async fn run_command(&self, db: &str, command: Document) -> MongoResult<Document> {
+        check_safe_command(&command)?;
+        let database = self.client.database(db);
+        let query = database.run_command(command, None);
+        self.run("run_command", db, query).await
+    }
+
+    pub async fn list_collections(&self, db: &str) -> MongoResult<Vec<String>> {
+        let database = self.client.database(db);
+        let query = database.list_collection_names(None);
+        self.run("list_collections", db, query).await
+    }
+
+    // names of the indexes of a collection, `_id_` included
+    pub async fn list_indexes(&self, db: &str, coll: &str) -> MongoResult<Vec<String>> {
+        let collection = self.client.database(db).collection::<Document>(coll);
+        self.run("list_indexes", coll, collection.list_index_names())
+            .await
+    }
+
+    // a no-op when an identical index exists already, its name is returned
+    pub async fn create_index(
+        &self,
+        db: &str,
+        coll: &str,
+        index: IndexModel,
+    ) -> MongoResult<String> {
+        let collection = self.client.database(db).collection::<Document>(coll);
+        let query = collection.create_index(index, None);
+        let result = self.run("create_index", coll, query).await?;
+        Ok(result.index_name)
     }
 
     pub async fn find_one<T>(
@@ -37,8 +396,83 @@ impl AppDatabase {
     where
         T: DeserializeOwned + Unpin + Send + Sync + 'static,
     {
-        let collection = self.0.database(db).collection::<T>(coll);
-        collection.find_one(filter, options).await
+        let collection = self.reader().database(db).collection::<T>(coll);
+        let query = collection.find_one(filter, options);
+        self.run("find_one", coll, query).await
+    }
+
+    // find all the documents matching the filter and collect them into a Vec
+    pub async fn find_many<T>(
+        &self,
+        db: &str,
+        coll: &str,
+        filter: Option<Document>,
+        options: Option<FindOptions>,
+    ) -> MongoResult<Vec<T>>
+    where
+        T: DeserializeOwned + Unpin + Send + Sync + 'static,
+    {
+        let collection = self.reader().database(db).collection::<T>(coll);
+        let query = async {
+            let cursor = collection.find(filter, options).await?;
+            cursor.try_collect().await
+        };
+        self.run("find_many", coll, query).await
+    }
+
+    // the documents one at a time, as the cursor fetches its batches, so
+    // the caller never holds more than a batch in memory. only opening the
+    // cursor is timed, the caller decides how fast the rest is read
+    pub async fn find_stream<T>(
+        &self,
+        db: &str,
+        coll: &str,
+        filter: Option<Document>,
+        options: Option<FindOptions>,
+    ) -> MongoResult<BoxStream<'static, MongoResult<T>>>
+    where
+        T: DeserializeOwned + Unpin + Send + Sync + 'static,
+    {
+        let collection = self.reader().database(db).collection::<T>(coll);
+        let query = collection.find(filter, options);
+        let cursor = self.run("find_stream", coll, query).await?;
+        Ok(cursor.boxed())
+    }
+
+    // documents coming out of the pipeline, collected
+    pub async fn aggregate(
+        &self,
+        db: &str,
+        coll: &str,
+        pipeline: Vec<Document>,
+        options: Option<AggregateOptions>,
+    ) -> MongoResult<Vec<Document>> {
+        let collection = self.reader().database(db).collection::<Document>(coll);
+        let query = async {
+            let cursor = collection.aggregate(pipeline, options).await?;
+            cursor.try_collect().await
+        };
+        self.run("aggregate", coll, query).await
+    }
+
+    pub async fn count_documents(
+        &self,
+        db: &str,
+        coll: &str,
+        filter: Option<Document>,
+        options: Option<CountOptions>,
+    ) -> MongoResult<u64> {
+        let collection = self.reader().database(db).collection::<Document>(coll);
+        let query = collection.count_documents(filter, options);
+        self.run("count_documents", coll, query).await
+    }
+
+    // reads the collection metadata instead of scanning it, so it is cheap
+    // but may be off after an unclean shutdown or during chunk migrations
+    pub async fn estimated_document_count(&self, db: &str, coll: &str) -> MongoResult<u64> {
+        let collection = self.reader().database(db).collection::<Document>(coll);
+        let query = collection.estimated_document_count(None);
+        self.run("estimated_document_count", coll, query).await
     }
 
     pub async fn insert_one<T>(
@@ -51,8 +485,11 @@ impl AppDatabase {
     where
         T: Serialize + 'static,
     {
-        let collection = self.0.database(db).collection::<T>(coll);
-        let result = collection.insert_one(doc, options).await?;
+        check_writable(&self.writable_collections, coll)?;
+        let collection = self.client.database(db).collection::<T>(coll);
+        let options = with_write_concern(options, &self.write_concern);
+        let query = collection.insert_one(doc, options);
+        let result = self.run("insert_one", coll, query).await?;
         let result = if let Bson::ObjectId(oid) = result.inserted_id {
             InsertOneResult {
                 inserted_id: oid.to_hex(),
@@ -64,4 +501,431 @@ impl AppDatabase {
         };
         Ok(result)
     }
+
+    // insert all the documents at once; when `ordered` is false the server
+    // goes on past a failed document, a `BulkWrite` error then lists the
+    // failed ones by index and all the others got inserted
+    pub async fn insert_many<T>(
+        &self,
+        db: &str,
+        coll: &str,
+        docs: &[T],
+        ordered: bool,
+    ) -> MongoResult<()>
+    where
+        T: Serialize + 'static,
+    {
+        check_writable(&self.writable_collections, coll)?;
+        let collection = self.client.database(db).collection::<T>(coll);
+        let options = InsertManyOptions::builder().ordered(ordered).build();
+        let options = with_write_concern(Some(options), &self.write_concern);
+        let query = collection.insert_many(docs, options);
+        self.run("insert_many", coll, query).await?;
+        Ok(())
+    }
+
+    pub async fn update_one(
+        &self,
+        db: &str,
+        coll: &str,
+        filter: Document,
+        update: Document,
+        options: Option<UpdateOptions>,
+    ) -> MongoResult<UpdateResult> {
+        check_writable(&self.writable_collections, coll)?;
+        let collection = self.client.database(db).collection::<Document>(coll);
+        let options = with_write_concern(options, &self.write_concern);
+        let query = collection.update_one(filter, update, options);
+        let result = self.run("update_one", coll, query).await?;
+        Ok(UpdateResult {
+            matched_count: result.matched_count,
+            modified_count: result.modified_count,
+        })
+    }
+
+    pub async fn update_many(
+        &self,
+        db: &str,
+        coll: &str,
+        filter: Document,
+        update: Document,
+        options: Option<UpdateOptions>,
+    ) -> MongoResult<UpdateResult> {
+        check_writable(&self.writable_collections, coll)?;
+        let collection = self.client.database(db).collection::<Document>(coll);
+        let options = with_write_concern(options, &self.write_concern);
+        let query = collection.update_many(filter, update, options);
+        let result = self.run("update_many", coll, query).await?;
+        Ok(UpdateResult {
+            matched_count: result.matched_count,
+            modified_count: result.modified_count,
+        })
+    }
+
+    // the number of deleted documents
+    pub async fn delete_many(
+        &self,
+        db: &str,
+        coll: &str,
+        filter: Document,
+        options: Option<DeleteOptions>,
+    ) -> MongoResult<u64> {
+        check_writable(&self.writable_collections, coll)?;
+        let collection = self.client.database(db).collection::<Document>(coll);
+        let options = with_write_concern(options, &self.write_concern);
+        let query = collection.delete_many(filter, options);
+        let result = self.run("delete_many", coll, query).await?;
+        Ok(result.deleted_count)
+    }
+
+    // run the `(filter, update)` pairs in a single transaction, either all
+    // of them are applied or none is. an update matching no document rolls
+    // the transaction back, it is then the last of the returned results.
+    // the write concern is the one of the transaction, operations inside it
+    // cannot have their own
+    pub async fn update_in_transaction(
+        &self,
+        db: &str,
+        coll: &str,
+        updates: Vec<(Document, Document)>,
+    ) -> MongoResult<Vec<UpdateResult>> {
+        check_writable(&self.writable_collections, coll)?;
+        let collection = self.client.database(db).collection::<Document>(coll);
+        let query = async {
+            let mut session = self.client.start_session(None).await?;
+            let options = TransactionOptions::builder()
+                .write_concern(self.write_concern.clone())
+                .build();
+            session.start_transaction(options).await?;
+            let mut results = Vec::with_capacity(updates.len());
+            for (filter, update) in updates {
+                let result = collection
+                    .update_one_with_session(filter, update, None, &mut session)
+                    .await;
+                let matched = match result {
+                    Ok(result) => {
+                        results.push(UpdateResult {
+                            matched_count: result.matched_count,
+                            modified_count: result.modified_count,
+                        });
+                        result.matched_count > 0
+                    }
+                    Err(err) => {
+                        abort(&mut session).await;
+                        return Err(err);
+                    }
+                };
+                if !matched {
+                    abort(&mut session).await;
+                    return Ok(results);
+                }
+            }
+            session.commit_transaction().await?;
+            Ok(results)
+        };
+        self.run("update_in_transaction", coll, query).await
+    }
+
+    // insert the document only if nothing matches the filter yet, returns
+    // true when a new document got created and false when one already existed
+    pub async fn insert_or_get<T>(
+        &self,
+        db: &str,
+        coll: &str,
+        filter: Document,
+        doc: &T,
+    ) -> MongoResult<bool>
+    where
+        T: Serialize + 'static,
+    {
+        check_writable(&self.writable_collections, coll)?;
+        let collection = self.client.database(db).collection::<Document>(coll);
+        let update = doc! {"$setOnInsert": to_stored_document(doc)?};
+        let options = UpdateOptions::builder().upsert(true).build();
+        let options = with_write_concern(Some(options), &self.write_concern);
+        let query = collection.update_one(filter, update, options);
+        let result = self.run("insert_or_get", coll, query).await?;
+        Ok(result.upserted_id.is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::{error::ErrorKind, options::Acknowledgment};
+
+    use super::*;
+
+    fn majority() -> Option<WriteConcern> {
+        Some(WriteConcern::builder().w(Acknowledgment::Majority).build())
+    }
+
+    #[test]
+    fn test_with_write_concern_fills_missing_options() {
+        let options = with_write_concern::<InsertOneOptions>(None, &majority()).unwrap();
+        assert_eq!(options.write_concern, majority());
+    }
+
+    #[test]
+    fn test_with_write_concern_keeps_caller_choice() {
+        let nodes = WriteConcern::builder().w(Acknowledgment::Nodes(1)).build();
+        let options = UpdateOptions::builder()
+            .upsert(true)
+            .write_concern(nodes.clone())
+            .build();
+        let options = with_write_concern(Some(options), &majority()).unwrap();
+        assert_eq!(options.write_concern, Some(nodes));
+        assert_eq!(options.upsert, Some(true));
+    }
+
+    #[tokio::test]
+    async fn test_connect_with_fallback_uses_next_uri() {
+        let attempts = std::sync::Mutex::new(Vec::new());
+        let result = connect_with_fallback(&["mongodb://down", "mongodb://up"], |uri| {
+            attempts.lock().unwrap().push(uri.to_string());
+            let uri = uri.to_string();
+            async move {
+                if uri == "mongodb://up" {
+                    Ok(uri)
+                } else {
+                    Err("connection refused")
+                }
+            }
+        })
+        .await;
+        assert_eq!(result, Ok("mongodb://up".to_string()));
+        assert_eq!(
+            *attempts.lock().unwrap(),
+            vec!["mongodb://down", "mongodb://up"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_connect_with_fallback_all_fail() {
+        let result: Result<(), String> =
+            connect_with_fallback(&["mongodb://a", "mongodb://b"], |uri| {
+                let err = format!("{uri} refused");
+                async move { Err(err) }
+            })
+            .await;
+        assert_eq!(result, Err("mongodb://b refused".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_connect_with_fallback_primary_first() {
+        let result: Result<&str, ()> =
+            connect_with_fallback(&["mongodb://a", "mongodb://b"], |_| async { Ok("conn") }).await;
+        assert_eq!(result, Ok("conn"));
+    }
+
+    // counts the warnings emitted while it is the default subscriber
+    #[derive(Clone, Default)]
+    struct WarnCount(std::sync::Arc<std::sync::atomic::AtomicUsize>);
+
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for WarnCount {
+        fn on_event(
+            &self,
+            event: &tracing::Event<'_>,
+            _ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            if *event.metadata().level() == tracing::Level::WARN {
+                self.0.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_log_if_slow() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let warnings = WarnCount::default();
+        let subscriber = tracing_subscriber::registry().with(warnings.clone());
+        let _guard = tracing::subscriber::set_default(subscriber);
+        let threshold = Duration::from_millis(20);
+
+        let fast = log_if_slow(threshold, "find_one", "users", async { 1 }).await;
+        assert_eq!(fast, 1);
+        assert_eq!(warnings.0.load(std::sync::atomic::Ordering::Relaxed), 0);
+
+        let slow = log_if_slow(threshold, "find_one", "users", async {
+            tokio::time::sleep(Duration::from_millis(40)).await;
+            2
+        })
+        .await;
+        assert_eq!(slow, 2);
+        assert_eq!(warnings.0.load(std::sync::atomic::Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_check_writable() {
+        let writable = vec!["users".to_string(), "audit".to_string()];
+        assert!(check_writable(&writable, "users").is_ok());
+        assert!(check_writable(&writable, "sessions").is_err());
+        assert!(check_writable(&[], "sessions").is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_with_timeout() {
+        let timeout = Some(Duration::from_millis(20));
+        let fast = with_timeout(timeout, "find_one", "users", async { Ok(1) }).await;
+        assert_eq!(fast.unwrap(), 1);
+
+        let slow = with_timeout(timeout, "find_one", "users", async {
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            Ok(2)
+        })
+        .await;
+        let err = slow.unwrap_err();
+        let ErrorKind::Io(io) = err.kind.as_ref() else {
+            panic!("expected an io error, got {err:?}");
+        };
+        assert_eq!(io.kind(), std::io::ErrorKind::TimedOut);
+        assert_eq!(io.to_string(), "find_one on `users` timed out after 20 ms");
+
+        let unbounded = with_timeout(None, "find_one", "users", async {
+            tokio::time::sleep(Duration::from_millis(40)).await;
+            Ok(3)
+        })
+        .await;
+        assert_eq!(unbounded.unwrap(), 3);
+    }
+
+    #[test]
+    fn test_check_safe_command() {
+        assert!(check_safe_command(&doc! {"dbStats": 1}).is_ok());
+        assert!(check_safe_command(&doc! {"dropDatabase": 1}).is_err());
+        assert!(check_safe_command(&doc! {}).is_err());
+    }
+
+    // no server is running, the command would hang if it got that far
+    #[tokio::test]
+    async fn test_run_command_rejects_unsafe_command() {
+        let db = AppDatabase::new(
+            "mongodb://localhost:1",
+            None,
+            None,
+            None,
+            Duration::ZERO,
+            DEFAULT_APP_NAME,
+        )
+        .await
+        .unwrap();
+        let err = db
+            .run_command("myDB", doc! {"dropDatabase": 1, "ping": 1})
+            .await
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("command `dropDatabase` is not allowed"));
+    }
+
+    // no server is running, the write would hang if it got that far
+    #[tokio::test]
+    async fn test_write_to_disallowed_collection_fails_before_db_call() {
+        let db = AppDatabase::new(
+            "mongodb://localhost:1",
+            None,
+            None,
+            None,
+            Duration::ZERO,
+            DEFAULT_APP_NAME,
+        )
+        .await
+        .unwrap()
+        .with_writable_collections(vec!["users".to_string()]);
+        let err = db
+            .insert_one("myDB", "sessions", &doc! {"id": 1}, None)
+            .await
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("collection `sessions` is not writable"));
+    }
+
+    #[tokio::test]
+    async fn test_reads_use_their_own_client_when_split() {
+        let uri = "mongodb://localhost:27017/sales";
+        let single = AppDatabase::new(uri, None, None, None, Duration::ZERO, DEFAULT_APP_NAME)
+            .await
+            .unwrap();
+        assert!(single.read_client.is_none());
+        let read_uri = Some("mongodb://localhost:27018/sales".to_string());
+        let split = AppDatabase::new(uri, read_uri, None, None, Duration::ZERO, DEFAULT_APP_NAME)
+            .await
+            .unwrap();
+        assert!(split.read_client.is_some());
+        assert_eq!(split.default_db_name().as_deref(), Some("sales"));
+    }
+
+    #[test]
+    fn test_is_reconnecting() {
+        let io = |kind| mongodb::error::Error::from(std::io::Error::new(kind, "lost"));
+        assert!(is_reconnecting(&io(std::io::ErrorKind::ConnectionReset)));
+        assert!(is_reconnecting(&io(std::io::ErrorKind::BrokenPipe)));
+        assert!(!is_reconnecting(&io(std::io::ErrorKind::TimedOut)));
+        assert!(!is_reconnecting(&io(std::io::ErrorKind::PermissionDenied)));
+    }
+
+    #[tokio::test]
+    async fn test_set_app_name() {
+        let mut options = ClientOptions::parse("mongodb://localhost:27017/?appName=mongosh")
+            .await
+            .unwrap();
+        set_app_name(&mut options, DEFAULT_APP_NAME);
+        assert_eq!(options.app_name.as_deref(), Some("axum_testing"));
+        set_app_name(&mut options, "billing");
+        assert_eq!(options.app_name.as_deref(), Some("billing"));
+    }
+
+    // creating the client does not connect, so no server is needed
+    #[tokio::test]
+    async fn test_default_db_name_from_uri() {
+        let threshold = Duration::from_millis(500);
+        let db = AppDatabase::new(
+            "mongodb://localhost:27017/sales",
+            None,
+            None,
+            None,
+            threshold,
+            DEFAULT_APP_NAME,
+        )
+        .await
+        .unwrap();
+        assert_eq!(db.default_db_name().as_deref(), Some("sales"));
+        let db = AppDatabase::new(
+            "mongodb://localhost:27017",
+            None,
+            None,
+            None,
+            threshold,
+            DEFAULT_APP_NAME,
+        )
+        .await
+        .unwrap();
+        assert_eq!(db.default_db_name(), None);
+    }
+
+    #[test]
+    fn test_tls_options() {
+        assert_eq!(tls_options(None, false), None);
+
+        let Some(Tls::Enabled(options)) = tls_options(Some(Path::new("/etc/ca.pem")), false) else {
+            panic!("expected tls to be enabled");
+        };
+        assert_eq!(
+            options.ca_file_path.as_deref(),
+            Some(Path::new("/etc/ca.pem"))
+        );
+        assert_eq!(options.allow_invalid_certificates, None);
+
+        let Some(Tls::Enabled(options)) = tls_options(None, true) else {
+            panic!("expected tls to be enabled");
+        };
+        assert_eq!(options.ca_file_path, None);
+        assert_eq!(options.allow_invalid_certificates, Some(true));
+    }
+
+    #[test]
+    fn test_with_write_concern_unconfigured() {
+        assert!(with_write_concern::<InsertOneOptions>(None, &None).is_none());
+    }
 }
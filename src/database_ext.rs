@@ -0,0 +1,108 @@
+use axum::async_trait;
+use futures::future::try_join_all;
+use mockall_double::double;
+use mongodb::{bson::Document, error::Result as MongoResult, options::FindOneOptions};
+use serde::de::DeserializeOwned;
+
+#[double]
+use crate::database::AppDatabase;
+
+// helpers composed from the AppDatabase primitives, written once on top
+// of them so the mocked primitives drive them in tests.
+// no handler reads a config-like collection yet
+#[async_trait]
+#[cfg_attr(not(test), allow(dead_code))]
+pub trait AppDatabaseExt {
+    // like `find_one` but falls back to the default value when nothing
+    // matches, handy for config-like collections
+    async fn find_one_or_default<T>(
+        &self,
+        db: &str,
+        coll: &str,
+        filter: Option<Document>,
+        options: Option<FindOneOptions>,
+    ) -> MongoResult<T>
+    where
+        T: DeserializeOwned + Default + Unpin + Send + Sync + 'static;
+
+    // open `connections` pool connections ahead of the first requests by
+    // pinging that many times at once, each concurrent ping needs its own
+    async fn prime_pool(&self, connections: u32) -> MongoResult<()>;
+}
+
+#[async_trait]
+impl AppDatabaseExt for AppDatabase {
+    async fn find_one_or_default<T>(
+        &self,
+        db: &str,
+        coll: &str,
+        filter: Option<Document>,
+        options: Option<FindOneOptions>,
+    ) -> MongoResult<T>
+    where
+        T: DeserializeOwned + Default + Unpin + Send + Sync + 'static,
+    {
+        let found = self.find_one::<T>(db, coll, filter, options).await?;
+        Ok(found.unwrap_or_default())
+    }
+
+    async fn prime_pool(&self, connections: u32) -> MongoResult<()> {
+        try_join_all((0..connections).map(|_| self.ping())).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockall::predicate::{always, eq};
+    use mongodb::bson::doc;
+    use serde::Deserialize;
+
+    #[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+    struct Settings {
+        signups_open: bool,
+        max_users: u32,
+    }
+
+    #[tokio::test]
+    async fn test_find_one_or_default_missing() {
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_find_one::<Settings>()
+            .with(eq("myDB"), eq("settings"), eq(Some(doc! {})), always())
+            .times(1)
+            .returning(|_, _, _, _| Ok(None));
+        let settings: Settings = mock_db
+            .find_one_or_default("myDB", "settings", Some(doc! {}), None)
+            .await
+            .unwrap();
+        assert_eq!(settings, Settings::default());
+    }
+
+    #[tokio::test]
+    async fn test_find_one_or_default_found() {
+        let stored = Settings {
+            signups_open: true,
+            max_users: 10,
+        };
+        let returned = stored.clone();
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_find_one::<Settings>()
+            .times(1)
+            .returning(move |_, _, _, _| Ok(Some(returned.clone())));
+        let settings: Settings = mock_db
+            .find_one_or_default("myDB", "settings", None, None)
+            .await
+            .unwrap();
+        assert_eq!(settings, stored);
+    }
+
+    #[tokio::test]
+    async fn test_prime_pool_pings_once_per_connection() {
+        let mut mock_db = AppDatabase::default();
+        mock_db.expect_ping().times(4).returning(|| Ok(()));
+        mock_db.prime_pool(4).await.unwrap();
+    }
+}
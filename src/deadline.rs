@@ -0,0 +1,76 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::State,
+    http::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::{config::Config, error::AppError};
+
+// middleware answering with 504 once the configured response deadline
+// passes, unlike the request timeout the client gets the JSON error body
+pub async fn response_deadline<B>(
+    State(config): State<Arc<Config>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let Some(deadline) = config.response_deadline else {
+        return next.run(req).await;
+    };
+    match tokio::time::timeout(deadline, next.run(req)).await {
+        Ok(res) => res,
+        Err(_) => {
+            let message = format!("no response within {} ms", deadline.as_millis());
+            AppError::GatewayTimeout(message).into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use axum::{body::Body, http::StatusCode, middleware, routing::get, Router};
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    fn app(deadline: Option<Duration>) -> Router {
+        async fn slow() -> &'static str {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            "done"
+        }
+        let config = Arc::new(Config {
+            response_deadline: deadline,
+            ..Default::default()
+        });
+        Router::new()
+            .route("/", get(slow))
+            .layer(middleware::from_fn_with_state(config, response_deadline))
+    }
+
+    #[tokio::test]
+    async fn test_response_deadline_exceeded() {
+        let req = Request::builder().uri("/").body(Body::empty()).unwrap();
+        let res = app(Some(Duration::from_millis(10)))
+            .oneshot(req)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::GATEWAY_TIMEOUT);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            json!({"success": false, "message": "no response within 10 ms"})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_response_deadline_disabled() {
+        let req = Request::builder().uri("/").body(Body::empty()).unwrap();
+        let res = app(None).oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+}
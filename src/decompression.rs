@@ -0,0 +1,68 @@
+use std::io::Read;
+
+use axum::{
+    body::Body,
+    http::{header, HeaderValue, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use flate2::read::GzDecoder;
+
+use crate::{buffer::read_request_body, error::AppError};
+
+// upper bound for a decompressed request body, protects against zip bombs
+pub const MAX_DECOMPRESSED_BODY_BYTES: u64 = 2 * 1024 * 1024;
+
+// middleware transparently decompressing gzip and br encoded request bodies,
+// so the extractors always see the plain payload
+pub async fn decompress_request(req: Request<Body>, next: Next<Body>) -> Response {
+    let encoding = match req.headers().get(header::CONTENT_ENCODING) {
+        Some(value) => value.to_str().unwrap_or_default().trim().to_lowercase(),
+        None => return next.run(req).await,
+    };
+    if encoding == "identity" {
+        return next.run(req).await;
+    }
+    if encoding != "gzip" && encoding != "br" {
+        let message = format!("unsupported content encoding `{encoding}`");
+        return AppError::UnsupportedMediaType(message).into_response();
+    }
+
+    let (mut parts, body) = req.into_parts();
+    // compressed data is smaller than what it decodes to, so the cap on the
+    // decoded body bounds the compressed one too
+    let compressed = match read_request_body(body, MAX_DECOMPRESSED_BODY_BYTES as usize).await {
+        Ok(bytes) => bytes,
+        Err(err) => return err.into_response(),
+    };
+    let decoded = match encoding.as_str() {
+        "gzip" => decode(GzDecoder::new(&compressed[..])),
+        _ => decode(brotli::Decompressor::new(&compressed[..], 4096)),
+    };
+    let decoded = match decoded {
+        Ok(decoded) => decoded,
+        Err(err) => return err.into_response(),
+    };
+
+    parts.headers.remove(header::CONTENT_ENCODING);
+    parts
+        .headers
+        .insert(header::CONTENT_LENGTH, HeaderValue::from(decoded.len()));
+    next.run(Request::from_parts(parts, Body::from(decoded)))
+        .await
+}
+
+fn decode(reader: impl Read) -> Result<Vec<u8>, AppError> {
+    let mut decoded = Vec::new();
+    reader
+        .take(MAX_DECOMPRESSED_BODY_BYTES + 1)
+        .read_to_end(&mut decoded)
+        .map_err(|_| AppError::BadRequest("request body could not be decompressed".to_string()))?;
+    if decoded.len() as u64 > MAX_DECOMPRESSED_BODY_BYTES {
+        let message = format!(
+            "decompressed request body must not exceed {MAX_DECOMPRESSED_BODY_BYTES} bytes"
+        );
+        return Err(AppError::PayloadTooLarge(message));
+    }
+    Ok(decoded)
+}
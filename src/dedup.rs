@@ -0,0 +1,212 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    body::{self, Body, Bytes, Full},
+    extract::State,
+    http::{HeaderMap, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use mongodb::bson::DateTime;
+
+use crate::{
+    buffer::{buffer_response, read_request_body, MAX_REQUEST_BODY_BYTES},
+    client_ip::client_ip,
+    clock::Clock,
+    config::Config,
+};
+
+// a successful response, kept to answer the duplicates of its request
+#[derive(Debug, Clone)]
+struct Submission {
+    at: DateTime,
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+// the POSTs answered within the last `duplicate_window`, keyed by a hash of
+// the client address, the path and the body
+#[derive(Debug, Clone, Default)]
+pub struct RecentSubmissions(Arc<Mutex<HashMap<u64, Submission>>>);
+
+impl RecentSubmissions {
+    // the response of a submission made after `since`, older ones are dropped
+    fn get(&self, key: u64, since: DateTime) -> Option<Submission> {
+        let mut submissions = self.0.lock().unwrap();
+        submissions.retain(|_, submission| submission.at > since);
+        submissions.get(&key).cloned()
+    }
+
+    fn insert(&self, key: u64, submission: Submission) {
+        self.0.lock().unwrap().insert(key, submission);
+    }
+}
+
+fn submission_key(ip: &std::net::IpAddr, path: &str, body: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    (ip, path, body).hash(&mut hasher);
+    hasher.finish()
+}
+
+// middleware answering a POST repeated by the same client within
+// `duplicate_window` with the response of the first one, so a double click
+// does not create two users. only successful responses are replayed, and a
+// duplicate arriving before the first one is answered still goes through
+pub async fn swallow_duplicate_submissions(
+    State(submissions): State<RecentSubmissions>,
+    State(config): State<Arc<Config>>,
+    State(clock): State<Arc<dyn Clock>>,
+    req: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let Some(window) = config.duplicate_window else {
+        return next.run(req).await;
+    };
+    if req.method() != Method::POST {
+        return next.run(req).await;
+    }
+    let Some(ip) = client_ip(&req, config.trust_proxy) else {
+        return next.run(req).await;
+    };
+    let (parts, body) = req.into_parts();
+    let body = match read_request_body(body, MAX_REQUEST_BODY_BYTES).await {
+        Ok(bytes) => bytes,
+        Err(err) => return err.into_response(),
+    };
+    let path = parts.uri.path_and_query().map_or("", |path| path.as_str());
+    let key = submission_key(&ip, path, &body);
+    let now = clock.now();
+    let since = DateTime::from_millis(now.timestamp_millis() - window.as_millis() as i64);
+    if let Some(first) = submissions.get(key, since) {
+        tracing::info!("replaying the response of a duplicate submission");
+        let mut res = Response::new(body::boxed(Full::from(first.body)));
+        *res.status_mut() = first.status;
+        *res.headers_mut() = first.headers;
+        return res;
+    }
+    let res = next.run(Request::from_parts(parts, Body::from(body))).await;
+    if !res.status().is_success() {
+        return res;
+    }
+    let (parts, body) = match buffer_response(res).await {
+        Ok(buffered) => buffered,
+        Err(res) => return res,
+    };
+    submissions.insert(
+        key,
+        Submission {
+            at: now,
+            status: parts.status,
+            headers: parts.headers.clone(),
+            body: body.clone(),
+        },
+    );
+    Response::from_parts(parts, body::boxed(Full::from(body)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, time::Duration};
+
+    use super::*;
+    use crate::build_router;
+    use crate::clock::FixedClock;
+    use crate::database::InsertOneResult;
+    use crate::models::User;
+    use crate::test_support::{allow_audit, test_state, NOW};
+    use crate::{AppDatabase, AppState};
+    use axum::{extract::ConnectInfo, http::header};
+    use tower::ServiceExt;
+
+    fn create_request() -> Request<Body> {
+        let user = User {
+            id: 5,
+            name: "Sibaprasad".to_string(),
+            phone: "56565656".to_string(),
+            ..Default::default()
+        };
+        post_user(serde_json::to_vec(&user).unwrap())
+    }
+
+    fn post_user(body: Vec<u8>) -> Request<Body> {
+        let mut req = Request::builder()
+            .method("POST")
+            .uri("/user")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .unwrap();
+        let peer = SocketAddr::from(([10, 0, 0, 1], 4000));
+        req.extensions_mut().insert(ConnectInfo(peer));
+        req
+    }
+
+    // submit the same user twice, the second time `after` the first one
+    async fn submit_twice(after: Duration, inserts: usize) -> Vec<Response> {
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_insert_one::<User>()
+            .times(inserts)
+            .returning(|_, _, _, _| {
+                Ok(InsertOneResult {
+                    inserted_id: "5".to_string(),
+                })
+            });
+        allow_audit(&mut mock_db);
+        let first = AppState {
+            config: Arc::new(Config {
+                duplicate_window: Some(Duration::from_secs(5)),
+                ..Default::default()
+            }),
+            ..test_state(mock_db)
+        };
+        let later = DateTime::from_millis(NOW.timestamp_millis() + after.as_millis() as i64);
+        let second = AppState {
+            clock: Arc::new(FixedClock(later)),
+            ..first.clone()
+        };
+        let mut responses = Vec::new();
+        for state in [first, second] {
+            let res = build_router(state).oneshot(create_request()).await.unwrap();
+            responses.push(res);
+        }
+        responses
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_within_window_replays_first_response() {
+        let responses = submit_twice(Duration::from_secs(2), 1).await;
+        let mut bodies = Vec::new();
+        for res in responses {
+            assert_eq!(res.status(), StatusCode::CREATED);
+            bodies.push(hyper::body::to_bytes(res.into_body()).await.unwrap());
+        }
+        assert_eq!(bodies[0], bodies[1]);
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_after_window_goes_through() {
+        let responses = submit_twice(Duration::from_secs(6), 2).await;
+        for res in responses {
+            assert_eq!(res.status(), StatusCode::CREATED);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_oversized_submission_refused_before_buffering() {
+        let state = AppState {
+            config: Arc::new(Config {
+                duplicate_window: Some(Duration::from_secs(5)),
+                ..Default::default()
+            }),
+            ..test_state(AppDatabase::default())
+        };
+        let body = vec![b' '; MAX_REQUEST_BODY_BYTES + 1];
+        let res = build_router(state).oneshot(post_user(body)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
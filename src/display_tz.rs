@@ -0,0 +1,174 @@
+use std::sync::Arc;
+
+use axum::{
+    body::{self, Full, HttpBody},
+    extract::State,
+    http::{header, Request},
+    middleware::Next,
+    response::Response,
+};
+use chrono::{FixedOffset, NaiveDateTime, SecondsFormat, TimeZone};
+use serde_json::Value;
+
+use crate::{buffer::buffer_response, config::Config, content_type::MediaType};
+
+// the extended JSON `{"$date": {"$numberLong": "..."}}` of a BSON date as
+// an ISO-8601 string in `offset`, e.g. `2023-02-10T09:03:20.000+05:30`
+fn format_date(value: &Value, offset: &FixedOffset) -> Option<String> {
+    let millis = value
+        .as_object()
+        .filter(|object| object.len() == 1)?
+        .get("$date")?
+        .as_object()
+        .filter(|date| date.len() == 1)?
+        .get("$numberLong")?
+        .as_str()?
+        .parse::<i64>()
+        .ok()?;
+    let utc = NaiveDateTime::from_timestamp_millis(millis)?;
+    let date = offset.from_utc_datetime(&utc);
+    Some(date.to_rfc3339_opts(SecondsFormat::Millis, true))
+}
+
+fn localize_dates(value: &mut Value, offset: &FixedOffset) {
+    if let Some(date) = format_date(value, offset) {
+        *value = Value::String(date);
+        return;
+    }
+    match value {
+        Value::Array(items) => items
+            .iter_mut()
+            .for_each(|item| localize_dates(item, offset)),
+        Value::Object(object) => object
+            .values_mut()
+            .for_each(|value| localize_dates(value, offset)),
+        _ => {}
+    }
+}
+
+// middleware rewriting the dates of the JSON responses to `display_tz`
+// when it is set. streamed bodies, like the export, are left in extended
+// JSON as they cannot be rewritten without buffering them
+pub async fn display_timezone<B>(
+    State(config): State<Arc<Config>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let res = next.run(req).await;
+    let Some(offset) = config.display_tz else {
+        return res;
+    };
+    let is_json = MediaType::from_headers(res.headers()).is_some_and(|media| media.is_json());
+    if !is_json || res.body().size_hint().exact().is_none() {
+        return res;
+    }
+    let (mut parts, bytes) = match buffer_response(res).await {
+        Ok(buffered) => buffered,
+        Err(res) => return res,
+    };
+    let Ok(mut value) = serde_json::from_slice::<Value>(&bytes) else {
+        return Response::from_parts(parts, body::boxed(Full::from(bytes)));
+    };
+    localize_dates(&mut value, &offset);
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, body::boxed(Full::from(value.to_string())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build_router;
+    use crate::database::InsertOneResult;
+    use crate::models::User;
+    use crate::test_support::{allow_audit, test_state, NOW};
+    use crate::{AppDatabase, AppState};
+    use axum::body::Body;
+    use axum::http::StatusCode;
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_localize_dates() {
+        let offset = FixedOffset::east_opt(5 * 3600 + 30 * 60).unwrap();
+        let date = serde_json::to_value(NOW).unwrap();
+        let mut value = json!({
+            "created_at": date,
+            "items": [{"updated_at": date}],
+            "note": {"$date": "not a date"},
+        });
+        localize_dates(&mut value, &offset);
+        assert_eq!(
+            value,
+            json!({
+                "created_at": "2023-02-10T09:03:20.000+05:30",
+                "items": [{"updated_at": "2023-02-10T09:03:20.000+05:30"}],
+                "note": {"$date": "not a date"},
+            })
+        );
+        let utc = FixedOffset::east_opt(0).unwrap();
+        assert_eq!(
+            format_date(&date, &utc).as_deref(),
+            Some("2023-02-10T03:33:20.000Z")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dates_shown_in_display_tz_stored_in_utc() {
+        let user = User {
+            id: 5,
+            name: "Sibaprasad".to_string(),
+            phone: "56565656".to_string(),
+            is_active: true,
+            ..Default::default()
+        };
+        let stored = User {
+            created_at: Some(NOW),
+            updated_at: Some(NOW),
+            ..user.clone()
+        };
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_insert_one::<User>()
+            .withf(move |_, _, doc, _| doc == &stored)
+            .times(1)
+            .returning(|_, _, _, _| {
+                Ok(InsertOneResult {
+                    inserted_id: "5".to_string(),
+                })
+            });
+        let found = User {
+            created_at: Some(NOW),
+            ..user.clone()
+        };
+        mock_db
+            .expect_find_one::<User>()
+            .times(1)
+            .returning(move |_, _, _, _| Ok(Some(found.clone())));
+        allow_audit(&mut mock_db);
+        let state = AppState {
+            config: Arc::new(Config {
+                display_tz: FixedOffset::east_opt(-3 * 3600),
+                ..Default::default()
+            }),
+            ..test_state(mock_db)
+        };
+        let app = build_router(state);
+        let req = Request::builder()
+            .method("POST")
+            .uri("/user")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(serde_json::to_string(&user).unwrap()))
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let req = Request::builder()
+            .uri("/user/5")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["data"]["created_at"], "2023-02-10T00:33:20.000-03:00");
+    }
+}
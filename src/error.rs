@@ -0,0 +1,264 @@
+use std::time::Duration;
+
+use axum::{
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+
+use crate::{database::is_unavailable, validation::FieldError};
+
+// message sent to the client for any internal error
+pub const INTERNAL_ERROR_MESSAGE: &str = "Unexpected error";
+// message sent to the client when a stored document fails to deserialize
+pub const STORED_DOCUMENT_MESSAGE: &str = "stored document could not be parsed";
+
+// error returned by the handlers, rendered as `{"success": false, "message": ...}`
+#[derive(Debug)]
+pub enum AppError {
+    BadRequest(String),
+    Unauthorized(String),
+    NotFound(String),
+    Conflict(String),
+    // a 409 for a write colliding with a stored document on a unique field,
+    // the body carries the id of that document when it could be found
+    Duplicate {
+        message: String,
+        existing_id: Option<i64>,
+    },
+    PayloadTooLarge(String),
+    UriTooLong(String),
+    UnsupportedMediaType(String),
+    // the duration tells the client when to try again, see `retry_after`
+    TooManyRequests(String, Duration),
+    // rendered as `{"success": false, "errors": [...]}` listing every invalid field
+    Validation(Vec<FieldError>),
+    // the duration tells the client when to try again, see `retry_after`
+    ServiceUnavailable(String, Duration),
+    GatewayTimeout(String),
+    // a stored document does not match its model any more, the string is
+    // what gets logged, the client only learns that it could not be parsed
+    StoredDocument(String),
+    Internal(anyhow::Error),
+}
+
+impl AppError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Conflict(_) | AppError::Duplicate { .. } => StatusCode::CONFLICT,
+            AppError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::UriTooLong(_) => StatusCode::URI_TOO_LONG,
+            AppError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            AppError::TooManyRequests(..) => StatusCode::TOO_MANY_REQUESTS,
+            AppError::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::ServiceUnavailable(..) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::GatewayTimeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            AppError::StoredDocument(_) | AppError::Internal(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    // message shown to the client, the underlying cause of an internal
+    // error is only included when `expose_details` is set
+    pub fn message(&self, expose_details: bool) -> String {
+        match self {
+            AppError::BadRequest(message)
+            | AppError::Unauthorized(message)
+            | AppError::NotFound(message)
+            | AppError::Conflict(message)
+            | AppError::Duplicate { message, .. }
+            | AppError::PayloadTooLarge(message)
+            | AppError::UriTooLong(message)
+            | AppError::UnsupportedMediaType(message)
+            | AppError::TooManyRequests(message, _)
+            | AppError::ServiceUnavailable(message, _)
+            | AppError::GatewayTimeout(message) => message.clone(),
+            AppError::Validation(_) => "validation failed".to_string(),
+            AppError::StoredDocument(_) => STORED_DOCUMENT_MESSAGE.to_string(),
+            AppError::Internal(err) if expose_details => {
+                format!("{INTERNAL_ERROR_MESSAGE}: {err:#}")
+            }
+            AppError::Internal(_) => INTERNAL_ERROR_MESSAGE.to_string(),
+        }
+    }
+
+    pub fn into_response_with(self, expose_details: bool) -> Response {
+        match &self {
+            AppError::Internal(err) => tracing::error!("{:?}", err),
+            AppError::StoredDocument(details) => {
+                tracing::error!("{STORED_DOCUMENT_MESSAGE}: {details}")
+            }
+            _ => {}
+        }
+        let body = match &self {
+            AppError::Validation(errors) => json!({"success": false, "errors": errors}),
+            AppError::Duplicate {
+                message,
+                existing_id,
+            } => json!({"success": false, "message": message, "existing_id": existing_id}),
+            _ => json!({"success": false, "message": self.message(expose_details)}),
+        };
+        let mut res = (self.status(), Json(body)).into_response();
+        if let AppError::TooManyRequests(_, wait) | AppError::ServiceUnavailable(_, wait) = &self {
+            res.headers_mut()
+                .insert(header::RETRY_AFTER, retry_after(*wait));
+        }
+        res
+    }
+}
+
+// `Retry-After` value for a wait, in whole seconds rounded up so clients
+// never come back too early, and at least one second
+pub fn retry_after(wait: Duration) -> HeaderValue {
+    let secs = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+    HeaderValue::from(secs.max(1))
+}
+
+impl IntoResponse for AppError {
+    // internals are only exposed in debug builds
+    fn into_response(self) -> Response {
+        self.into_response_with(cfg!(debug_assertions))
+    }
+}
+
+// message sent to the client when a database operation ran out of time
+pub const DB_TIMEOUT_MESSAGE: &str = "database operation timed out";
+// message sent to the client when no database server could be reached
+pub const DB_UNAVAILABLE_MESSAGE: &str = "database unavailable";
+pub const DB_UNAVAILABLE_RETRY_AFTER: Duration = Duration::from_secs(5);
+
+impl From<mongodb::error::Error> for AppError {
+    // the operations cut off by the `AppDatabase` timeout fail with a
+    // `TimedOut` io error, they are reported as such rather than as a 500.
+    // losing the database altogether is a 503
+    fn from(err: mongodb::error::Error) -> Self {
+        match err.kind.as_ref() {
+            mongodb::error::ErrorKind::Io(io) if io.kind() == std::io::ErrorKind::TimedOut => {
+                tracing::warn!("{}", io);
+                AppError::GatewayTimeout(DB_TIMEOUT_MESSAGE.to_string())
+            }
+            _ if is_unavailable(&err) => {
+                tracing::warn!("{}", err);
+                AppError::ServiceUnavailable(
+                    DB_UNAVAILABLE_MESSAGE.to_string(),
+                    DB_UNAVAILABLE_RETRY_AFTER,
+                )
+            }
+            _ => AppError::Internal(err.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn body_json(res: Response) -> serde_json::Value {
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_internal_error_hides_details_in_prod() {
+        let err = AppError::Internal(anyhow::anyhow!("connection refused"));
+        let res = err.into_response_with(false);
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = body_json(res).await;
+        assert_eq!(
+            body,
+            json!({"success": false, "message": INTERNAL_ERROR_MESSAGE})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_internal_error_exposes_details_in_debug() {
+        let err = AppError::Internal(anyhow::anyhow!("connection refused"));
+        let res = err.into_response_with(true);
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = body_json(res).await;
+        assert_eq!(
+            body,
+            json!({"success": false, "message": "Unexpected error: connection refused"})
+        );
+    }
+
+    #[test]
+    fn test_retry_after_rounds_up() {
+        assert_eq!(retry_after(Duration::from_secs(5)), "5");
+        assert_eq!(retry_after(Duration::from_millis(5001)), "6");
+        assert_eq!(retry_after(Duration::ZERO), "1");
+    }
+
+    #[tokio::test]
+    async fn test_retry_after_on_429_and_503() {
+        let errors = [
+            AppError::TooManyRequests("slow down".to_string(), Duration::from_secs(30)),
+            AppError::ServiceUnavailable(
+                "database unreachable".to_string(),
+                Duration::from_secs(5),
+            ),
+        ];
+        for (err, expected) in errors.into_iter().zip([30, 5]) {
+            let res = err.into_response_with(false);
+            let value = res.headers()[header::RETRY_AFTER].to_str().unwrap();
+            assert_eq!(value.parse::<u64>().unwrap(), expected);
+        }
+        let res = AppError::NotFound("user not found".to_string()).into_response_with(false);
+        assert!(res.headers().get(header::RETRY_AFTER).is_none());
+    }
+
+    #[test]
+    fn test_database_timeout_is_gateway_timeout() {
+        let timed_out = std::io::Error::new(std::io::ErrorKind::TimedOut, "find_one timed out");
+        let err = AppError::from(mongodb::error::Error::from(timed_out));
+        assert_eq!(err.status(), StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(err.message(true), DB_TIMEOUT_MESSAGE);
+        let refused = std::io::Error::other("connection refused");
+        let err = AppError::from(mongodb::error::Error::from(refused));
+        assert_eq!(err.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[test]
+    fn test_database_unreachable_is_service_unavailable() {
+        let reset = std::io::Error::new(std::io::ErrorKind::ConnectionReset, "reset by peer");
+        let err = AppError::from(mongodb::error::Error::from(reset));
+        assert_eq!(err.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(err.message(false), DB_UNAVAILABLE_MESSAGE);
+    }
+
+    #[test]
+    fn test_client_errors_are_not_affected_by_mode() {
+        let err = AppError::BadRequest("bad input".to_string());
+        assert_eq!(err.message(false), err.message(true));
+        assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_validation_error_lists_all_fields() {
+        let err = AppError::Validation(vec![
+            FieldError {
+                field: "id".to_string(),
+                message: "must be greater than 0".to_string(),
+            },
+            FieldError {
+                field: "phone".to_string(),
+                message: "must contain 6 to 15 digits".to_string(),
+            },
+        ]);
+        let res = err.into_response_with(false);
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body = body_json(res).await;
+        assert_eq!(
+            body,
+            json!({"success": false, "errors": [
+                {"field": "id", "message": "must be greater than 0"},
+                {"field": "phone", "message": "must contain 6 to 15 digits"},
+            ]})
+        );
+    }
+}
@@ -0,0 +1,338 @@
+use std::sync::Arc;
+
+use axum::{
+    async_trait,
+    body::{Bytes, HttpBody},
+    extract::{FromRef, FromRequest, FromRequestParts, Query},
+    http::{request::Parts, Request},
+    response::{IntoResponse, Response},
+    BoxError,
+};
+use serde::{de::DeserializeOwned, Deserialize};
+
+use crate::{config::Config, content_type::MediaType, error::AppError, validation::FieldError};
+
+// payloads listing the top level fields they accept, used to reject
+// unknown fields when strict JSON is enabled
+pub trait KnownFields {
+    const FIELDS: &'static [&'static str];
+
+    // the keys of `value` missing from `FIELDS`, only objects have any
+    fn unknown_fields(value: &serde_json::Value) -> Vec<String> {
+        let Some(object) = value.as_object() else {
+            return Vec::new();
+        };
+        object
+            .keys()
+            .filter(|key| !Self::FIELDS.contains(&key.as_str()))
+            .cloned()
+            .collect()
+    }
+}
+
+// every element of an array body is checked, its unknown fields are named
+// `{index}.{field}` like the validation errors of a batch
+impl<T: KnownFields> KnownFields for Vec<T> {
+    const FIELDS: &'static [&'static str] = T::FIELDS;
+
+    fn unknown_fields(value: &serde_json::Value) -> Vec<String> {
+        let Some(items) = value.as_array() else {
+            return Vec::new();
+        };
+        items
+            .iter()
+            .enumerate()
+            .flat_map(|(index, item)| {
+                T::unknown_fields(item)
+                    .into_iter()
+                    .map(move |field| format!("{index}.{field}"))
+            })
+            .collect()
+    }
+}
+
+// a list of ids has no keys to check
+impl KnownFields for i64 {
+    const FIELDS: &'static [&'static str] = &[];
+}
+
+// true when arrays and objects nest deeper than `max_depth`, found by
+// scanning the raw bytes so a hostile payload is refused before serde
+// recurses into it. the scan only counts brackets outside of strings,
+// whether the JSON is well formed is left to the parser
+fn exceeds_json_depth(bytes: &[u8], max_depth: usize) -> bool {
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for &byte in bytes {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match byte {
+            b'"' => in_string = true,
+            b'[' | b'{' => {
+                depth += 1;
+                if depth > max_depth {
+                    return true;
+                }
+            }
+            b']' | b'}' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    false
+}
+
+// JSON body extractor which, in strict mode, rejects the fields the
+// target type does not know with 422 instead of silently dropping them.
+// bodies nesting deeper than the configured depth are rejected with 400
+#[derive(Debug)]
+pub struct AppJson<T>(pub T);
+
+pub const BODY_REQUIRED_MESSAGE: &str = "request body required";
+
+#[async_trait]
+impl<S, B, T> FromRequest<S, B> for AppJson<T>
+where
+    T: DeserializeOwned + KnownFields,
+    Arc<Config>: FromRef<S>,
+    S: Send + Sync,
+    B: HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        let config = Arc::<Config>::from_ref(state);
+        let is_json =
+            MediaType::from_headers(req.headers()).is_some_and(|media_type| media_type.is_json());
+        let bytes = Bytes::from_request(req, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        // checked first as a missing body often comes without a content type too
+        if bytes.iter().all(u8::is_ascii_whitespace) {
+            let message = BODY_REQUIRED_MESSAGE.to_string();
+            return Err(AppError::BadRequest(message).into_response());
+        }
+        if !is_json {
+            let message = "expected request with `Content-Type: application/json`".to_string();
+            return Err(AppError::UnsupportedMediaType(message).into_response());
+        }
+        if exceeds_json_depth(&bytes, config.max_json_depth) {
+            let message = format!(
+                "JSON body must not nest deeper than {} levels",
+                config.max_json_depth
+            );
+            return Err(AppError::BadRequest(message).into_response());
+        }
+        let value: serde_json::Value = serde_json::from_slice(&bytes).map_err(|err| {
+            AppError::BadRequest(format!("invalid JSON body: {err}")).into_response()
+        })?;
+        if config.strict_json {
+            let unknown: Vec<FieldError> = T::unknown_fields(&value)
+                .into_iter()
+                .map(|field| FieldError {
+                    field,
+                    message: "unknown field".to_string(),
+                })
+                .collect();
+            if !unknown.is_empty() {
+                return Err(AppError::Validation(unknown).into_response());
+            }
+        }
+        // the path names the failing field, `.` means the body itself
+        serde_path_to_error::deserialize(value)
+            .map(AppJson)
+            .map_err(|err| {
+                let message = match err.path().to_string().as_str() {
+                    "." => format!("invalid JSON body: {}", err.inner()),
+                    path => format!("invalid JSON body at `{path}`: {}", err.inner()),
+                };
+                AppError::BadRequest(message).into_response()
+            })
+    }
+}
+
+pub const DRY_RUN_HEADER: &str = "x-dry-run";
+
+#[derive(Debug, Deserialize)]
+struct DryRunParams {
+    dry_run: Option<bool>,
+}
+
+// set by `?dry_run=true` or `X-Dry-Run: true`, the mutating handlers then
+// validate and compose the write but return it instead of running it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DryRun(pub bool);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for DryRun
+where
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(params) = Query::<DryRunParams>::from_request_parts(parts, state)
+            .await
+            .map_err(|_| AppError::BadRequest("dry_run must be a boolean".to_string()))?;
+        let header = match parts.headers.get(DRY_RUN_HEADER) {
+            Some(value) => match value.to_str().map(str::to_lowercase).as_deref() {
+                Ok("true" | "1") => true,
+                Ok("false" | "0") => false,
+                _ => {
+                    return Err(AppError::BadRequest(format!(
+                        "{DRY_RUN_HEADER} must be a boolean"
+                    )))
+                }
+            },
+            None => false,
+        };
+        Ok(DryRun(header || params.dry_run == Some(true)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::User;
+    use crate::test_support::test_state;
+    use crate::AppDatabase;
+    use axum::{body::Body, http::StatusCode, routing::post, Router};
+    use tower::ServiceExt;
+
+    async fn post_json(body: String) -> StatusCode {
+        post_json_response(body).await.status()
+    }
+
+    async fn post_json_response(body: String) -> Response {
+        let mut state = test_state(AppDatabase::default());
+        state.config = Arc::new(Config {
+            max_json_depth: 4,
+            ..Default::default()
+        });
+        let app = Router::new()
+            .route(
+                "/",
+                post(|AppJson(user): AppJson<User>| async move { user.name }),
+            )
+            .with_state(state);
+        let req = Request::builder()
+            .method("POST")
+            .uri("/")
+            .header("Content-Type", "application/json; charset=utf-8")
+            .body(Body::from(body))
+            .unwrap();
+        app.oneshot(req).await.unwrap()
+    }
+
+    #[test]
+    fn test_exceeds_json_depth() {
+        assert!(!exceeds_json_depth(br#"{"a": [[1], {"b": 2}]}"#, 3));
+        assert!(exceeds_json_depth(br#"{"a": [[1], {"b": [2]}]}"#, 3));
+        // brackets inside strings do not count
+        assert!(!exceeds_json_depth(br#"{"a": "[[[[\"{{{{"}"#, 1));
+    }
+
+    #[tokio::test]
+    async fn test_app_json_names_path_of_invalid_field() {
+        let cases = [
+            (
+                r#"{"id": "abc", "name": "Sibu", "phone": "56565656", "isActive": true}"#,
+                "invalid JSON body at `id`: invalid type: string \"abc\", expected i64",
+            ),
+            (
+                r#"{"id": 1, "name": "Sibu", "phone": "56565656", "isActive": "yes"}"#,
+                "invalid JSON body at `isActive`: invalid type: string \"yes\", expected a boolean",
+            ),
+        ];
+        for (body, expected) in cases {
+            let res = post_json_response(body.to_string()).await;
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+            let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["message"], expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_app_json_requires_body() {
+        for body in ["", " \n"] {
+            let res = post_json_response(body.to_string()).await;
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+            let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["message"], BODY_REQUIRED_MESSAGE);
+        }
+        let res = post_json_response("{".to_string()).await;
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(body["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("invalid JSON body"));
+    }
+
+    #[tokio::test]
+    async fn test_app_json_accepts_normal_payload() {
+        let body =
+            r#"{"id": 1, "name": "Sibu", "phone": "56565656", "isActive": true, "tags": [["a"]]}"#;
+        assert_eq!(post_json(body.to_string()).await, StatusCode::OK);
+    }
+
+    // a sample of every property of the published schema, in the formats it
+    // names, is accepted as it is
+    #[tokio::test]
+    async fn test_app_json_accepts_schema_conforming_user() {
+        let schema = User::json_schema();
+        let timestamp = serde_json::json!({"$date": {"$numberLong": "1676000000000"}});
+        let sample = serde_json::json!({
+            "id": 1,
+            "name": "Sibu",
+            "phone": "+9156565656",
+            "email": "sibu@example.com",
+            "isActive": true,
+            "balance": "12.50",
+            "created_at": timestamp,
+            "updated_at": timestamp,
+            "deleted_at": timestamp,
+        });
+        let properties = schema["properties"].as_object().unwrap();
+        assert!(properties.keys().all(|key| sample.get(key).is_some()));
+        let app = Router::new()
+            .route(
+                "/",
+                post(|AppJson(user): AppJson<User>| async move {
+                    axum::Json(serde_json::to_value(user).unwrap())
+                }),
+            )
+            .with_state(test_state(AppDatabase::default()));
+        let req = Request::builder()
+            .method("POST")
+            .uri("/")
+            .header("Content-Type", "application/json")
+            .body(Body::from(sample.to_string()))
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let user: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(user, sample);
+    }
+
+    #[tokio::test]
+    async fn test_app_json_rejects_over_deep_payload() {
+        let nested = format!("{}{}", "[".repeat(10), "]".repeat(10));
+        let body = format!(
+            r#"{{"id": 1, "name": "Sibu", "phone": "56565656", "isActive": true, "tags": {nested}}}"#
+        );
+        assert_eq!(post_json(body).await, StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,115 @@
+use std::{collections::BTreeSet, sync::Arc};
+
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, State},
+    http::{request::Parts, Request},
+    middleware::Next,
+    response::Response,
+};
+
+use crate::{config::Config, error::AppError};
+
+// comma separated flags a client turns on for its own request
+pub const FEATURES_HEADER: &str = "x-features";
+
+// the create endpoints answer with the stored user instead of its id
+pub const CREATE_RETURNS_USER: &str = "create_returns_user";
+
+// feature flags enabled for the current request, the configured ones
+// plus those the client asked for. handlers take it as an extractor,
+// without the layer no flag is enabled
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Features(BTreeSet<String>);
+
+impl Features {
+    pub fn is_enabled(&self, flag: &str) -> bool {
+        self.0.contains(flag)
+    }
+}
+
+// lowercased flags of a comma separated list
+pub fn parse_flags(value: &str) -> impl Iterator<Item = String> + '_ {
+    value
+        .split(',')
+        .map(|flag| flag.trim().to_lowercase())
+        .filter(|flag| !flag.is_empty())
+}
+
+// middleware putting the `Features` of the request into its extensions
+pub async fn inject_features<B>(
+    State(config): State<Arc<Config>>,
+    mut req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let mut flags: BTreeSet<String> = config.feature_flags.iter().cloned().collect();
+    for value in req.headers().get_all(FEATURES_HEADER) {
+        if let Ok(value) = value.to_str() {
+            flags.extend(parse_flags(value));
+        }
+    }
+    req.extensions_mut().insert(Features(flags));
+    next.run(req).await
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Features
+where
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(parts
+            .extensions
+            .get::<Features>()
+            .cloned()
+            .unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::test_state;
+    use crate::AppDatabase;
+    use axum::{body::Body, routing::get, Router};
+    use tower::ServiceExt;
+
+    async fn enabled_flags(config: Config, header: Option<&str>) -> String {
+        let mut state = test_state(AppDatabase::default());
+        state.config = Arc::new(config);
+        let app = Router::new()
+            .route(
+                "/",
+                get(|Features(flags): Features| async move {
+                    flags.into_iter().collect::<Vec<_>>().join(",")
+                }),
+            )
+            .layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                inject_features,
+            ))
+            .with_state(state);
+        let mut req = Request::builder().uri("/");
+        if let Some(header) = header {
+            req = req.header(FEATURES_HEADER, header);
+        }
+        let res = app.oneshot(req.body(Body::empty()).unwrap()).await.unwrap();
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_features_from_config_and_header() {
+        let config = Config {
+            feature_flags: vec!["beta".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(enabled_flags(config.clone(), None).await, "beta");
+        assert_eq!(
+            enabled_flags(config, Some(" New_List, ,other")).await,
+            "beta,new_list,other"
+        );
+    }
+}
@@ -0,0 +1,162 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    response::IntoResponse,
+};
+use mockall_double::double;
+use mongodb::bson::{doc, Bson};
+
+use crate::{bson_json::to_plain_json, config::Config, error::AppError, response::ApiResponse};
+
+#[double]
+use crate::database::AppDatabase;
+
+// names of all the collections in the application database
+pub async fn list_collections_handler(
+    State(database): State<Arc<AppDatabase>>,
+    State(config): State<Arc<Config>>,
+) -> Result<impl IntoResponse, AppError> {
+    let names = database.list_collections(&config.db_name).await?;
+    Ok(ApiResponse::ok(names))
+}
+
+// names of the indexes of a collection, e.g. to check that `id_1` exists
+pub async fn list_indexes_handler(
+    State(database): State<Arc<AppDatabase>>,
+    State(config): State<Arc<Config>>,
+    Path(collection): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    let names = database.list_indexes(&config.db_name, &collection).await?;
+    Ok(ApiResponse::ok(names))
+}
+
+// storage statistics of the application database
+pub async fn db_stats_handler(
+    State(database): State<Arc<AppDatabase>>,
+    State(config): State<Arc<Config>>,
+) -> Result<impl IntoResponse, AppError> {
+    let stats = database
+        .run_command(&config.db_name, doc! {"dbStats": 1})
+        .await?;
+    Ok(ApiResponse::ok(to_plain_json(Bson::Document(stats))))
+}
+
+// connections, memory and operation counters of the server
+pub async fn server_status_handler(
+    State(database): State<Arc<AppDatabase>>,
+) -> Result<impl IntoResponse, AppError> {
+    let status = database
+        .run_command("admin", doc! {"serverStatus": 1})
+        .await?;
+    Ok(ApiResponse::ok(to_plain_json(Bson::Document(status))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build_router;
+    use crate::database::DB_NAME;
+    use crate::test_support::{admin_state, test_state};
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use mockall::predicate::eq;
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    fn collections_request(token: Option<&str>) -> Request<Body> {
+        let mut req = Request::builder().uri("/admin/collections");
+        if let Some(token) = token {
+            req = req.header("Authorization", format!("Bearer {token}"));
+        }
+        req.body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_list_collections_handler() {
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_list_collections()
+            .with(eq(DB_NAME))
+            .times(1)
+            .returning(|_| Ok(vec!["users".to_string(), "audit".to_string()]));
+        let app = build_router(admin_state(mock_db));
+        let res = app
+            .oneshot(collections_request(Some("s3cret")))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body, json!({"success": true, "data": ["users", "audit"]}));
+    }
+
+    #[tokio::test]
+    async fn test_list_indexes_handler() {
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_list_indexes()
+            .with(eq(DB_NAME), eq("users"))
+            .times(1)
+            .returning(|_, _| Ok(vec!["_id_".to_string(), "id_1".to_string()]));
+        let app = build_router(admin_state(mock_db));
+        let req = Request::builder()
+            .uri("/admin/collections/users/indexes")
+            .header("Authorization", "Bearer s3cret")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body, json!({"success": true, "data": ["_id_", "id_1"]}));
+    }
+
+    #[tokio::test]
+    async fn test_db_stats_handler() {
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_run_command()
+            .with(eq(DB_NAME), eq(doc! {"dbStats": 1}))
+            .times(1)
+            .returning(|_, _| {
+                Ok(doc! {"db": DB_NAME, "collections": 2, "dataSize": 1024.0, "ok": 1.0})
+            });
+        let app = build_router(admin_state(mock_db));
+        let req = Request::builder()
+            .uri("/admin/stats")
+            .header("Authorization", "Bearer s3cret")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body["data"],
+            json!({"db": DB_NAME, "collections": 2, "dataSize": 1024.0, "ok": 1.0})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_list_collections_handler_rejects_invalid_token() {
+        let mut mock_db = AppDatabase::default();
+        mock_db.expect_list_collections().times(0);
+        let app = build_router(admin_state(mock_db));
+        for token in [None, Some("wrong")] {
+            let res = app
+                .clone()
+                .oneshot(collections_request(token))
+                .await
+                .unwrap();
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_list_collections_handler_without_configured_token() {
+        let app = build_router(test_state(AppDatabase::default()));
+        let res = app.oneshot(collections_request(Some(""))).await.unwrap();
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,170 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::{extract::State, response::IntoResponse};
+use mockall_double::double;
+use mongodb::{bson::doc, options::CountOptions};
+use serde_json::json;
+
+use crate::{bson_json::to_plain_json, config::Config, error::AppError, response::ApiResponse};
+
+#[double]
+use crate::database::AppDatabase;
+
+// how long clients are told to wait before probing again
+pub const READINESS_RETRY_AFTER: Duration = Duration::from_secs(5);
+
+// readiness probe, besides pinging the server it reads from a collection
+// so missing permissions for the app user are caught as well
+pub async fn readiness_handler(
+    State(database): State<Arc<AppDatabase>>,
+    State(config): State<Arc<Config>>,
+) -> Result<impl IntoResponse, AppError> {
+    if let Err(err) = database.ping().await {
+        tracing::warn!("readiness ping failed: {:?}", err);
+        return Err(AppError::ServiceUnavailable(
+            "database unreachable".to_string(),
+            READINESS_RETRY_AFTER,
+        ));
+    }
+    let options = CountOptions::builder().limit(1).build();
+    let count = database
+        .count_documents(
+            &config.db_name,
+            &config.readiness_collection,
+            None,
+            Some(options),
+        )
+        .await;
+    if let Err(err) = count {
+        tracing::warn!("readiness collection check failed: {:?}", err);
+        return Err(AppError::ServiceUnavailable(
+            format!(
+                "collection {} is not accessible",
+                config.readiness_collection
+            ),
+            READINESS_RETRY_AFTER,
+        ));
+    }
+    Ok(ApiResponse::ok(json!({"status": "ready"})))
+}
+
+// version of the server and its connection counters, taken from
+// `serverStatus`, to size the pools. `connections` counts the connections of
+// every client of the server, not only the ones of this instance
+pub async fn detailed_health_handler(
+    State(database): State<Arc<AppDatabase>>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut status = database
+        .run_command("admin", doc! {"serverStatus": 1})
+        .await?;
+    let mut field = |name: &str| status.remove(name).map_or(json!(null), to_plain_json);
+    Ok(ApiResponse::ok(json!({
+        "status": "ok",
+        "version": field("version"),
+        "uptime_secs": field("uptime"),
+        "connections": field("connections"),
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build_router;
+    use crate::database::DB_NAME;
+    use crate::test_support::{admin_state, mongo_error, test_state};
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use mockall::predicate::eq;
+    use mockall::predicate::function;
+    use tower::ServiceExt;
+
+    fn readyz_request() -> Request<Body> {
+        Request::builder()
+            .uri("/readyz")
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_readiness_handler_ok() {
+        let mut mock_db = AppDatabase::default();
+        mock_db.expect_ping().times(1).returning(|| Ok(()));
+        mock_db
+            .expect_count_documents()
+            .with(
+                eq(DB_NAME),
+                eq("users"),
+                eq(None),
+                function(|x: &Option<CountOptions>| x.as_ref().unwrap().limit == Some(1)),
+            )
+            .times(1)
+            .returning(|_, _, _, _| Ok(1));
+        let app = build_router(test_state(mock_db));
+        let res = app.oneshot(readyz_request()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_readiness_handler_collection_not_accessible() {
+        let mut mock_db = AppDatabase::default();
+        mock_db.expect_ping().times(1).returning(|| Ok(()));
+        mock_db
+            .expect_count_documents()
+            .times(1)
+            .returning(|_, _, _, _| Err(mongo_error("not authorized on myDB")));
+        let app = build_router(test_state(mock_db));
+        let res = app.oneshot(readyz_request()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn test_readiness_handler_ping_failed() {
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_ping()
+            .times(1)
+            .returning(|| Err(mongo_error("not authorized on myDB")));
+        mock_db.expect_count_documents().times(0);
+        let app = build_router(test_state(mock_db));
+        let res = app.oneshot(readyz_request()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(res.headers()[axum::http::header::RETRY_AFTER], "5");
+    }
+
+    #[tokio::test]
+    async fn test_detailed_health_handler_reports_connections() {
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_run_command()
+            .with(eq("admin"), eq(doc! {"serverStatus": 1}))
+            .times(1)
+            .returning(|_, _| {
+                Ok(doc! {
+                    "host": "mongo-0",
+                    "version": "6.0.4",
+                    "uptime": 3600.0,
+                    "connections": {"current": 12, "available": 838_848, "active": 3},
+                    "ok": 1.0,
+                })
+            });
+        let app = build_router(admin_state(mock_db));
+        let req = Request::builder()
+            .uri("/health/detailed")
+            .header("Authorization", "Bearer s3cret")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body["data"],
+            json!({
+                "status": "ok",
+                "version": "6.0.4",
+                "uptime_secs": 3600.0,
+                "connections": {"current": 12, "available": 838_848, "active": 3},
+            })
+        );
+    }
+}
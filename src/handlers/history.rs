@@ -0,0 +1,64 @@
+use axum::{extract::Path, response::IntoResponse};
+
+use crate::{error::AppError, repo::UserRepo, response::ApiResponse};
+
+// every recorded change of the user, oldest first
+pub async fn user_history_handler(
+    repo: UserRepo,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    Ok(ApiResponse::ok(repo.history(id).await?))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::audit::AUDIT_COLLECTION;
+    use crate::build_router;
+    use crate::database::DB_NAME;
+    use crate::models::{AuditEntry, AuditOperation, User};
+    use crate::test_support::{test_state, NOW};
+    use crate::AppDatabase;
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use mockall::predicate::{always, eq};
+    use mongodb::bson::doc;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_user_history_handler() {
+        let entry = AuditEntry {
+            user_id: 7,
+            operation: AuditOperation::Create,
+            old: None,
+            new: Some(User {
+                id: 7,
+                ..Default::default()
+            }),
+            timestamp: NOW,
+        };
+        let returned = entry.clone();
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_find_many::<AuditEntry>()
+            .with(
+                eq(DB_NAME),
+                eq(AUDIT_COLLECTION),
+                eq(Some(doc! {"user_id": 7_i64})),
+                always(),
+            )
+            .times(1)
+            .returning(move |_, _, _, _| Ok(vec![returned.clone()]));
+        let app = build_router(test_state(mock_db));
+        let req = Request::builder()
+            .uri("/user/7/history")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["data"][0]["operation"], "create");
+        assert_eq!(body["data"][0]["new"]["id"], 7);
+        assert!(body["data"][0]["old"].is_null());
+    }
+}
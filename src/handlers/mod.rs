@@ -0,0 +1,6 @@
+pub mod admin;
+pub mod health;
+pub mod history;
+pub mod schema;
+pub mod user;
+pub mod verification;
@@ -0,0 +1,53 @@
+use axum::{
+    http::{header, HeaderValue},
+    response::IntoResponse,
+    Json,
+};
+
+use crate::models::User;
+
+pub const SCHEMA_CONTENT_TYPE: &str = "application/schema+json";
+
+// the schema itself rather than an envelope, so code generators can read it
+pub async fn user_schema_handler() -> impl IntoResponse {
+    (
+        [(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static(SCHEMA_CONTENT_TYPE),
+        )],
+        Json(User::json_schema()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::build_router;
+    use crate::test_support::test_state;
+    use crate::AppDatabase;
+    use axum::body::Body;
+    use axum::http::{header, Request, StatusCode};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_user_schema_handler() {
+        let app = build_router(test_state(AppDatabase::default()));
+        let req = Request::builder()
+            .uri("/schema/user")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers()[header::CONTENT_TYPE],
+            "application/schema+json"
+        );
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let schema: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            schema["required"],
+            serde_json::json!(["id", "name", "phone", "isActive"])
+        );
+        assert_eq!(schema["properties"]["isActive"]["type"], "boolean");
+        assert!(schema["properties"].get("is_active").is_none());
+    }
+}
@@ -0,0 +1,3776 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use futures::TryStreamExt;
+use mongodb::{
+    bson::{doc, Bson, DateTime, Document},
+    error::{ErrorKind, WriteFailure},
+};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::json;
+use tracing::Span;
+
+use crate::{
+    clock::Clock,
+    config::Config,
+    error::AppError,
+    extract::{AppJson, DryRun, KnownFields},
+    features::{Features, CREATE_RETURNS_USER},
+    models::{AuditEntry, AuditOperation, User, UserResponse, UserResponseV2},
+    pagination::PageParams,
+    repo::{name_collation, UserRepo, USERS_COLLECTION},
+    response::{streamed_array, ApiResponse},
+    validation::{is_valid_phone, FieldError, Validator},
+};
+
+pub async fn get_user_handler(repo: UserRepo) -> Result<Response, AppError> {
+    let fetched = repo.get_allow_stale(76).await?;
+    let user = fetched
+        .value
+        .ok_or_else(|| AppError::NotFound("user not found".to_string()))?;
+    let res = ApiResponse::ok(UserResponse::from(user)).selectable(UserResponse::FIELDS);
+    Ok(with_stale_warning(res.into_response(), fetched.stale))
+}
+
+// `/v2/user`, same user as `/v1/user` in the v2 shape
+pub async fn get_user_v2_handler(repo: UserRepo) -> Result<Response, AppError> {
+    let fetched = repo.get_allow_stale(76).await?;
+    let user = fetched
+        .value
+        .ok_or_else(|| AppError::NotFound("user not found".to_string()))?;
+    let res = ApiResponse::ok(UserResponseV2::from(user)).selectable(UserResponseV2::FIELDS);
+    Ok(with_stale_warning(res.into_response(), fetched.stale))
+}
+
+// `Warning` sent along a user served from the stale cache, see `get_allow_stale`
+pub const STALE_WARNING: &str = "110 - \"Response is Stale\"";
+
+fn with_stale_warning(mut res: Response, stale: bool) -> Response {
+    if stale {
+        res.headers_mut()
+            .insert(header::WARNING, HeaderValue::from_static(STALE_WARNING));
+    }
+    res
+}
+
+// true when the client copy, dated by If-Modified-Since, is still current;
+// HTTP dates only have a precision of one second
+fn not_modified_since(last_modified: DateTime, headers: &HeaderMap) -> bool {
+    let since = headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| httpdate::parse_http_date(value).ok());
+    let Some(since) = since else {
+        return false;
+    };
+    let modified_secs = last_modified.timestamp_millis().div_euclid(1000);
+    let since_secs = since
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs() as i64;
+    modified_secs <= since_secs
+}
+
+pub async fn get_user_by_id_handler(
+    repo: UserRepo,
+    Path(id): Path<i64>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let fetched = repo.get_allow_stale(id).await?;
+    let user = fetched
+        .value
+        .ok_or_else(|| AppError::NotFound("user not found".to_string()))?;
+    let Some(last_modified) = user.updated_at.or(user.created_at) else {
+        let res = ApiResponse::ok(UserResponse::from(user))
+            .selectable(UserResponse::FIELDS)
+            .into_response();
+        return Ok(with_stale_warning(res, fetched.stale));
+    };
+    let last_modified_header =
+        HeaderValue::from_str(&httpdate::fmt_http_date(last_modified.to_system_time()))
+            .expect("http date is a valid header value");
+    if not_modified_since(last_modified, &headers) {
+        return Ok((
+            StatusCode::NOT_MODIFIED,
+            [(header::LAST_MODIFIED, last_modified_header)],
+        )
+            .into_response());
+    }
+    let mut res = ApiResponse::ok(UserResponse::from(user))
+        .selectable(UserResponse::FIELDS)
+        .into_response();
+    res.headers_mut()
+        .insert(header::LAST_MODIFIED, last_modified_header);
+    Ok(with_stale_warning(res, fetched.stale))
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ListUsersParams {
+    pub created_after: Option<String>,
+    pub created_before: Option<String>,
+    // users with this name, whatever its case
+    pub name: Option<String>,
+}
+
+fn parse_date_param(name: &str, value: &str) -> Result<DateTime, AppError> {
+    DateTime::parse_rfc3339_str(value).map_err(|_| {
+        AppError::BadRequest(format!("{name} must be an ISO-8601 date, got `{value}`"))
+    })
+}
+
+// build the `created_at` range filter from the optional bounds
+fn created_at_filter(params: &ListUsersParams) -> Result<Document, AppError> {
+    let mut range = Document::new();
+    if let Some(after) = &params.created_after {
+        range.insert("$gte", parse_date_param("created_after", after)?);
+    }
+    if let Some(before) = &params.created_before {
+        range.insert("$lte", parse_date_param("created_before", before)?);
+    }
+    if range.is_empty() {
+        return Ok(Document::new());
+    }
+    Ok(doc! {"created_at": range})
+}
+
+pub async fn list_users_handler(
+    repo: UserRepo,
+    State(config): State<Arc<Config>>,
+    Query(params): Query<ListUsersParams>,
+    Query(page): Query<PageParams>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut filter = created_at_filter(&params)?;
+    let sort = page.sort(User::FIELDS, doc! {"id": 1})?;
+    let mut options = page.find_options(config.default_page_limit, sort);
+    // matched under the collation of the name index rather than by a regex
+    if let Some(name) = params.name {
+        filter.insert("name", name);
+        options.collation = Some(name_collation());
+    }
+    let (page, partial) = match config.partial_list_after {
+        Some(budget) => {
+            let partial = repo
+                .page_within(filter, options, page.with_total(), budget)
+                .await?;
+            (partial.page, partial.partial)
+        }
+        None => (repo.page(filter, options, page.with_total()).await?, false),
+    };
+    let items: Vec<UserResponse> = page.items.into_iter().map(UserResponse::from).collect();
+    let res = if partial {
+        ApiResponse::with_status(StatusCode::PARTIAL_CONTENT, items).partial()
+    } else {
+        ApiResponse::ok(items)
+    };
+    let res = res.selectable(UserResponse::FIELDS);
+    Ok(match page.total {
+        Some(total) => res.with_total(total),
+        None => res,
+    })
+}
+
+// a page of users along with the active and inactive counts, for the dashboard
+pub async fn user_facets_handler(
+    repo: UserRepo,
+    State(config): State<Arc<Config>>,
+    Query(page): Query<PageParams>,
+) -> Result<impl IntoResponse, AppError> {
+    let sort = page.sort(User::FIELDS, doc! {"id": 1})?;
+    let limit = page.limit(config.default_page_limit);
+    let facets = repo.facets(sort, page.skip.unwrap_or(0), limit).await?;
+    let items: Vec<UserResponse> = facets.items.into_iter().map(UserResponse::from).collect();
+    Ok(ApiResponse::ok(json!({
+        "items": items,
+        "counts": {"active": facets.active_count, "inactive": facets.inactive_count},
+    })))
+}
+
+// approximate number of users, good enough for dashboards
+pub async fn estimate_user_count_handler(repo: UserRepo) -> Result<impl IntoResponse, AppError> {
+    let count = repo.estimated_count().await?;
+    Ok(ApiResponse::ok(json!({ "estimated_count": count })))
+}
+
+// every user, streamed as the cursor reads them so memory stays bounded
+// however large the collection is, in the shape of `GET /users`. it runs
+// under the longer export timeout
+pub async fn export_users_handler(repo: UserRepo) -> Result<Response, AppError> {
+    let users = repo.stream(None, None).await?;
+    Ok(streamed_array(users.map_ok(UserResponse::from)))
+}
+
+// operators a bulk update filter may apply to a field
+const BULK_FILTER_OPERATORS: &[&str] = &[
+    "$eq", "$ne", "$gt", "$gte", "$lt", "$lte", "$in", "$nin", "$exists",
+];
+// stored encrypted when a phone cipher is configured, a filter on the
+// plaintext would silently match nothing
+const UNFILTERABLE_FIELDS: &[&str] = &["phone"];
+// fields a bulk update may set, the ids and timestamps are left alone
+const BULK_UPDATE_FIELDS: &[&str] = &["name", "phone", "email", "isActive"];
+
+#[derive(Debug, Deserialize)]
+pub struct BulkUpdatePayload {
+    pub filter: Document,
+    pub update: Document,
+}
+
+impl KnownFields for BulkUpdatePayload {
+    const FIELDS: &'static [&'static str] = &["filter", "update"];
+}
+
+// the filter may only compare user fields using plain operators, which
+// keeps out `$where`, `$expr` and the like
+fn check_bulk_filter(filter: &Document) -> Result<(), AppError> {
+    if filter.is_empty() {
+        return Err(AppError::BadRequest("filter must not be empty".to_string()));
+    }
+    for (key, value) in filter {
+        if !User::FIELDS.contains(&key.as_str()) {
+            return Err(AppError::BadRequest(format!(
+                "filter key `{key}` is not allowed"
+            )));
+        }
+        if UNFILTERABLE_FIELDS.contains(&key.as_str()) {
+            return Err(AppError::BadRequest(format!(
+                "filter key `{key}` is not filterable"
+            )));
+        }
+        if let Bson::Document(condition) = value {
+            if let Some(operator) = condition
+                .keys()
+                .find(|operator| !BULK_FILTER_OPERATORS.contains(&operator.as_str()))
+            {
+                return Err(AppError::BadRequest(format!(
+                    "filter operator `{operator}` is not allowed"
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+// the update holds the fields to set, checked like the ones of a single user
+fn check_bulk_update(update: &Document, allowed_email_domains: &[String]) -> Result<(), AppError> {
+    if update.is_empty() {
+        return Err(AppError::BadRequest("update must not be empty".to_string()));
+    }
+    if let Some(key) = update
+        .keys()
+        .find(|key| !BULK_UPDATE_FIELDS.contains(&key.as_str()))
+    {
+        return Err(AppError::BadRequest(format!(
+            "update key `{key}` is not allowed"
+        )));
+    }
+    let mut validator = Validator::default();
+    if let Some(name) = update.get("name") {
+        let valid = name.as_str().is_some_and(|name| !name.trim().is_empty());
+        validator.check(valid, "name", "must be a non-empty string");
+    }
+    if let Some(phone) = update.get("phone") {
+        let valid = phone.as_str().is_some_and(is_valid_phone);
+        validator.check(valid, "phone", "must contain 6 to 15 digits");
+    }
+    match update.get("email").map(Bson::as_str) {
+        Some(Some(email)) => validator.check_email(email, allowed_email_domains),
+        Some(None) => validator.check(false, "email", "must be a valid email"),
+        None => {}
+    }
+    if let Some(is_active) = update.get("isActive") {
+        validator.check(
+            is_active.as_bool().is_some(),
+            "isActive",
+            "must be a boolean",
+        );
+    }
+    validator.finish()
+}
+
+// admin bulk edit setting the same fields on every matching user
+pub async fn bulk_update_users_handler(
+    repo: UserRepo,
+    State(clock): State<Arc<dyn Clock>>,
+    State(config): State<Arc<Config>>,
+    AppJson(payload): AppJson<BulkUpdatePayload>,
+) -> Result<impl IntoResponse, AppError> {
+    check_bulk_filter(&payload.filter)?;
+    check_bulk_update(&payload.update, &config.allowed_email_domains)?;
+    let now = clock.now();
+    let mut set = payload.update;
+    set.insert("updated_at", now);
+    // read before and after the write so the audit log gets both versions
+    let old = repo.find(Some(payload.filter.clone()), None).await?;
+    let result = repo.update_many(payload.filter, doc! {"$set": set}).await?;
+    if result.modified_count > 0 {
+        let ids: Vec<i64> = old.iter().map(|user| user.id).collect();
+        let new = repo.find(Some(doc! {"id": {"$in": ids}}), None).await?;
+        for user in new {
+            let Some(old) = old.iter().find(|old| old.id == user.id) else {
+                continue;
+            };
+            if *old == user {
+                continue;
+            }
+            let entry = AuditEntry {
+                user_id: user.id,
+                operation: AuditOperation::Update,
+                old: Some(old.clone()),
+                new: Some(user),
+                timestamp: now,
+            };
+            repo.record_audit(&entry).await;
+        }
+    }
+    Ok(ApiResponse::ok(json!({
+        "matched_count": result.matched_count,
+        "modified_count": result.modified_count,
+    })))
+}
+
+// maximum number of ids accepted in a single batch lookup
+pub const MAX_BATCH_IDS: usize = 500;
+
+pub async fn get_users_by_ids_handler(
+    repo: UserRepo,
+    AppJson(ids): AppJson<Vec<i64>>,
+) -> Result<impl IntoResponse, AppError> {
+    if ids.len() > MAX_BATCH_IDS {
+        let message = format!("at most {MAX_BATCH_IDS} ids can be requested at once");
+        return Err(AppError::BadRequest(message));
+    }
+    let filter = Some(doc! {"id": {"$in": &ids}});
+    let found: HashMap<i64, User> = repo
+        .find(filter, None)
+        .await?
+        .into_iter()
+        .map(|user| (user.id, user))
+        .collect();
+    // `$in` does not keep the order of the ids, the users are put back in the
+    // requested order with `null` for the missing ones so clients can zip them
+    let users: Vec<Option<UserResponse>> = ids
+        .iter()
+        .map(|id| found.get(id).cloned().map(UserResponse::from))
+        .collect();
+    Ok(ApiResponse::ok(users).selectable(UserResponse::FIELDS))
+}
+
+// admin bulk delete of the users with the given ids, at most
+// `MAX_BATCH_IDS` of them. the users are read first so the audit log
+// keeps what was deleted
+pub async fn delete_users_handler(
+    repo: UserRepo,
+    State(clock): State<Arc<dyn Clock>>,
+    AppJson(ids): AppJson<Vec<i64>>,
+) -> Result<impl IntoResponse, AppError> {
+    // an empty list would delete nothing, it is most likely a client bug
+    if ids.is_empty() {
+        return Err(AppError::BadRequest("no ids to delete".to_string()));
+    }
+    if ids.len() > MAX_BATCH_IDS {
+        let message = format!("at most {MAX_BATCH_IDS} ids can be deleted at once");
+        return Err(AppError::BadRequest(message));
+    }
+    let users = repo.find(Some(doc! {"id": {"$in": &ids}}), None).await?;
+    let deleted_count = repo.delete_by_ids(&ids).await?;
+    let now = clock.now();
+    for user in users {
+        let entry = AuditEntry {
+            user_id: user.id,
+            operation: AuditOperation::Delete,
+            old: Some(user),
+            new: None,
+            timestamp: now,
+        };
+        repo.record_audit(&entry).await;
+    }
+    Ok(ApiResponse::ok(json!({"deleted_count": deleted_count})))
+}
+
+// description of the write a dry run skipped
+fn dry_run_outcome(
+    operation: &str,
+    filter: Option<Document>,
+    document: impl Serialize,
+) -> ApiResponse<serde_json::Value> {
+    ApiResponse::ok(json!({
+        "dry_run": true,
+        "operation": operation,
+        "collection": USERS_COLLECTION,
+        "filter": filter,
+        "document": document,
+    }))
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct CreateUserParams {
+    pub if_not_exists: Option<bool>,
+}
+
+// the write handlers record the id of the user they change on their
+// span, so every log line of the request can be tied to the user
+#[tracing::instrument(skip_all, fields(user_id = tracing::field::Empty))]
+pub async fn create_user_handler(
+    repo: UserRepo,
+    State(clock): State<Arc<dyn Clock>>,
+    State(config): State<Arc<Config>>,
+    Query(params): Query<CreateUserParams>,
+    DryRun(dry_run): DryRun,
+    features: Features,
+    AppJson(mut payload): AppJson<User>,
+) -> Result<Response, AppError> {
+    Span::current().record("user_id", payload.id);
+    payload.validate(&config.allowed_email_domains, config.require_email)?;
+    let now = clock.now();
+    payload.created_at = Some(now);
+    payload.updated_at = Some(now);
+    if params.if_not_exists == Some(true) {
+        return create_user_if_not_exists(&repo, payload, now, dry_run).await;
+    }
+    if dry_run {
+        return Ok(dry_run_outcome("insert_one", None, &payload).into_response());
+    }
+    let result = match repo.insert(&payload).await {
+        Ok(result) => result,
+        Err(err) => return Err(insert_error(&repo, &payload, err).await),
+    };
+    let entry = AuditEntry {
+        user_id: payload.id,
+        operation: AuditOperation::Create,
+        old: None,
+        new: Some(payload.clone()),
+        timestamp: now,
+    };
+    repo.record_audit(&entry).await;
+    if features.is_enabled(CREATE_RETURNS_USER) {
+        let user = UserResponse::from(payload);
+        return Ok(ApiResponse::with_status(StatusCode::CREATED, user).into_response());
+    }
+    let body = json!({"insertedID": result.inserted_id });
+    Ok(ApiResponse::with_status(StatusCode::CREATED, body).into_response())
+}
+
+// the unique field named by a duplicate key error, from the index of the
+// message like `E11000 duplicate key error collection: myDB.users index: email_1`
+fn duplicate_key_field(err: &mongodb::error::Error) -> Option<&str> {
+    let ErrorKind::Write(WriteFailure::WriteError(write_error)) = err.kind.as_ref() else {
+        return None;
+    };
+    if write_error.code != DUPLICATE_KEY_CODE {
+        return None;
+    }
+    let (_, index) = write_error.message.split_once("index: ")?;
+    let index = index.split_whitespace().next()?;
+    Some(index.strip_suffix("_1").unwrap_or(index))
+}
+
+// a duplicate key becomes a 409 naming the user it collides with, which the
+// error does not carry so it is looked up by the same unique field
+async fn insert_error(repo: &UserRepo, user: &User, err: mongodb::error::Error) -> AppError {
+    let Some(field) = duplicate_key_field(&err) else {
+        return err.into();
+    };
+    let filter = match field {
+        "id" => Some(doc! {"id": user.id}),
+        "email" => user.email.as_ref().map(|email| doc! {"email": email}),
+        _ => None,
+    };
+    let existing_id = match filter {
+        Some(filter) => match repo.find_one(filter).await {
+            Ok(existing) => existing.map(|existing| existing.id),
+            Err(err) => {
+                tracing::warn!("failed to look up the user colliding on {field}: {err:?}");
+                None
+            }
+        },
+        None => None,
+    };
+    AppError::Duplicate {
+        message: format!("a user with this {field} already exists"),
+        existing_id,
+    }
+}
+
+// `?if_not_exists=true`: a user already stored under the id is returned
+// with 200 rather than treated as a conflict, so clients can safely retry.
+// a soft deleted user still holds its id but is not returned, that one is
+// a conflict
+async fn create_user_if_not_exists(
+    repo: &UserRepo,
+    user: User,
+    now: DateTime,
+    dry_run: bool,
+) -> Result<Response, AppError> {
+    if dry_run {
+        let filter = doc! {"id": user.id};
+        return Ok(dry_run_outcome("insert_or_get", Some(filter), &user).into_response());
+    }
+    if !repo.insert_or_get(&user).await? {
+        let existing = repo
+            .get(user.id)
+            .await?
+            .ok_or_else(|| AppError::Conflict(format!("user {} exists but is deleted", user.id)))?;
+        return Ok(ApiResponse::ok(UserResponse::from(existing)).into_response());
+    }
+    let entry = AuditEntry {
+        user_id: user.id,
+        operation: AuditOperation::Create,
+        old: None,
+        new: Some(user.clone()),
+        timestamp: now,
+    };
+    repo.record_audit(&entry).await;
+    Ok(ApiResponse::with_status(StatusCode::CREATED, UserResponse::from(user)).into_response())
+}
+
+// maximum number of users accepted in a single batch insert
+pub const MAX_BATCH_INSERT: usize = 500;
+// server error code of a duplicate key
+const DUPLICATE_KEY_CODE: i32 = 11000;
+
+// outcome of one user of a batch insert
+#[derive(Debug, Serialize, PartialEq)]
+pub struct BatchItemResult {
+    pub index: usize,
+    pub id: i64,
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+// per user results of an unordered insert, from the write errors the
+// server reported; any other error fails the whole batch
+fn batch_results(
+    users: &[User],
+    result: mongodb::error::Result<()>,
+) -> Result<Vec<BatchItemResult>, AppError> {
+    let mut failures = HashMap::new();
+    if let Err(err) = result {
+        let ErrorKind::BulkWrite(failure) = err.kind.as_ref() else {
+            return Err(err.into());
+        };
+        if failure.write_concern_error.is_some() {
+            return Err(err.into());
+        }
+        for write_error in failure.write_errors.iter().flatten() {
+            let message = if write_error.code == DUPLICATE_KEY_CODE {
+                "a user with this id already exists"
+            } else {
+                "the user could not be inserted"
+            };
+            failures.insert(write_error.index, message.to_string());
+        }
+    }
+    Ok(users
+        .iter()
+        .enumerate()
+        .map(|(index, user)| {
+            let message = failures.remove(&index);
+            BatchItemResult {
+                index,
+                id: user.id,
+                success: message.is_none(),
+                message,
+            }
+        })
+        .collect())
+}
+
+// insert many users at once, a failing user does not stop the others;
+// answers 207 listing the outcome of every user when some failed. every
+// user is validated first, a single invalid one rejects the whole batch
+pub async fn create_users_handler(
+    repo: UserRepo,
+    State(clock): State<Arc<dyn Clock>>,
+    State(config): State<Arc<Config>>,
+    AppJson(mut users): AppJson<Vec<User>>,
+) -> Result<impl IntoResponse, AppError> {
+    // the driver refuses an empty insert, it is most likely a client bug
+    if users.is_empty() {
+        return Err(AppError::BadRequest("no users to create".to_string()));
+    }
+    if users.len() > MAX_BATCH_INSERT {
+        let message = format!("at most {MAX_BATCH_INSERT} users can be created at once");
+        return Err(AppError::BadRequest(message));
+    }
+    let mut errors = Vec::new();
+    for (index, user) in users.iter().enumerate() {
+        if let Err(AppError::Validation(invalid)) =
+            user.validate(&config.allowed_email_domains, config.require_email)
+        {
+            errors.extend(invalid.into_iter().map(|error| FieldError {
+                field: format!("{index}.{}", error.field),
+                ..error
+            }));
+        }
+    }
+    if !errors.is_empty() {
+        return Err(AppError::Validation(errors));
+    }
+    let now = clock.now();
+    for user in &mut users {
+        user.created_at = Some(now);
+        user.updated_at = Some(now);
+    }
+    let result = repo.insert_many(&users).await;
+    let results = batch_results(&users, result)?;
+    for (user, item) in users.iter().zip(&results) {
+        if item.success {
+            let entry = AuditEntry {
+                user_id: user.id,
+                operation: AuditOperation::Create,
+                old: None,
+                new: Some(user.clone()),
+                timestamp: now,
+            };
+            repo.record_audit(&entry).await;
+        }
+    }
+    let status = if results.iter().all(|item| item.success) {
+        StatusCode::CREATED
+    } else {
+        StatusCode::MULTI_STATUS
+    };
+    Ok(ApiResponse::with_status(status, results))
+}
+
+// retry-safe create using the client supplied id, a repeated request
+// finds the existing user instead of inserting a duplicate
+#[tracing::instrument(skip_all, fields(user_id = tracing::field::Empty))]
+pub async fn put_user_handler(
+    repo: UserRepo,
+    State(clock): State<Arc<dyn Clock>>,
+    State(config): State<Arc<Config>>,
+    Path(id): Path<i64>,
+    DryRun(dry_run): DryRun,
+    AppJson(mut payload): AppJson<User>,
+) -> Result<impl IntoResponse, AppError> {
+    Span::current().record("user_id", id);
+    if payload.id != id {
+        return Err(AppError::BadRequest(
+            "id in the path does not match the body".to_string(),
+        ));
+    }
+    payload.validate(&config.allowed_email_domains, config.require_email)?;
+    let now = clock.now();
+    payload.created_at = Some(now);
+    payload.updated_at = Some(now);
+    if dry_run {
+        let filter = doc! {"id": id};
+        return Ok(dry_run_outcome("insert_or_get", Some(filter), &payload));
+    }
+    let created = repo.insert_or_get(&payload).await?;
+    if created {
+        let entry = AuditEntry {
+            user_id: id,
+            operation: AuditOperation::Create,
+            old: None,
+            new: Some(payload),
+            timestamp: now,
+        };
+        repo.record_audit(&entry).await;
+    }
+    let status = if created {
+        StatusCode::CREATED
+    } else {
+        StatusCode::OK
+    };
+    Ok(ApiResponse::with_status(
+        status,
+        json!({ "created": created }),
+    ))
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct CloneUserPayload {
+    // an id is picked when missing
+    pub id: Option<i64>,
+}
+
+impl KnownFields for CloneUserPayload {
+    const FIELDS: &'static [&'static str] = &["id"];
+}
+
+// copy an existing user under a new id, to use it as a template
+#[tracing::instrument(skip_all, fields(user_id = tracing::field::Empty))]
+pub async fn clone_user_handler(
+    repo: UserRepo,
+    State(clock): State<Arc<dyn Clock>>,
+    State(config): State<Arc<Config>>,
+    Path(source_id): Path<i64>,
+    AppJson(payload): AppJson<CloneUserPayload>,
+) -> Result<impl IntoResponse, AppError> {
+    let source = repo
+        .get(source_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("user not found".to_string()))?;
+    let id = match payload.id {
+        Some(id) => id,
+        None => repo.next_id().await?,
+    };
+    Span::current().record("user_id", id);
+    let now = clock.now();
+    let user = User {
+        id,
+        created_at: Some(now),
+        updated_at: Some(now),
+        deleted_at: None,
+        ..source
+    };
+    user.validate(&config.allowed_email_domains, config.require_email)?;
+    if !repo.insert_or_get(&user).await? {
+        return Err(AppError::Conflict(format!("user {id} already exists")));
+    }
+    let entry = AuditEntry {
+        user_id: id,
+        operation: AuditOperation::Create,
+        old: None,
+        new: Some(user.clone()),
+        timestamp: now,
+    };
+    repo.record_audit(&entry).await;
+    Ok(ApiResponse::with_status(
+        StatusCode::CREATED,
+        UserResponse::from(user),
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MergeUserPayload {
+    pub source_id: i64,
+}
+
+impl KnownFields for MergeUserPayload {
+    const FIELDS: &'static [&'static str] = &["source_id"];
+}
+
+// the optional fields of the source the target is missing, as a `$set`
+// document and the merged target
+fn merge_missing_fields(target: &User, source: &User, now: DateTime) -> (Document, User) {
+    let mut merged = target.clone();
+    let mut set = doc! {"updated_at": now};
+    if merged.email.is_none() {
+        if let Some(email) = &source.email {
+            set.insert("email", email);
+            merged.email = Some(email.clone());
+        }
+    }
+    if merged.balance.is_none() {
+        if let Some(balance) = source.balance {
+            set.insert("balance", balance);
+            merged.balance = Some(balance);
+        }
+    }
+    merged.updated_at = Some(now);
+    (set, merged)
+}
+
+// fold a duplicate account into this one: the fields the target is missing
+// are copied from the source, then the source is soft deleted, both in a
+// single transaction
+#[tracing::instrument(skip_all, fields(user_id = tracing::field::Empty))]
+pub async fn merge_user_handler(
+    repo: UserRepo,
+    State(clock): State<Arc<dyn Clock>>,
+    Path(id): Path<i64>,
+    AppJson(payload): AppJson<MergeUserPayload>,
+) -> Result<impl IntoResponse, AppError> {
+    Span::current().record("user_id", id);
+    let source_id = payload.source_id;
+    if source_id == id {
+        return Err(AppError::BadRequest(
+            "a user cannot be merged into itself".to_string(),
+        ));
+    }
+    let target = repo
+        .get(id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("user not found".to_string()))?;
+    // a deleted source is read as well, to be refused with a conflict
+    let source = repo
+        .clone()
+        .with_deleted()
+        .get(source_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("source user not found".to_string()))?;
+    if source.deleted_at.is_some() {
+        return Err(AppError::Conflict(format!(
+            "user {source_id} is already deleted"
+        )));
+    }
+    let now = clock.now();
+    let (set, merged) = merge_missing_fields(&target, &source, now);
+    let deleted = User {
+        deleted_at: Some(now),
+        updated_at: Some(now),
+        ..source.clone()
+    };
+    let updates = vec![
+        (id, doc! {"$set": set}),
+        (
+            source_id,
+            doc! {"$set": {"deleted_at": now, "updated_at": now}},
+        ),
+    ];
+    // either user vanished since it was read, nothing got written
+    let results = repo.update_in_transaction(updates).await?;
+    if results.len() < 2 || results.iter().any(|result| result.matched_count == 0) {
+        return Err(AppError::NotFound("user not found".to_string()));
+    }
+    for (old, new) in [(target, merged.clone()), (source, deleted)] {
+        let entry = AuditEntry {
+            user_id: old.id,
+            operation: AuditOperation::Update,
+            old: Some(old),
+            new: Some(new),
+            timestamp: now,
+        };
+        repo.record_audit(&entry).await;
+    }
+    Ok(ApiResponse::ok(UserResponse::from(merged)).selectable(UserResponse::FIELDS))
+}
+
+// heartbeat bumping `updated_at` and nothing else
+#[tracing::instrument(skip_all, fields(user_id = tracing::field::Empty))]
+pub async fn touch_user_handler(
+    repo: UserRepo,
+    State(clock): State<Arc<dyn Clock>>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    Span::current().record("user_id", id);
+    let now = clock.now();
+    let update = doc! {"$set": {"updated_at": now}};
+    let result = repo.audited(id, now, repo.update(id, update)).await?;
+    if result.matched_count == 0 {
+        return Err(AppError::NotFound("user not found".to_string()));
+    }
+    Ok(ApiResponse::ok(json!({ "updated_at": now })))
+}
+
+// bring a soft deleted user back, e.g. one merged by mistake
+#[tracing::instrument(skip_all, fields(user_id = tracing::field::Empty))]
+pub async fn restore_user_handler(
+    repo: UserRepo,
+    State(clock): State<Arc<dyn Clock>>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    Span::current().record("user_id", id);
+    let now = clock.now();
+    // the user is read before and after the restore, deleted at first
+    let repo = repo.with_deleted();
+    let result = repo.audited(id, now, repo.restore(id, now)).await?;
+    if result.matched_count == 0 {
+        return Err(AppError::NotFound(
+            "no deleted user with this id".to_string(),
+        ));
+    }
+    Ok(ApiResponse::ok(
+        json!({ "restored": true, "updated_at": now }),
+    ))
+}
+
+// a single step of a set-fields update, the `op` tag picks the kind so any
+// other operation is refused while parsing the body
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase", deny_unknown_fields)]
+pub enum FieldOperation {
+    Set {
+        field: String,
+        value: serde_json::Value,
+    },
+    Unset {
+        field: String,
+    },
+}
+
+impl KnownFields for FieldOperation {
+    const FIELDS: &'static [&'static str] = &["op", "field", "value"];
+}
+
+// only the optional fields can be removed
+const UNSET_FIELDS: &[&str] = &["email"];
+
+// the update applying `operations` in order, each field may be changed once.
+// the values are checked like the ones of a bulk update
+fn field_operations_update(
+    operations: Vec<FieldOperation>,
+    allowed_email_domains: &[String],
+    require_email: bool,
+    now: DateTime,
+) -> Result<Document, AppError> {
+    if operations.is_empty() {
+        return Err(AppError::BadRequest(
+            "operations must not be empty".to_string(),
+        ));
+    }
+    let mut set = Document::new();
+    let mut unset = Document::new();
+    for (index, operation) in operations.into_iter().enumerate() {
+        let (field, allowed) = match &operation {
+            FieldOperation::Set { field, .. } => (field, BULK_UPDATE_FIELDS),
+            FieldOperation::Unset { field } => (field, UNSET_FIELDS),
+        };
+        if !allowed.contains(&field.as_str()) {
+            return Err(AppError::BadRequest(format!(
+                "operation {index}: field `{field}` cannot be changed this way"
+            )));
+        }
+        if set.contains_key(field) || unset.contains_key(field) {
+            return Err(AppError::BadRequest(format!(
+                "operation {index}: field `{field}` is already changed"
+            )));
+        }
+        match operation {
+            FieldOperation::Set { field, value } => {
+                let value = mongodb::bson::to_bson(&value)
+                    .map_err(|err| AppError::BadRequest(err.to_string()))?;
+                set.insert(field, value);
+            }
+            FieldOperation::Unset { field } => {
+                if require_email && field == "email" {
+                    return Err(AppError::Validation(vec![FieldError {
+                        field,
+                        message: "is required".to_string(),
+                    }]));
+                }
+                unset.insert(field, "");
+            }
+        }
+    }
+    if !set.is_empty() {
+        check_bulk_update(&set, allowed_email_domains)?;
+    }
+    set.insert("updated_at", now);
+    let mut update = doc! {"$set": set};
+    if !unset.is_empty() {
+        update.insert("$unset", unset);
+    }
+    Ok(update)
+}
+
+// advanced update taking a list of typed operations instead of a raw update
+#[tracing::instrument(skip_all, fields(user_id = tracing::field::Empty))]
+pub async fn set_user_fields_handler(
+    repo: UserRepo,
+    State(clock): State<Arc<dyn Clock>>,
+    State(config): State<Arc<Config>>,
+    Path(id): Path<i64>,
+    AppJson(operations): AppJson<Vec<FieldOperation>>,
+) -> Result<impl IntoResponse, AppError> {
+    Span::current().record("user_id", id);
+    let now = clock.now();
+    let update = field_operations_update(
+        operations,
+        &config.allowed_email_domains,
+        config.require_email,
+        now,
+    )?;
+    let result = repo.audited(id, now, repo.update(id, update)).await?;
+    if result.matched_count == 0 {
+        return Err(AppError::NotFound("user not found".to_string()));
+    }
+    Ok(ApiResponse::ok(json!({
+        "matched_count": result.matched_count,
+        "modified_count": result.modified_count,
+    })))
+}
+
+// the array fields whose elements can be changed one at a time, ahead of
+// them being added to `User`
+const ARRAY_FIELDS: &[&str] = &["roles"];
+
+// the element of an array field equal to `matches` gets replaced by `value`
+#[derive(Debug, Deserialize)]
+pub struct ElementUpdate {
+    pub matches: serde_json::Value,
+    pub value: serde_json::Value,
+}
+
+impl KnownFields for ElementUpdate {
+    const FIELDS: &'static [&'static str] = &["matches", "value"];
+}
+
+// true when a `$` key shows up anywhere in the value, which would turn an
+// equality match into a query operator like `$ne` or `$where`
+fn has_operator(value: &serde_json::Value) -> bool {
+    match value {
+        serde_json::Value::Object(object) => object
+            .iter()
+            .any(|(key, value)| key.starts_with('$') || has_operator(value)),
+        serde_json::Value::Array(items) => items.iter().any(has_operator),
+        _ => false,
+    }
+}
+
+// change the elements of an array field of a user through `arrayFilters`,
+// a user without a matching element is not found
+#[tracing::instrument(skip_all, fields(user_id = tracing::field::Empty))]
+pub async fn update_user_element_handler(
+    repo: UserRepo,
+    State(clock): State<Arc<dyn Clock>>,
+    Path((id, field)): Path<(i64, String)>,
+    AppJson(payload): AppJson<ElementUpdate>,
+) -> Result<impl IntoResponse, AppError> {
+    Span::current().record("user_id", id);
+    if !ARRAY_FIELDS.contains(&field.as_str()) {
+        return Err(AppError::BadRequest(format!(
+            "field `{field}` is not an array field"
+        )));
+    }
+    if has_operator(&payload.matches) {
+        return Err(AppError::BadRequest(
+            "matches must be a plain value, operators are not allowed".to_string(),
+        ));
+    }
+    let to_bson = |value: &serde_json::Value| {
+        mongodb::bson::to_bson(value).map_err(|err| AppError::BadRequest(err.to_string()))
+    };
+    let matches = to_bson(&payload.matches)?;
+    let value = to_bson(&payload.value)?;
+    let filter = doc! {"id": id, field.as_str(): matches.clone()};
+    let now = clock.now();
+    let update = doc! {"$set": {
+        format!("{field}.$[element]"): value,
+        "updated_at": now,
+    }};
+    let array_filters = vec![doc! {"element": matches}];
+    let write = repo.update_elements(filter, update, array_filters);
+    let result = repo.audited(id, now, write).await?;
+    if result.matched_count == 0 {
+        return Err(AppError::NotFound(
+            "no user with a matching element".to_string(),
+        ));
+    }
+    Ok(ApiResponse::ok(json!({
+        "matched_count": result.matched_count,
+        "modified_count": result.modified_count,
+    })))
+}
+
+// keeps an explicit `null` apart from a missing field: a missing field
+// stays `None` through `#[serde(default)]` and `null` becomes `Some(None)`
+fn deserialize_some<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+// fields of a user which can be changed after creation, absent fields are kept
+#[derive(Debug, Default, Deserialize)]
+pub struct UserPatch {
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub name: Option<Option<String>>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub phone: Option<Option<String>>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub email: Option<Option<String>>,
+    #[serde(rename = "isActive", default, deserialize_with = "deserialize_some")]
+    pub is_active: Option<Option<bool>>,
+}
+
+impl KnownFields for UserPatch {
+    const FIELDS: &'static [&'static str] = &["name", "phone", "email", "isActive"];
+}
+
+impl UserPatch {
+    // with `merge` the JSON Merge Patch (RFC 7396) rules apply and a `null`
+    // removes the field, only email is optional so the other fields refuse
+    // it; a plain JSON patch ignores `null` like a missing field
+    fn apply(&self, user: &mut User, merge: bool) -> Result<(), AppError> {
+        let mut validator = Validator::default();
+        let required = [
+            ("name", self.name.as_ref().map(Option::is_none)),
+            ("phone", self.phone.as_ref().map(Option::is_none)),
+            ("isActive", self.is_active.as_ref().map(Option::is_none)),
+        ];
+        for (field, is_null) in required {
+            validator.check(
+                !(merge && is_null == Some(true)),
+                field,
+                "cannot be removed",
+            );
+        }
+        validator.finish()?;
+
+        if let Some(Some(name)) = &self.name {
+            user.name = name.clone();
+        }
+        if let Some(Some(phone)) = &self.phone {
+            user.phone = phone.clone();
+        }
+        match &self.email {
+            Some(Some(email)) => user.email = Some(email.clone()),
+            Some(None) if merge => user.email = None,
+            _ => {}
+        }
+        if let Some(Some(is_active)) = self.is_active {
+            user.is_active = is_active;
+        }
+        Ok(())
+    }
+
+    // the update writing only the fields the patch carries, so a concurrent
+    // write to any other field is kept. under `merge` a removed email is
+    // `$unset`, `apply` already refused removing the other fields
+    fn update_document(&self, merge: bool, now: DateTime) -> Document {
+        let mut set = doc! {"updated_at": now};
+        if let Some(Some(name)) = &self.name {
+            set.insert("name", name);
+        }
+        if let Some(Some(phone)) = &self.phone {
+            set.insert("phone", phone);
+        }
+        if let Some(Some(email)) = &self.email {
+            set.insert("email", email);
+        }
+        if let Some(Some(is_active)) = self.is_active {
+            set.insert("isActive", is_active);
+        }
+        let mut update = doc! {"$set": set};
+        if merge && matches!(self.email, Some(None)) {
+            update.insert("$unset", doc! {"email": ""});
+        }
+        update
+    }
+}
+
+pub const MERGE_PATCH_CONTENT_TYPE: &str = "application/merge-patch+json";
+
+fn is_merge_patch(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with(MERGE_PATCH_CONTENT_TYPE))
+}
+
+// the user after a patch, `changed` is false when the patch left it as it was
+#[derive(Debug, Serialize)]
+struct PatchedUser {
+    #[serde(flatten)]
+    user: UserResponse,
+    changed: bool,
+}
+
+// partial update, `application/merge-patch+json` bodies follow JSON Merge
+// Patch while plain JSON bodies only set the fields they carry
+#[tracing::instrument(skip_all, fields(user_id = tracing::field::Empty))]
+pub async fn patch_user_handler(
+    repo: UserRepo,
+    State(clock): State<Arc<dyn Clock>>,
+    State(config): State<Arc<Config>>,
+    Path(id): Path<i64>,
+    DryRun(dry_run): DryRun,
+    headers: HeaderMap,
+    AppJson(patch): AppJson<UserPatch>,
+) -> Result<Response, AppError> {
+    Span::current().record("user_id", id);
+    let old = repo
+        .get(id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("user not found".to_string()))?;
+    let merge = is_merge_patch(&headers);
+    let mut user = old.clone();
+    patch.apply(&mut user, merge)?;
+    // nothing to write when the patch does not change anything
+    if user == old {
+        let patched = PatchedUser {
+            user: UserResponse::from(user),
+            changed: false,
+        };
+        return Ok(ApiResponse::ok(patched).into_response());
+    }
+    user.validate(&config.allowed_email_domains, config.require_email)?;
+    let now = clock.now();
+    let update = patch.update_document(merge, now);
+    if dry_run {
+        let filter = doc! {"id": id};
+        return Ok(dry_run_outcome("update_one", Some(filter), update).into_response());
+    }
+    // the user got removed between the read and the update
+    let result = repo.update_changed(id, update).await?;
+    if result.matched_count == 0 {
+        return Err(AppError::NotFound("user not found".to_string()));
+    }
+    // a concurrent request may have written the same values first
+    let changed = result.modified_count > 0;
+    if changed {
+        user.updated_at = Some(now);
+        let entry = AuditEntry {
+            user_id: id,
+            operation: AuditOperation::Update,
+            old: Some(old),
+            new: Some(user.clone()),
+            timestamp: now,
+        };
+        repo.record_audit(&entry).await;
+    }
+    let patched = PatchedUser {
+        user: UserResponse::from(user),
+        changed,
+    };
+    Ok(ApiResponse::ok(patched).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::AUDIT_COLLECTION;
+    use crate::build_router;
+    use crate::database::{InsertOneResult, UpdateResult, DB_NAME};
+    use crate::extract::BODY_REQUIRED_MESSAGE;
+    use crate::test_support::{admin_state, allow_audit, allow_user_reads, live, test_state, NOW};
+    use crate::{AppDatabase, AppState};
+    use axum::body::Body;
+    use axum::http::Request;
+    use axum::routing::{get, post};
+    use axum::Router;
+    use mockall::predicate::always;
+    use mockall::predicate::eq;
+    use mockall::predicate::function;
+    use mongodb::bson::oid::ObjectId;
+    use mongodb::bson::to_document;
+    use mongodb::options::Collation;
+    use mongodb::options::CountOptions;
+    use mongodb::options::FindOneOptions;
+    use mongodb::options::FindOptions;
+    use mongodb::options::InsertOneOptions;
+    use mongodb::options::UpdateOptions;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_create_user_handler() {
+        let user = User {
+            id: 200075,
+            name: "Sibaprasad".to_string(),
+            phone: "56565656".to_string(),
+            email: None,
+            is_active: true,
+            balance: None,
+            created_at: None,
+            updated_at: None,
+            deleted_at: None,
+        };
+        let stored = User {
+            created_at: Some(NOW),
+            updated_at: Some(NOW),
+            ..user.clone()
+        };
+        let coll_name = "users";
+        let insert_one_result = InsertOneResult {
+            inserted_id: ObjectId::new().to_hex(),
+        };
+        let is_none = function(|x: &Option<InsertOneOptions>| x.is_none());
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_insert_one::<User>()
+            .with(eq(DB_NAME), eq(coll_name), eq(stored), is_none)
+            .times(1)
+            .returning(move |_, _, _, _| Ok(insert_one_result.clone()));
+        allow_audit(&mut mock_db);
+        let app = Router::new()
+            .route("/", get(create_user_handler))
+            .with_state(test_state(mock_db));
+        let stringified = serde_json::to_string(&user).unwrap();
+        let req = Request::builder()
+            .uri("/")
+            .header("Content-Type", "application/json")
+            .body(stringified)
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn test_create_user_handler_requires_email_when_configured() {
+        let user = User {
+            id: 5,
+            name: "Sibaprasad".to_string(),
+            phone: "56565656".to_string(),
+            ..Default::default()
+        };
+        let mut mock_db = AppDatabase::default();
+        mock_db.expect_insert_one::<User>().times(0);
+        let state = AppState {
+            config: Arc::new(Config {
+                require_email: true,
+                ..Default::default()
+            }),
+            ..test_state(mock_db)
+        };
+        let req = Request::builder()
+            .method("POST")
+            .uri("/user")
+            .header("Content-Type", "application/json")
+            .body(Body::from(serde_json::to_string(&user).unwrap()))
+            .unwrap();
+        let res = build_router(state).oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["errors"][0]["field"], "email", "{body}");
+    }
+
+    // collects the fields recorded on spans after their creation
+    #[derive(Clone, Default)]
+    struct RecordedFields(Arc<std::sync::Mutex<Vec<(String, String)>>>);
+
+    impl tracing::field::Visit for RecordedFields {
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            let value = format!("{value:?}");
+            self.0
+                .lock()
+                .unwrap()
+                .push((field.name().to_string(), value));
+        }
+    }
+
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for RecordedFields {
+        fn on_record(
+            &self,
+            _span: &tracing::span::Id,
+            values: &tracing::span::Record<'_>,
+            _ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            values.record(&mut self.clone());
+        }
+    }
+
+    fn create_if_not_exists_request(user: &User) -> Request<Body> {
+        Request::builder()
+            .method("POST")
+            .uri("/user?if_not_exists=true")
+            .header("Content-Type", "application/json")
+            .body(Body::from(serde_json::to_string(user).unwrap()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_create_user_handler_if_not_exists_creates() {
+        let user = User {
+            id: 12,
+            name: "Sibaprasad".to_string(),
+            phone: "56565656".to_string(),
+            ..Default::default()
+        };
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_insert_or_get::<User>()
+            .with(
+                eq(DB_NAME),
+                eq("users"),
+                eq(doc! {"id": 12_i64}),
+                function(|x: &User| x.id == 12 && x.created_at == Some(NOW)),
+            )
+            .times(1)
+            .returning(|_, _, _, _| Ok(true));
+        mock_db.expect_insert_one::<User>().times(0);
+        allow_audit(&mut mock_db);
+        let app = build_router(test_state(mock_db));
+        let res = app
+            .oneshot(create_if_not_exists_request(&user))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["data"]["id"], 12);
+    }
+
+    #[tokio::test]
+    async fn test_create_user_handler_if_not_exists_returns_existing() {
+        let user = User {
+            id: 12,
+            name: "Sibaprasad".to_string(),
+            phone: "56565656".to_string(),
+            ..Default::default()
+        };
+        let existing = User {
+            name: "Sibu".to_string(),
+            ..user.clone()
+        };
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_insert_or_get::<User>()
+            .times(1)
+            .returning(|_, _, _, _| Ok(false));
+        mock_db
+            .expect_find_one::<User>()
+            .with(
+                eq(DB_NAME),
+                eq("users"),
+                eq(Some(live(doc! {"id": 12_i64}))),
+                always(),
+            )
+            .times(1)
+            .returning(move |_, _, _, _| Ok(Some(existing.clone())));
+        mock_db.expect_insert_one::<AuditEntry>().times(0);
+        let app = build_router(test_state(mock_db));
+        let res = app
+            .oneshot(create_if_not_exists_request(&user))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["data"]["name"], "Sibu");
+    }
+
+    #[tokio::test]
+    async fn test_create_user_handler_if_not_exists_deleted_user_conflicts() {
+        let user = User {
+            id: 12,
+            name: "Sibaprasad".to_string(),
+            phone: "56565656".to_string(),
+            ..Default::default()
+        };
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_insert_or_get::<User>()
+            .times(1)
+            .returning(|_, _, _, _| Ok(false));
+        // the id is taken by a soft deleted user, which the live read skips
+        mock_db
+            .expect_find_one::<User>()
+            .with(
+                eq(DB_NAME),
+                eq("users"),
+                eq(Some(live(doc! {"id": 12_i64}))),
+                always(),
+            )
+            .times(1)
+            .returning(|_, _, _, _| Ok(None));
+        mock_db.expect_insert_one::<AuditEntry>().times(0);
+        let app = build_router(test_state(mock_db));
+        let res = app
+            .oneshot(create_if_not_exists_request(&user))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["message"], "user 12 exists but is deleted");
+    }
+
+    #[tokio::test]
+    async fn test_create_user_handler_records_user_id_on_span() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let recorded = RecordedFields::default();
+        let subscriber = tracing_subscriber::registry().with(recorded.clone());
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let app = build_router(test_state(create_user_mock(31)));
+        let user = User {
+            id: 31,
+            name: "Sibaprasad".to_string(),
+            phone: "56565656".to_string(),
+            ..Default::default()
+        };
+        let req = Request::builder()
+            .method("POST")
+            .uri("/user")
+            .header("Content-Type", "application/json")
+            .body(Body::from(serde_json::to_string(&user).unwrap()))
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let fields = recorded.0.lock().unwrap();
+        assert!(fields.contains(&("user_id".to_string(), "31".to_string())));
+    }
+
+    #[tokio::test]
+    async fn test_create_user_handler_returns_user_with_feature_flag() {
+        let user = User {
+            id: 31,
+            name: "Sibaprasad".to_string(),
+            phone: "56565656".to_string(),
+            ..Default::default()
+        };
+        let app = build_router(test_state(create_user_mock(31)));
+        let req = Request::builder()
+            .method("POST")
+            .uri("/user")
+            .header("Content-Type", "application/json")
+            .header(crate::features::FEATURES_HEADER, CREATE_RETURNS_USER)
+            .body(Body::from(serde_json::to_string(&user).unwrap()))
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["data"]["id"], 31);
+        assert_eq!(body["data"]["display_name"], "Sibaprasad (****5656)");
+        assert!(body["data"].get("insertedID").is_none());
+    }
+
+    fn duplicate_key_error(index: &str) -> mongodb::error::Error {
+        let write_error = mongodb::bson::from_document(doc! {
+            "code": 11000,
+            "errmsg": format!("E11000 duplicate key error collection: myDB.users index: {index} dup key: {{ }}"),
+        })
+        .unwrap();
+        ErrorKind::Write(WriteFailure::WriteError(write_error)).into()
+    }
+
+    async fn create_conflict(mock_db: AppDatabase, user: &User) -> serde_json::Value {
+        let app = build_router(test_state(mock_db));
+        let req = Request::builder()
+            .method("POST")
+            .uri("/user")
+            .header("Content-Type", "application/json")
+            .body(Body::from(serde_json::to_string(user).unwrap()))
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_create_user_handler_conflict_returns_existing_id() {
+        let user = User {
+            id: 8,
+            name: "Sibu".to_string(),
+            phone: "56565656".to_string(),
+            email: Some("sibu@example.com".to_string()),
+            ..Default::default()
+        };
+        let existing = User {
+            id: 3,
+            ..user.clone()
+        };
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_insert_one::<User>()
+            .times(1)
+            .returning(|_, _, _, _| Err(duplicate_key_error("email_1")));
+        mock_db
+            .expect_find_one::<User>()
+            .with(
+                eq(DB_NAME),
+                eq("users"),
+                eq(Some(live(doc! {"email": "sibu@example.com"}))),
+                always(),
+            )
+            .times(1)
+            .returning(move |_, _, _, _| Ok(Some(existing.clone())));
+        mock_db.expect_insert_one::<AuditEntry>().times(0);
+        let body = create_conflict(mock_db, &user).await;
+        assert_eq!(body["message"], "a user with this email already exists");
+        assert_eq!(body["existing_id"], 3);
+    }
+
+    #[tokio::test]
+    async fn test_create_user_handler_conflict_on_id() {
+        let user = User {
+            id: 8,
+            name: "Sibu".to_string(),
+            phone: "56565656".to_string(),
+            ..Default::default()
+        };
+        let existing = user.clone();
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_insert_one::<User>()
+            .times(1)
+            .returning(|_, _, _, _| Err(duplicate_key_error("id_1")));
+        mock_db
+            .expect_find_one::<User>()
+            .with(
+                eq(DB_NAME),
+                eq("users"),
+                eq(Some(live(doc! {"id": 8_i64}))),
+                always(),
+            )
+            .times(1)
+            .returning(move |_, _, _, _| Ok(Some(existing.clone())));
+        let body = create_conflict(mock_db, &user).await;
+        assert_eq!(body["message"], "a user with this id already exists");
+        assert_eq!(body["existing_id"], 8);
+    }
+
+    fn create_user_mock(id: i64) -> AppDatabase {
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_insert_one::<User>()
+            .with(
+                eq(DB_NAME),
+                eq("users"),
+                function(move |x: &User| x.id == id),
+                always(),
+            )
+            .times(1)
+            .returning(|_, _, _, _| {
+                Ok(InsertOneResult {
+                    inserted_id: String::new(),
+                })
+            });
+        allow_audit(&mut mock_db);
+        mock_db
+    }
+
+    #[tokio::test]
+    async fn test_get_user_handler() {
+        let user = User {
+            id: 76,
+            name: "Sibaprasad".to_string(),
+            phone: "56565656".to_string(),
+            ..Default::default()
+        };
+        let coll_name = "users";
+        let filter = Some(live(doc! {"id": 76_i64}));
+        let is_none = function(|x: &Option<FindOneOptions>| x.is_none());
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_find_one::<User>()
+            .with(eq(DB_NAME), eq(coll_name), eq(filter), is_none)
+            .times(1)
+            .returning(move |_, _, _, _| Ok(Some(user.clone())));
+        let app = Router::new()
+            .route("/", get(get_user_handler))
+            .with_state(test_state(mock_db));
+        let req = Request::builder().uri("/").body(Body::empty()).unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["data"]["display_name"], "Sibaprasad (****5656)");
+        assert_eq!(body["data"]["phone"], "56565656");
+    }
+
+    fn versioned_user_mock() -> AppDatabase {
+        let user = User {
+            id: 76,
+            name: "Sibu".to_string(),
+            phone: "56565656".to_string(),
+            email: Some("sibu@example.com".to_string()),
+            is_active: true,
+            ..Default::default()
+        };
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_find_one::<User>()
+            .times(1)
+            .returning(move |_, _, _, _| Ok(Some(user.clone())));
+        mock_db
+    }
+
+    async fn get_data(db: AppDatabase, uri: &str) -> serde_json::Value {
+        let app = build_router(test_state(db));
+        let req = Request::builder().uri(uri).body(Body::empty()).unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        body["data"].clone()
+    }
+
+    #[tokio::test]
+    async fn test_get_user_v1_and_v2_shapes() {
+        let v1 = get_data(versioned_user_mock(), "/v1/user").await;
+        assert_eq!(
+            v1,
+            json!({
+                "id": 76,
+                "name": "Sibu",
+                "phone": "56565656",
+                "email": "sibu@example.com",
+                "isActive": true,
+                "display_name": "Sibu (****5656)",
+            })
+        );
+        let v2 = get_data(versioned_user_mock(), "/v2/user").await;
+        assert_eq!(
+            v2,
+            json!({
+                "id": 76,
+                "name": "Sibu",
+                "contact": {"phone": "56565656", "email": "sibu@example.com"},
+                "isActive": true,
+                "display_name": "Sibu (****5656)",
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_users_by_ids_handler() {
+        let users = vec![
+            User {
+                id: 1,
+                ..Default::default()
+            },
+            User {
+                id: 2,
+                ..Default::default()
+            },
+        ];
+        let coll_name = "users";
+        let filter = Some(live(doc! {"id": {"$in": [1_i64, 2_i64]}}));
+        let is_none = function(|x: &Option<FindOptions>| x.is_none());
+        let mut mock_db = AppDatabase::default();
+        let returned = users.clone();
+        mock_db
+            .expect_find_many::<User>()
+            .with(eq(DB_NAME), eq(coll_name), eq(filter), is_none)
+            .times(1)
+            .returning(move |_, _, _, _| Ok(returned.clone()));
+        let app = Router::new()
+            .route("/", post(get_users_by_ids_handler))
+            .with_state(test_state(mock_db));
+        let req = Request::builder()
+            .method("POST")
+            .uri("/")
+            .header("Content-Type", "application/json")
+            .body(Body::from("[1, 2]"))
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let users: Vec<UserResponse> = users.into_iter().map(UserResponse::from).collect();
+        assert_eq!(body, json!({"success": true, "data": users}));
+    }
+
+    #[tokio::test]
+    async fn test_get_users_by_ids_handler_keeps_request_order() {
+        let user = |id| User {
+            id,
+            name: format!("user {id}"),
+            phone: "56565656".to_string(),
+            ..Default::default()
+        };
+        let out_of_order = vec![user(3), user(1)];
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_find_many::<User>()
+            .times(1)
+            .returning(move |_, _, _, _| Ok(out_of_order.clone()));
+        let app = build_router(test_state(mock_db));
+        let req = Request::builder()
+            .method("POST")
+            .uri("/users/by-ids")
+            .header("Content-Type", "application/json")
+            .body(Body::from("[1, 2, 3]"))
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let expected = vec![
+            Some(UserResponse::from(user(1))),
+            None,
+            Some(UserResponse::from(user(3))),
+        ];
+        assert_eq!(body, json!({"success": true, "data": expected}));
+    }
+
+    #[tokio::test]
+    async fn test_get_users_by_ids_handler_over_limit() {
+        let mut mock_db = AppDatabase::default();
+        mock_db.expect_find_many::<User>().times(0);
+        let app = Router::new()
+            .route("/", post(get_users_by_ids_handler))
+            .with_state(test_state(mock_db));
+        let ids: Vec<i64> = (0..=MAX_BATCH_IDS as i64).collect();
+        let req = Request::builder()
+            .method("POST")
+            .uri("/")
+            .header("Content-Type", "application/json")
+            .body(Body::from(serde_json::to_string(&ids).unwrap()))
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    // the array bodies go through `AppJson` as well, so a missing body is
+    // answered in the same shape as for the other routes
+    #[tokio::test]
+    async fn test_array_body_handlers_require_body() {
+        let app = build_router(admin_state(AppDatabase::default()));
+        for uri in ["/users", "/users/batch", "/users/by-ids", "/users/delete"] {
+            let req = Request::builder()
+                .method("POST")
+                .uri(uri)
+                .header("Authorization", "Bearer s3cret")
+                .body(Body::empty())
+                .unwrap();
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{uri}");
+            let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["message"], BODY_REQUIRED_MESSAGE, "{uri}");
+        }
+    }
+
+    fn put_user_request(user: &User) -> Request<Body> {
+        Request::builder()
+            .method("PUT")
+            .uri(format!("/user/{}", user.id))
+            .header("Content-Type", "application/json")
+            .body(Body::from(serde_json::to_string(user).unwrap()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_put_user_handler_new_insert() {
+        let user = User {
+            id: 42,
+            name: "Sibaprasad".to_string(),
+            phone: "56565656".to_string(),
+            ..Default::default()
+        };
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_insert_or_get::<User>()
+            .with(
+                eq(DB_NAME),
+                eq("users"),
+                eq(doc! {"id": 42_i64}),
+                eq(User {
+                    created_at: Some(NOW),
+                    updated_at: Some(NOW),
+                    ..user.clone()
+                }),
+            )
+            .times(1)
+            .returning(|_, _, _, _| Ok(true));
+        allow_audit(&mut mock_db);
+        let app = build_router(test_state(mock_db));
+        let res = app.oneshot(put_user_request(&user)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body, json!({"success": true, "data": {"created": true}}));
+    }
+
+    #[tokio::test]
+    async fn test_put_user_handler_already_exists() {
+        let user = User {
+            id: 42,
+            name: "Sibaprasad".to_string(),
+            phone: "56565656".to_string(),
+            ..Default::default()
+        };
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_insert_or_get::<User>()
+            .times(1)
+            .returning(|_, _, _, _| Ok(false));
+        let app = build_router(test_state(mock_db));
+        let res = app.oneshot(put_user_request(&user)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body, json!({"success": true, "data": {"created": false}}));
+    }
+
+    #[tokio::test]
+    async fn test_list_users_handler_created_range() {
+        let after = DateTime::parse_rfc3339_str("2023-01-01T00:00:00Z").unwrap();
+        let before = DateTime::parse_rfc3339_str("2023-02-01T00:00:00Z").unwrap();
+        let filter = Some(live(doc! {"created_at": {"$gte": after, "$lte": before}}));
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_find_many::<User>()
+            .with(eq(DB_NAME), eq("users"), eq(filter.clone()), always())
+            .times(1)
+            .returning(|_, _, _, _| Ok(vec![User::default()]));
+        mock_db
+            .expect_count_documents()
+            .with(eq(DB_NAME), eq("users"), eq(filter), always())
+            .times(1)
+            .returning(|_, _, _, _| Ok(1));
+        let app = build_router(test_state(mock_db));
+        let req = Request::builder()
+            .uri("/users?created_after=2023-01-01T00:00:00Z&created_before=2023-02-01T00:00:00Z")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_list_users_handler_name_under_collation() {
+        let expected = to_document(&name_collation()).unwrap();
+        let is_name_collation = move |collation: Option<&Collation>| {
+            collation.map(|collation| to_document(collation).unwrap()) == Some(expected.clone())
+        };
+        let find_collation = is_name_collation.clone();
+        let filter = Some(live(doc! {"name": "SIBU"}));
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_find_many::<User>()
+            .with(
+                eq(DB_NAME),
+                eq("users"),
+                eq(filter.clone()),
+                function(move |x: &Option<FindOptions>| {
+                    find_collation(x.as_ref().and_then(|o| o.collation.as_ref()))
+                }),
+            )
+            .times(1)
+            .returning(|_, _, _, _| Ok(vec![User::default()]));
+        mock_db
+            .expect_count_documents()
+            .with(
+                eq(DB_NAME),
+                eq("users"),
+                eq(filter),
+                function(move |x: &Option<CountOptions>| {
+                    is_name_collation(x.as_ref().and_then(|o| o.collation.as_ref()))
+                }),
+            )
+            .times(1)
+            .returning(|_, _, _, _| Ok(1));
+        let app = build_router(test_state(mock_db));
+        let req = Request::builder()
+            .uri("/users?name=SIBU")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_list_users_handler_returns_page_and_total() {
+        let users = vec![
+            User {
+                id: 1,
+                ..Default::default()
+            },
+            User {
+                id: 2,
+                ..Default::default()
+            },
+        ];
+        let returned = users.clone();
+        let is_page = function(|x: &Option<FindOptions>| {
+            let options = x.as_ref().unwrap();
+            options.limit == Some(2)
+                && options.skip == Some(4)
+                && options.sort == Some(doc! {"id": 1})
+        });
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_find_many::<User>()
+            .with(eq(DB_NAME), eq("users"), eq(Some(live(doc! {}))), is_page)
+            .times(1)
+            .returning(move |_, _, _, _| Ok(returned.clone()));
+        mock_db
+            .expect_count_documents()
+            .with(
+                eq(DB_NAME),
+                eq("users"),
+                eq(Some(live(doc! {}))),
+                function(|x: &Option<CountOptions>| x.is_none()),
+            )
+            .times(1)
+            .returning(|_, _, _, _| Ok(42));
+        let app = build_router(test_state(mock_db));
+        let req = Request::builder()
+            .uri("/users?limit=2&skip=4")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let users: Vec<UserResponse> = users.into_iter().map(UserResponse::from).collect();
+        assert_eq!(body, json!({"success": true, "data": users, "total": 42}));
+    }
+
+    #[tokio::test]
+    async fn test_list_users_handler_without_total() {
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_find_many::<User>()
+            .times(1)
+            .returning(|_, _, _, _| Ok(vec![User::default()]));
+        let app = build_router(test_state(mock_db));
+        let req = Request::builder()
+            .uri("/users?with_total=false")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(body.get("total").is_none());
+        assert_eq!(body["data"].as_array().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_list_users_handler_with_total() {
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_find_many::<User>()
+            .times(1)
+            .returning(|_, _, _, _| Ok(vec![User::default()]));
+        mock_db
+            .expect_count_documents()
+            .times(1)
+            .returning(|_, _, _, _| Ok(7));
+        let app = build_router(test_state(mock_db));
+        let req = Request::builder()
+            .uri("/users?with_total=true")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["total"], 7);
+    }
+
+    #[tokio::test]
+    async fn test_list_users_handler_no_results() {
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_find_many::<User>()
+            .times(1)
+            .returning(|_, _, _, _| Ok(Vec::new()));
+        mock_db
+            .expect_count_documents()
+            .times(1)
+            .returning(|_, _, _, _| Ok(0));
+        let app = build_router(test_state(mock_db));
+        let req = Request::builder()
+            .uri("/users")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body, json!({"success": true, "data": [], "total": 0}));
+    }
+
+    #[tokio::test]
+    async fn test_user_facets_handler() {
+        let user = User {
+            id: 9,
+            name: "Sibaprasad".to_string(),
+            phone: "56565656".to_string(),
+            is_active: true,
+            ..Default::default()
+        };
+        let stored = to_document(&user).unwrap();
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_aggregate()
+            .with(
+                eq(DB_NAME),
+                eq("users"),
+                eq(vec![
+                    doc! {"$match": live(doc! {})},
+                    doc! {
+                        "$facet": {
+                            "items": [{"$sort": {"id": -1}}, {"$skip": 0_i64}, {"$limit": 1_i64}],
+                            "by_active": [{"$group": {"_id": "$isActive", "count": {"$sum": 1}}}],
+                        }
+                    },
+                ]),
+                always(),
+            )
+            .times(1)
+            .returning(move |_, _, _, _| {
+                Ok(vec![doc! {
+                    "items": [stored.clone()],
+                    "by_active": [{"_id": true, "count": 2}, {"_id": false, "count": 1}],
+                }])
+            });
+        let app = build_router(test_state(mock_db));
+        let req = Request::builder()
+            .uri("/user/facets?limit=1&sort=-id")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["data"]["counts"], json!({"active": 2, "inactive": 1}));
+        assert_eq!(body["data"]["items"][0]["id"], 9);
+        assert_eq!(
+            body["data"]["items"][0]["display_name"],
+            "Sibaprasad (****5656)"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_estimate_user_count_handler() {
+        let mut mock_db = AppDatabase::default();
+        mock_db.expect_count_documents().times(0);
+        mock_db
+            .expect_estimated_document_count()
+            .with(eq(DB_NAME), eq("users"))
+            .times(1)
+            .returning(|_, _| Ok(1234));
+        let app = build_router(test_state(mock_db));
+        let req = Request::builder()
+            .uri("/user/count/estimate")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            json!({"success": true, "data": {"estimated_count": 1234}})
+        );
+    }
+
+    fn bulk_update_request(payload: serde_json::Value) -> Request<Body> {
+        Request::builder()
+            .method("PATCH")
+            .uri("/users")
+            .header("Content-Type", "application/json")
+            .header("Authorization", "Bearer s3cret")
+            .body(Body::from(payload.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_bulk_update_users_handler() {
+        let mut mock_db = AppDatabase::default();
+        let filter = doc! {"isActive": false, "id": {"$gte": 100_i32}};
+        mock_db
+            .expect_find_many::<User>()
+            .with(eq(DB_NAME), eq("users"), eq(Some(live(filter))), always())
+            .times(1)
+            .returning(|_, _, _, _| {
+                Ok(vec![User {
+                    id: 100,
+                    ..Default::default()
+                }])
+            });
+        mock_db
+            .expect_find_many::<User>()
+            .with(
+                eq(DB_NAME),
+                eq("users"),
+                eq(Some(live(doc! {"id": {"$in": [100_i64]}}))),
+                always(),
+            )
+            .times(1)
+            .returning(|_, _, _, _| {
+                Ok(vec![User {
+                    id: 100,
+                    is_active: true,
+                    ..Default::default()
+                }])
+            });
+        mock_db
+            .expect_insert_one::<AuditEntry>()
+            .withf(|_, coll, entry: &AuditEntry, _| {
+                coll == AUDIT_COLLECTION
+                    && entry.user_id == 100
+                    && entry.operation == AuditOperation::Update
+                    && entry.old.as_ref().is_some_and(|user| !user.is_active)
+                    && entry.new.as_ref().is_some_and(|user| user.is_active)
+            })
+            .times(1)
+            .returning(|_, _, _, _| {
+                Ok(InsertOneResult {
+                    inserted_id: String::new(),
+                })
+            });
+        mock_db
+            .expect_update_many()
+            .with(
+                eq(DB_NAME),
+                eq("users"),
+                eq(live(doc! {"isActive": false, "id": {"$gte": 100_i32}})),
+                eq(doc! {"$set": {"isActive": true, "updated_at": NOW}}),
+                always(),
+            )
+            .times(1)
+            .returning(|_, _, _, _, _| {
+                Ok(UpdateResult {
+                    matched_count: 5,
+                    modified_count: 4,
+                })
+            });
+        let app = build_router(admin_state(mock_db));
+        let req = bulk_update_request(json!({
+            "filter": {"isActive": false, "id": {"$gte": 100}},
+            "update": {"isActive": true},
+        }));
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            json!({"success": true, "data": {"matched_count": 5, "modified_count": 4}})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bulk_update_users_handler_rejects_dangerous_operators() {
+        let payloads = [
+            json!({"filter": {"$where": "sleep(1000)"}, "update": {"isActive": true}}),
+            json!({"filter": {"name": {"$regex": ".*"}}, "update": {"isActive": true}}),
+            json!({"filter": {"id": 1}, "update": {"$unset": {"name": ""}}}),
+        ];
+        for payload in payloads {
+            let mut mock_db = AppDatabase::default();
+            mock_db.expect_update_many().times(0);
+            let app = build_router(admin_state(mock_db));
+            let res = app.oneshot(bulk_update_request(payload)).await.unwrap();
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bulk_update_users_handler_rejects_phone_filter() {
+        let mut mock_db = AppDatabase::default();
+        mock_db.expect_find_many::<User>().times(0);
+        mock_db.expect_update_many().times(0);
+        let app = build_router(admin_state(mock_db));
+        let payload = json!({"filter": {"phone": "56565656"}, "update": {"isActive": true}});
+        let res = app.oneshot(bulk_update_request(payload)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["message"], "filter key `phone` is not filterable");
+    }
+
+    fn delete_users_request(ids: serde_json::Value) -> Request<Body> {
+        Request::builder()
+            .method("POST")
+            .uri("/users/delete")
+            .header("Content-Type", "application/json")
+            .header("Authorization", "Bearer s3cret")
+            .body(Body::from(ids.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_delete_users_handler() {
+        let mut mock_db = AppDatabase::default();
+        let found = [3, 4].map(|id| User {
+            id,
+            ..Default::default()
+        });
+        mock_db
+            .expect_find_many::<User>()
+            .with(
+                eq(DB_NAME),
+                eq("users"),
+                eq(Some(live(doc! {"id": {"$in": [3_i64, 4_i64, 9_i64]}}))),
+                always(),
+            )
+            .times(1)
+            .returning(move |_, _, _, _| Ok(found.to_vec()));
+        mock_db
+            .expect_insert_one::<AuditEntry>()
+            .withf(|_, coll, entry: &AuditEntry, _| {
+                coll == AUDIT_COLLECTION
+                    && entry.operation == AuditOperation::Delete
+                    && entry.old.as_ref().map(|user| user.id) == Some(entry.user_id)
+                    && entry.new.is_none()
+            })
+            .times(2)
+            .returning(|_, _, _, _| {
+                Ok(InsertOneResult {
+                    inserted_id: String::new(),
+                })
+            });
+        mock_db
+            .expect_delete_many()
+            .with(
+                eq(DB_NAME),
+                eq("users"),
+                eq(live(doc! {"id": {"$in": [3_i64, 4_i64, 9_i64]}})),
+                always(),
+            )
+            .times(1)
+            .returning(|_, _, _, _| Ok(2));
+        let app = build_router(admin_state(mock_db));
+        let res = app
+            .oneshot(delete_users_request(json!([3, 4, 9])))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body, json!({"success": true, "data": {"deleted_count": 2}}));
+    }
+
+    #[tokio::test]
+    async fn test_delete_users_handler_rejects_empty_and_oversized_lists() {
+        let too_many: Vec<i64> = (1..=MAX_BATCH_IDS as i64 + 1).collect();
+        for ids in [json!([]), json!(too_many)] {
+            let mut mock_db = AppDatabase::default();
+            mock_db.expect_delete_many().times(0);
+            let app = build_router(admin_state(mock_db));
+            let res = app.oneshot(delete_users_request(ids)).await.unwrap();
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    async fn next_chunk(body: &mut axum::body::BoxBody) -> Option<String> {
+        use hyper::body::HttpBody;
+
+        let chunk = body.data().await?;
+        Some(String::from_utf8(chunk.unwrap().to_vec()).unwrap())
+    }
+
+    // the cursor is a channel, so the test controls when each user arrives
+    // and can check it is written out before the next one exists
+    #[tokio::test]
+    async fn test_export_users_handler_streams_users() {
+        use futures::StreamExt;
+
+        let (cursor, users) = futures::channel::mpsc::unbounded::<mongodb::error::Result<User>>();
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_find_stream::<User>()
+            .with(eq(DB_NAME), eq("users"), eq(Some(live(doc! {}))), always())
+            .times(1)
+            .return_once(move |_, _, _, _| Ok(users.boxed()));
+        let app = build_router(test_state(mock_db));
+        let req = Request::builder()
+            .uri("/users/export")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let mut body = res.into_body();
+        assert_eq!(
+            next_chunk(&mut body).await.unwrap(),
+            r#"{"success":true,"data":["#
+        );
+        for id in 1..=2 {
+            let user = User {
+                id,
+                ..Default::default()
+            };
+            cursor.unbounded_send(Ok(user.clone())).unwrap();
+            let separator = if id == 1 { "" } else { "," };
+            let user = UserResponse::from(user);
+            let expected = format!("{separator}{}", serde_json::to_string(&user).unwrap());
+            assert_eq!(next_chunk(&mut body).await.unwrap(), expected);
+        }
+        drop(cursor);
+        assert_eq!(next_chunk(&mut body).await.unwrap(), "]}");
+        assert!(next_chunk(&mut body).await.is_none());
+    }
+
+    // the cursor yields two users and then stalls past the deadline
+    #[tokio::test]
+    async fn test_list_users_handler_partial_on_slow_cursor() {
+        use futures::StreamExt;
+
+        let (cursor, users) = futures::channel::mpsc::unbounded::<mongodb::error::Result<User>>();
+        for id in 1..=2 {
+            let user = User {
+                id,
+                ..Default::default()
+            };
+            cursor.unbounded_send(Ok(user)).unwrap();
+        }
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_find_stream::<User>()
+            .times(1)
+            .return_once(move |_, _, _, _| Ok(users.boxed()));
+        mock_db.expect_find_many::<User>().times(0);
+        let state = AppState {
+            config: Arc::new(Config {
+                partial_list_after: Some(Duration::from_millis(50)),
+                ..Default::default()
+            }),
+            ..test_state(mock_db)
+        };
+        let app = build_router(state);
+        let req = Request::builder()
+            .uri("/users?with_total=false")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["partial"], true);
+        let ids: Vec<i64> = body["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|user| user["id"].as_i64().unwrap())
+            .collect();
+        assert_eq!(ids, vec![1, 2]);
+        drop(cursor);
+    }
+
+    #[tokio::test]
+    async fn test_list_users_handler_invalid_date() {
+        let mut mock_db = AppDatabase::default();
+        mock_db.expect_find_many::<User>().times(0);
+        let app = build_router(test_state(mock_db));
+        let req = Request::builder()
+            .uri("/users?created_after=yesterday")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body["message"],
+            "created_after must be an ISO-8601 date, got `yesterday`"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_create_user_handler_ignores_client_created_at() {
+        let user = User {
+            id: 1,
+            name: "Sibaprasad".to_string(),
+            phone: "56565656".to_string(),
+            created_at: Some(DateTime::from_millis(0)),
+            ..Default::default()
+        };
+        let is_stamped = function(|x: &User| x.created_at == Some(NOW));
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_insert_one::<User>()
+            .with(eq(DB_NAME), eq("users"), is_stamped, always())
+            .times(1)
+            .returning(|_, _, _, _| {
+                Ok(InsertOneResult {
+                    inserted_id: String::new(),
+                })
+            });
+        allow_audit(&mut mock_db);
+        let app = build_router(test_state(mock_db));
+        let req = Request::builder()
+            .method("POST")
+            .uri("/user")
+            .header("Content-Type", "application/json")
+            .body(Body::from(serde_json::to_string(&user).unwrap()))
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn test_create_user_handler_reports_all_invalid_fields() {
+        let mut mock_db = AppDatabase::default();
+        mock_db.expect_insert_one::<User>().times(0);
+        let app = build_router(test_state(mock_db));
+        let payload = json!({
+            "id": 0,
+            "name": "",
+            "phone": "12ab",
+            "email": "not-an-email",
+            "isActive": true,
+        });
+        let req = Request::builder()
+            .method("POST")
+            .uri("/user")
+            .header("Content-Type", "application/json")
+            .body(Body::from(payload.to_string()))
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["success"], false);
+        let fields: Vec<&str> = body["errors"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| e["field"].as_str().unwrap())
+            .collect();
+        assert_eq!(fields, vec!["id", "name", "phone", "email"]);
+    }
+
+    async fn json_body(res: Response) -> serde_json::Value {
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    // a balance sent as a decimal string comes back as the same string from
+    // every endpoint returning the user
+    #[tokio::test]
+    async fn test_balance_round_trips_as_decimal_string() {
+        use futures::StreamExt;
+
+        let stored = Arc::new(std::sync::Mutex::new(None::<User>));
+        let mut mock_db = AppDatabase::default();
+        let written = stored.clone();
+        mock_db
+            .expect_insert_one::<User>()
+            .withf(|_, _, user: &User, _| {
+                user.balance == Some(crate::test_support::decimal(false, 1250, -2))
+            })
+            .times(1)
+            .returning(move |_, _, user, _| {
+                *written.lock().unwrap() = Some(user.clone());
+                Ok(InsertOneResult {
+                    inserted_id: String::new(),
+                })
+            });
+        let read = stored.clone();
+        mock_db
+            .expect_find_one::<User>()
+            .returning(move |_, _, _, _| Ok(read.lock().unwrap().clone()));
+        mock_db
+            .expect_update_one()
+            .times(1)
+            .returning(|_, _, _, _, _| {
+                Ok(UpdateResult {
+                    matched_count: 1,
+                    modified_count: 1,
+                })
+            });
+        mock_db
+            .expect_insert_or_get::<User>()
+            .times(1)
+            .returning(|_, _, _, _| Ok(true));
+        let exported = stored.clone();
+        mock_db
+            .expect_find_stream::<User>()
+            .times(1)
+            .returning(move |_, _, _, _| {
+                let user = exported.lock().unwrap().clone().unwrap();
+                Ok(futures::stream::iter([Ok(user)]).boxed())
+            });
+        allow_audit(&mut mock_db);
+        let app = build_router(test_state(mock_db));
+
+        let user = json!({
+            "id": 42,
+            "name": "Sibaprasad",
+            "phone": "56565656",
+            "isActive": true,
+            "balance": "12.50",
+        });
+        let req = Request::builder()
+            .method("POST")
+            .uri("/user")
+            .header("Content-Type", "application/json")
+            .body(Body::from(user.to_string()))
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert!(res.status().is_success(), "{}", res.status());
+
+        let patch = patch_user_request(42, json!({"name": "Sibu"}));
+        let res = app.clone().oneshot(patch).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(json_body(res).await["data"]["balance"], "12.50");
+
+        let clone = Request::builder()
+            .method("POST")
+            .uri("/user/42/clone")
+            .header("Content-Type", "application/json")
+            .body(Body::from(json!({"id": 43}).to_string()))
+            .unwrap();
+        let res = app.clone().oneshot(clone).await.unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(json_body(res).await["data"]["balance"], "12.50");
+
+        let export = Request::builder()
+            .uri("/users/export")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(export).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(json_body(res).await["data"][0]["balance"], "12.50");
+    }
+
+    fn patch_user_request(id: i64, patch: serde_json::Value) -> Request<Body> {
+        Request::builder()
+            .method("PATCH")
+            .uri(format!("/user/{id}"))
+            .header("Content-Type", "application/json")
+            .body(Body::from(patch.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_patch_user_handler_writes_audit_entry() {
+        let old = User {
+            id: 3,
+            name: "Sibaprasad".to_string(),
+            phone: "56565656".to_string(),
+            ..Default::default()
+        };
+        let new = User {
+            name: "Sibu".to_string(),
+            updated_at: Some(NOW),
+            ..old.clone()
+        };
+        let mut mock_db = AppDatabase::default();
+        let stored = old.clone();
+        mock_db
+            .expect_find_one::<User>()
+            .times(1)
+            .returning(move |_, _, _, _| Ok(Some(stored.clone())));
+        mock_db
+            .expect_update_one()
+            .with(
+                eq(DB_NAME),
+                eq("users"),
+                eq(live(doc! {"id": 3_i64, "$or": [{"name": {"$ne": "Sibu"}}]})),
+                eq(doc! {"$set": {"updated_at": NOW, "name": "Sibu"}}),
+                always(),
+            )
+            .times(1)
+            .returning(|_, _, _, _, _| {
+                Ok(UpdateResult {
+                    matched_count: 1,
+                    modified_count: 1,
+                })
+            });
+        let entry = AuditEntry {
+            user_id: 3,
+            operation: AuditOperation::Update,
+            old: Some(old),
+            new: Some(new.clone()),
+            timestamp: NOW,
+        };
+        mock_db
+            .expect_insert_one::<AuditEntry>()
+            .with(eq(DB_NAME), eq(AUDIT_COLLECTION), eq(entry), always())
+            .times(1)
+            .returning(|_, _, _, _| {
+                Ok(InsertOneResult {
+                    inserted_id: String::new(),
+                })
+            });
+        let app = build_router(test_state(mock_db));
+        let res = app
+            .oneshot(patch_user_request(3, json!({"name": "Sibu"})))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["data"]["name"], "Sibu");
+        assert_eq!(body["data"]["changed"], true);
+    }
+
+    fn merge_patch_request(patch: serde_json::Value) -> Request<Body> {
+        Request::builder()
+            .method("PATCH")
+            .uri("/user/3")
+            .header("Content-Type", MERGE_PATCH_CONTENT_TYPE)
+            .body(Body::from(patch.to_string()))
+            .unwrap()
+    }
+
+    fn user_with_email() -> User {
+        User {
+            id: 3,
+            name: "Sibaprasad".to_string(),
+            phone: "56565656".to_string(),
+            email: Some("sibu@example.com".to_string()),
+            ..Default::default()
+        }
+    }
+
+    // the expected update comes with the conditions its filter requires,
+    // one of them must hold for the write to change anything
+    fn merge_patch_mock(expected_update: Option<(Vec<Document>, Document)>) -> AppDatabase {
+        let stored = user_with_email();
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_find_one::<User>()
+            .times(1)
+            .returning(move |_, _, _, _| Ok(Some(stored.clone())));
+        match expected_update {
+            Some((differs, update)) => {
+                mock_db
+                    .expect_update_one()
+                    .with(
+                        eq(DB_NAME),
+                        eq("users"),
+                        eq(live(doc! {"id": 3_i64, "$or": differs})),
+                        eq(update),
+                        always(),
+                    )
+                    .times(1)
+                    .returning(|_, _, _, _, _| {
+                        Ok(UpdateResult {
+                            matched_count: 1,
+                            modified_count: 1,
+                        })
+                    });
+                allow_audit(&mut mock_db);
+            }
+            None => {
+                mock_db.expect_update_one().times(0);
+            }
+        }
+        mock_db
+    }
+
+    #[tokio::test]
+    async fn test_merge_patch_sets_field() {
+        let differs = vec![doc! {"phone": {"$ne": "+9156565656"}}];
+        let update = doc! {"$set": {"updated_at": NOW, "phone": "+9156565656"}};
+        let app = build_router(test_state(merge_patch_mock(Some((differs, update)))));
+        let res = app
+            .oneshot(merge_patch_request(json!({"phone": "+9156565656"})))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_merge_patch_null_unsets_email() {
+        let differs = vec![doc! {"email": {"$exists": true}}];
+        let update = doc! {
+            "$set": {"updated_at": NOW},
+            "$unset": {"email": ""},
+        };
+        let app = build_router(test_state(merge_patch_mock(Some((differs, update)))));
+        let res = app
+            .oneshot(merge_patch_request(json!({"email": null})))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(body["data"].get("email").is_none());
+    }
+
+    // the stored user already holds the patched values when the write runs,
+    // so nothing is modified and no audit entry is written
+    #[tokio::test]
+    async fn test_patch_user_handler_unchanged_by_concurrent_write() {
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_find_one::<User>()
+            .times(1)
+            .returning(|_, _, _, _| Ok(Some(user_with_email())));
+        mock_db
+            .expect_update_one()
+            .times(1)
+            .returning(|_, _, _, _, _| {
+                Ok(UpdateResult {
+                    matched_count: 0,
+                    modified_count: 0,
+                })
+            });
+        mock_db
+            .expect_count_documents()
+            .with(
+                eq(DB_NAME),
+                eq("users"),
+                eq(Some(live(doc! {"id": 3_i64}))),
+                always(),
+            )
+            .times(1)
+            .returning(|_, _, _, _| Ok(1));
+        mock_db.expect_insert_one::<AuditEntry>().times(0);
+        let app = build_router(test_state(mock_db));
+        let res = app
+            .oneshot(patch_user_request(3, json!({"name": "Sibu"})))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["data"]["changed"], false);
+        assert_eq!(body["data"]["display_name"], "Sibu (****5656)");
+        assert!(body["data"].get("updated_at").is_none());
+    }
+
+    // a plain JSON patch ignores the `null`, the other fields are left to
+    // any concurrent write
+    #[tokio::test]
+    async fn test_patch_user_handler_sets_only_present_fields() {
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_find_one::<User>()
+            .times(1)
+            .returning(|_, _, _, _| Ok(Some(user_with_email())));
+        mock_db
+            .expect_update_one()
+            .with(
+                eq(DB_NAME),
+                eq("users"),
+                eq(live(doc! {
+                    "id": 3_i64,
+                    "$or": [{"name": {"$ne": "Sibu"}}, {"isActive": {"$ne": true}}],
+                })),
+                eq(doc! {"$set": {"updated_at": NOW, "name": "Sibu", "isActive": true}}),
+                always(),
+            )
+            .times(1)
+            .returning(|_, _, _, _, _| {
+                Ok(UpdateResult {
+                    matched_count: 1,
+                    modified_count: 1,
+                })
+            });
+        allow_audit(&mut mock_db);
+        let app = build_router(test_state(mock_db));
+        let patch = json!({"name": "Sibu", "isActive": true, "email": null});
+        let res = app.oneshot(patch_user_request(3, patch)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["data"]["email"], "sibu@example.com");
+    }
+
+    #[tokio::test]
+    async fn test_merge_patch_empty_is_noop() {
+        let app = build_router(test_state(merge_patch_mock(None)));
+        let res = app.oneshot(merge_patch_request(json!({}))).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["data"]["changed"], false);
+    }
+
+    #[tokio::test]
+    async fn test_merge_patch_cannot_remove_required_field() {
+        let app = build_router(test_state(merge_patch_mock(None)));
+        let res = app
+            .oneshot(merge_patch_request(json!({"name": null})))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn test_create_user_handler_dry_run() {
+        let mut mock_db = AppDatabase::default();
+        mock_db.expect_insert_one::<User>().times(0);
+        mock_db.expect_insert_one::<AuditEntry>().times(0);
+        let app = build_router(test_state(mock_db));
+        let user = User {
+            id: 9,
+            name: "Sibaprasad".to_string(),
+            phone: "56565656".to_string(),
+            ..Default::default()
+        };
+        let req = Request::builder()
+            .method("POST")
+            .uri("/user?dry_run=true")
+            .header("Content-Type", "application/json")
+            .body(Body::from(serde_json::to_string(&user).unwrap()))
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["data"]["dry_run"], true);
+        assert_eq!(body["data"]["operation"], "insert_one");
+        assert_eq!(body["data"]["collection"], "users");
+        assert_eq!(body["data"]["document"]["id"], 9);
+    }
+
+    #[tokio::test]
+    async fn test_patch_user_handler_dry_run() {
+        let mut mock_db = merge_patch_mock(None);
+        mock_db.expect_insert_one::<AuditEntry>().times(0);
+        let app = build_router(test_state(mock_db));
+        let req = Request::builder()
+            .method("PATCH")
+            .uri("/user/3")
+            .header("Content-Type", "application/json")
+            .header("X-Dry-Run", "true")
+            .body(Body::from(json!({"name": "Sibu"}).to_string()))
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["data"]["operation"], "update_one");
+        assert_eq!(body["data"]["filter"], json!({"id": 3}));
+        assert_eq!(body["data"]["document"]["$set"]["name"], "Sibu");
+    }
+
+    #[tokio::test]
+    async fn test_patch_user_handler_not_found() {
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_find_one::<User>()
+            .times(1)
+            .returning(|_, _, _, _| Ok(None));
+        mock_db.expect_update_one().times(0);
+        let app = build_router(test_state(mock_db));
+        let res = app
+            .oneshot(patch_user_request(3, json!({"name": "Sibu"})))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    fn create_request_with_extra_field() -> Request<Body> {
+        let payload = json!({
+            "id": 1,
+            "name": "Sibaprasad",
+            "phone": "56565656",
+            "isActive": true,
+            "nickname": "sibu",
+        });
+        Request::builder()
+            .method("POST")
+            .uri("/user")
+            .header("Content-Type", "application/json")
+            .body(Body::from(payload.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_create_user_handler_strict_rejects_unknown_field() {
+        let mut mock_db = AppDatabase::default();
+        mock_db.expect_insert_one::<User>().times(0);
+        let state = AppState {
+            config: Arc::new(Config {
+                strict_json: true,
+                ..Default::default()
+            }),
+            ..test_state(mock_db)
+        };
+        let app = build_router(state);
+        let res = app
+            .oneshot(create_request_with_extra_field())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            json!({"success": false, "errors": [
+                {"field": "nickname", "message": "unknown field"},
+            ]})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_create_user_handler_lenient_ignores_unknown_field() {
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_insert_one::<User>()
+            .times(1)
+            .returning(|_, _, _, _| {
+                Ok(InsertOneResult {
+                    inserted_id: String::new(),
+                })
+            });
+        allow_audit(&mut mock_db);
+        let app = build_router(test_state(mock_db));
+        let res = app
+            .oneshot(create_request_with_extra_field())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+    }
+
+    fn batch_user(id: i64) -> User {
+        User {
+            id,
+            name: "Sibaprasad".to_string(),
+            phone: "56565656".to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn create_users_request(users: &[User]) -> Request<Body> {
+        create_users_request_at("/users", users)
+    }
+
+    fn create_users_request_at(uri: &str, users: &[User]) -> Request<Body> {
+        Request::builder()
+            .method("POST")
+            .uri(uri)
+            .header("Content-Type", "application/json")
+            .body(Body::from(serde_json::to_string(users).unwrap()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_create_users_handler_partial_failure() {
+        let failure = mongodb::bson::from_document(doc! {
+            "writeErrors": [{
+                "index": 1,
+                "code": 11000,
+                "errmsg": "E11000 duplicate key error collection: myDB.users index: id_1",
+            }],
+        })
+        .unwrap();
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_insert_many::<User>()
+            .with(
+                eq(DB_NAME),
+                eq("users"),
+                function(|users: &[User]| {
+                    users.len() == 3 && users.iter().all(|u| u.created_at == Some(NOW))
+                }),
+                eq(false),
+            )
+            .times(1)
+            .returning(move |_, _, _, _| Err(ErrorKind::BulkWrite(Clone::clone(&failure)).into()));
+        mock_db
+            .expect_insert_one::<AuditEntry>()
+            .withf(|_, _, entry: &AuditEntry, _| entry.user_id != 2)
+            .times(2)
+            .returning(|_, _, _, _| {
+                Ok(InsertOneResult {
+                    inserted_id: String::new(),
+                })
+            });
+        let app = build_router(test_state(mock_db));
+        let users = [batch_user(1), batch_user(2), batch_user(3)];
+        let res = app.oneshot(create_users_request(&users)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::MULTI_STATUS);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body["data"],
+            json!([
+                {"index": 0, "id": 1, "success": true},
+                {
+                    "index": 1,
+                    "id": 2,
+                    "success": false,
+                    "message": "a user with this id already exists",
+                },
+                {"index": 2, "id": 3, "success": true},
+            ])
+        );
+    }
+
+    // nothing is written when any user of the batch is invalid
+    #[tokio::test]
+    async fn test_create_users_handler_strict_rejects_unknown_field_by_index() {
+        let mut mock_db = AppDatabase::default();
+        mock_db.expect_insert_many::<User>().times(0);
+        let state = AppState {
+            config: Arc::new(Config {
+                strict_json: true,
+                ..Default::default()
+            }),
+            ..test_state(mock_db)
+        };
+        let app = build_router(state);
+        let users = json!([
+            {"id": 1, "name": "Sibaprasad", "phone": "56565656", "isActive": true},
+            {"id": 2, "nmae": "Sibu", "phone": "12121212", "isActive": true},
+        ]);
+        let req = Request::builder()
+            .method("POST")
+            .uri("/users/batch")
+            .header("Content-Type", "application/json")
+            .body(Body::from(users.to_string()))
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            json!({"success": false, "errors": [
+                {"field": "1.nmae", "message": "unknown field"},
+            ]})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_create_users_handler_rejects_empty_list() {
+        for uri in ["/users", "/users/batch"] {
+            let mut mock_db = AppDatabase::default();
+            mock_db.expect_insert_many::<User>().times(0);
+            let app = build_router(test_state(mock_db));
+            let req = Request::builder()
+                .method("POST")
+                .uri(uri)
+                .header("Content-Type", "application/json")
+                .body(Body::from("[]"))
+                .unwrap();
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{uri}");
+            let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["message"], "no users to create", "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_create_users_handler_reports_invalid_users_by_index() {
+        for uri in ["/users", "/users/batch"] {
+            let mut mock_db = AppDatabase::default();
+            mock_db.expect_insert_many::<User>().times(0);
+            let app = build_router(test_state(mock_db));
+            let users = [
+                batch_user(1),
+                User {
+                    phone: "1".to_string(),
+                    ..batch_user(2)
+                },
+            ];
+            let res = app
+                .oneshot(create_users_request_at(uri, &users))
+                .await
+                .unwrap();
+            assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY, "{uri}");
+            let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["errors"][0]["field"], "1.phone");
+        }
+    }
+
+    fn touch_mock(matched_count: u64) -> AppDatabase {
+        let mut mock_db = AppDatabase::default();
+        allow_user_reads(
+            &mut mock_db,
+            User {
+                id: 5,
+                ..Default::default()
+            },
+        );
+        allow_audit(&mut mock_db);
+        mock_db
+            .expect_update_one()
+            .with(
+                eq(DB_NAME),
+                eq("users"),
+                eq(live(doc! {"id": 5_i64})),
+                eq(doc! {"$set": {"updated_at": NOW}}),
+                always(),
+            )
+            .times(1)
+            .returning(move |_, _, _, _, _| {
+                Ok(UpdateResult {
+                    matched_count,
+                    modified_count: matched_count,
+                })
+            });
+        mock_db
+    }
+
+    fn touch_request() -> Request<Body> {
+        Request::builder()
+            .method("POST")
+            .uri("/user/5/touch")
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_touch_user_handler() {
+        let app = build_router(test_state(touch_mock(1)));
+        let res = app.oneshot(touch_request()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_touch_user_handler_missing_user() {
+        let app = build_router(test_state(touch_mock(0)));
+        let res = app.oneshot(touch_request()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    fn restore_mock(matched_count: u64) -> AppDatabase {
+        let mut mock_db = AppDatabase::default();
+        allow_user_reads(
+            &mut mock_db,
+            User {
+                id: 5,
+                ..Default::default()
+            },
+        );
+        allow_audit(&mut mock_db);
+        mock_db
+            .expect_update_one()
+            .with(
+                eq(DB_NAME),
+                eq("users"),
+                eq(doc! {"id": 5_i64, "deleted_at": {"$ne": null}}),
+                eq(doc! {"$unset": {"deleted_at": ""}, "$set": {"updated_at": NOW}}),
+                always(),
+            )
+            .times(1)
+            .returning(move |_, _, _, _, _| {
+                Ok(UpdateResult {
+                    matched_count,
+                    modified_count: matched_count,
+                })
+            });
+        mock_db
+    }
+
+    fn restore_request() -> Request<Body> {
+        Request::builder()
+            .method("POST")
+            .uri("/user/5/restore")
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_restore_user_handler() {
+        let app = build_router(test_state(restore_mock(1)));
+        let res = app.oneshot(restore_request()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["data"]["restored"], true);
+    }
+
+    // a user which is not deleted and a missing one both match nothing
+    #[tokio::test]
+    async fn test_restore_user_handler_not_deleted() {
+        let app = build_router(test_state(restore_mock(0)));
+        let res = app.oneshot(restore_request()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    fn set_fields_request(operations: serde_json::Value) -> Request<Body> {
+        Request::builder()
+            .method("POST")
+            .uri("/user/5/set-fields")
+            .header("Content-Type", "application/json")
+            .body(Body::from(operations.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_set_user_fields_handler() {
+        let mut mock_db = AppDatabase::default();
+        allow_user_reads(
+            &mut mock_db,
+            User {
+                id: 5,
+                ..Default::default()
+            },
+        );
+        allow_audit(&mut mock_db);
+        mock_db
+            .expect_update_one()
+            .with(
+                eq(DB_NAME),
+                eq("users"),
+                eq(live(doc! {"id": 5_i64})),
+                eq(doc! {
+                    "$set": {"phone": "12345678", "updated_at": NOW},
+                    "$unset": {"email": ""},
+                }),
+                always(),
+            )
+            .times(1)
+            .returning(|_, _, _, _, _| {
+                Ok(UpdateResult {
+                    matched_count: 1,
+                    modified_count: 1,
+                })
+            });
+        let app = build_router(test_state(mock_db));
+        let operations = json!([
+            {"op": "set", "field": "phone", "value": "12345678"},
+            {"op": "unset", "field": "email"},
+        ]);
+        let res = app.oneshot(set_fields_request(operations)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_set_user_fields_handler_rejects_fields_and_ops() {
+        let rejected = [
+            json!([{"op": "set", "field": "id", "value": 6}]),
+            json!([{"op": "unset", "field": "name"}]),
+            json!([{"op": "inc", "field": "balance", "value": 1}]),
+            json!([{"op": "set", "field": "phone", "value": "abc"}]),
+            json!([
+                {"op": "set", "field": "email", "value": "sibu@example.com"},
+                {"op": "unset", "field": "email"},
+            ]),
+            json!([]),
+        ];
+        for operations in rejected {
+            let mut mock_db = AppDatabase::default();
+            mock_db.expect_update_one().times(0);
+            let app = build_router(test_state(mock_db));
+            let res = app
+                .oneshot(set_fields_request(operations.clone()))
+                .await
+                .unwrap();
+            assert!(
+                res.status().is_client_error(),
+                "{operations} got {}",
+                res.status()
+            );
+        }
+    }
+
+    fn element_request(field: &str) -> Request<Body> {
+        Request::builder()
+            .method("PATCH")
+            .uri(format!("/user/5/elements/{field}"))
+            .header("Content-Type", "application/json")
+            .body(Body::from(
+                json!({"matches": "editor", "value": "admin"}).to_string(),
+            ))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_update_user_element_handler_passes_array_filters() {
+        let mut mock_db = AppDatabase::default();
+        allow_user_reads(
+            &mut mock_db,
+            User {
+                id: 5,
+                ..Default::default()
+            },
+        );
+        allow_audit(&mut mock_db);
+        mock_db
+            .expect_update_one()
+            .with(
+                eq(DB_NAME),
+                eq("users"),
+                eq(live(doc! {"id": 5_i64, "roles": "editor"})),
+                eq(doc! {"$set": {"roles.$[element]": "admin", "updated_at": NOW}}),
+                function(|options: &Option<UpdateOptions>| {
+                    options.as_ref().and_then(|o| o.array_filters.clone())
+                        == Some(vec![doc! {"element": "editor"}])
+                }),
+            )
+            .times(1)
+            .returning(|_, _, _, _, _| {
+                Ok(UpdateResult {
+                    matched_count: 1,
+                    modified_count: 1,
+                })
+            });
+        let app = build_router(test_state(mock_db));
+        let res = app.oneshot(element_request("roles")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_update_user_element_handler_rejects_operators() {
+        let rejected = [
+            json!({"$ne": null}),
+            json!({"name": {"$regex": ".*"}}),
+            json!([{"$where": "true"}]),
+        ];
+        for matches in rejected {
+            let mut mock_db = AppDatabase::default();
+            mock_db.expect_update_one().times(0);
+            let app = build_router(test_state(mock_db));
+            let req = Request::builder()
+                .method("PATCH")
+                .uri("/user/5/elements/roles")
+                .header("Content-Type", "application/json")
+                .body(Body::from(
+                    json!({"matches": matches, "value": "admin"}).to_string(),
+                ))
+                .unwrap();
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{matches}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_update_user_element_handler_rejects_scalar_field() {
+        let mut mock_db = AppDatabase::default();
+        mock_db.expect_update_one().times(0);
+        let app = build_router(test_state(mock_db));
+        let res = app.oneshot(element_request("name")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    fn clone_request(source_id: i64, payload: serde_json::Value) -> Request<Body> {
+        Request::builder()
+            .method("POST")
+            .uri(format!("/user/{source_id}/clone"))
+            .header("Content-Type", "application/json")
+            .body(Body::from(payload.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_clone_user_handler() {
+        let source = User {
+            id: 3,
+            name: "Sibaprasad".to_string(),
+            phone: "56565656".to_string(),
+            created_at: Some(DateTime::from_millis(0)),
+            ..Default::default()
+        };
+        let highest = User {
+            id: 41,
+            ..source.clone()
+        };
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_find_one::<User>()
+            .with(
+                eq(DB_NAME),
+                eq("users"),
+                eq(Some(live(doc! {"id": 3_i64}))),
+                always(),
+            )
+            .times(1)
+            .returning(move |_, _, _, _| Ok(Some(source.clone())));
+        mock_db
+            .expect_find_many::<User>()
+            .times(1)
+            .returning(move |_, _, _, _| Ok(vec![highest.clone()]));
+        mock_db
+            .expect_insert_or_get::<User>()
+            .with(
+                eq(DB_NAME),
+                eq("users"),
+                eq(doc! {"id": 42_i64}),
+                function(|x: &User| x.id == 42 && x.created_at == Some(NOW)),
+            )
+            .times(1)
+            .returning(|_, _, _, _| Ok(true));
+        allow_audit(&mut mock_db);
+        let app = build_router(test_state(mock_db));
+        let res = app.oneshot(clone_request(3, json!({}))).await.unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["data"]["id"], 42);
+        assert_eq!(body["data"]["name"], "Sibaprasad");
+        assert_eq!(body["data"]["display_name"], "Sibaprasad (****5656)");
+    }
+
+    fn merge_request(id: i64, source_id: i64) -> Request<Body> {
+        Request::builder()
+            .method("POST")
+            .uri(format!("/user/{id}/merge"))
+            .header("Content-Type", "application/json")
+            .body(Body::from(json!({ "source_id": source_id }).to_string()))
+            .unwrap()
+    }
+
+    fn expect_get(mock_db: &mut AppDatabase, user: Option<User>, filter: Document) {
+        mock_db
+            .expect_find_one::<User>()
+            .with(eq(DB_NAME), eq("users"), eq(Some(filter)), always())
+            .times(1)
+            .returning(move |_, _, _, _| Ok(user.clone()));
+    }
+
+    #[tokio::test]
+    async fn test_merge_user_handler() {
+        let target = User {
+            id: 3,
+            name: "Sibaprasad".to_string(),
+            phone: "56565656".to_string(),
+            ..Default::default()
+        };
+        let source = User {
+            id: 4,
+            name: "Sibu".to_string(),
+            phone: "12121212".to_string(),
+            email: Some("sibu@example.com".to_string()),
+            ..Default::default()
+        };
+        let mut mock_db = AppDatabase::default();
+        expect_get(&mut mock_db, Some(target), live(doc! {"id": 3_i64}));
+        expect_get(&mut mock_db, Some(source), doc! {"id": 4_i64});
+        mock_db
+            .expect_update_in_transaction()
+            .with(
+                eq(DB_NAME),
+                eq("users"),
+                eq(vec![
+                    (
+                        live(doc! {"id": 3_i64}),
+                        doc! {"$set": {"updated_at": NOW, "email": "sibu@example.com"}},
+                    ),
+                    (
+                        live(doc! {"id": 4_i64}),
+                        doc! {"$set": {"deleted_at": NOW, "updated_at": NOW}},
+                    ),
+                ]),
+            )
+            .times(1)
+            .returning(|_, _, _| {
+                let result = UpdateResult {
+                    matched_count: 1,
+                    modified_count: 1,
+                };
+                Ok(vec![result.clone(), result])
+            });
+        allow_audit(&mut mock_db);
+        let app = build_router(test_state(mock_db));
+        let res = app.oneshot(merge_request(3, 4)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["data"]["id"], 3);
+        assert_eq!(body["data"]["name"], "Sibaprasad");
+        assert_eq!(body["data"]["email"], "sibu@example.com");
+    }
+
+    #[tokio::test]
+    async fn test_merge_user_handler_missing_source() {
+        let target = User {
+            id: 3,
+            ..Default::default()
+        };
+        let mut mock_db = AppDatabase::default();
+        expect_get(&mut mock_db, Some(target), live(doc! {"id": 3_i64}));
+        expect_get(&mut mock_db, None, doc! {"id": 4_i64});
+        mock_db.expect_update_in_transaction().times(0);
+        let app = build_router(test_state(mock_db));
+        let res = app.oneshot(merge_request(3, 4)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["message"], "source user not found");
+    }
+
+    // the source is read with the deleted users, so a second merge of it is
+    // told apart from a missing source
+    #[tokio::test]
+    async fn test_merge_user_handler_deleted_source() {
+        let target = User {
+            id: 3,
+            ..Default::default()
+        };
+        let source = User {
+            id: 4,
+            deleted_at: Some(NOW),
+            ..Default::default()
+        };
+        let mut mock_db = AppDatabase::default();
+        expect_get(&mut mock_db, Some(target), live(doc! {"id": 3_i64}));
+        expect_get(&mut mock_db, Some(source), doc! {"id": 4_i64});
+        mock_db.expect_update_in_transaction().times(0);
+        let app = build_router(test_state(mock_db));
+        let res = app.oneshot(merge_request(3, 4)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn test_clone_user_handler_missing_source() {
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_find_one::<User>()
+            .times(1)
+            .returning(|_, _, _, _| Ok(None));
+        mock_db.expect_insert_or_get::<User>().times(0);
+        let app = build_router(test_state(mock_db));
+        let res = app
+            .oneshot(clone_request(3, json!({"id": 50})))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_clone_user_handler_taken_id() {
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_find_one::<User>()
+            .times(1)
+            .returning(|_, _, _, _| {
+                Ok(Some(User {
+                    id: 3,
+                    name: "Sibaprasad".to_string(),
+                    phone: "56565656".to_string(),
+                    ..Default::default()
+                }))
+            });
+        mock_db
+            .expect_insert_or_get::<User>()
+            .times(1)
+            .returning(|_, _, _, _| Ok(false));
+        let app = build_router(test_state(mock_db));
+        let res = app
+            .oneshot(clone_request(3, json!({"id": 50})))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+    }
+
+    fn get_user_by_id_mock() -> AppDatabase {
+        let user = User {
+            id: 5,
+            name: "Sibaprasad".to_string(),
+            created_at: Some(NOW),
+            updated_at: Some(NOW),
+            ..Default::default()
+        };
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_find_one::<User>()
+            .with(
+                eq(DB_NAME),
+                eq("users"),
+                eq(Some(live(doc! {"id": 5_i64}))),
+                always(),
+            )
+            .times(1)
+            .returning(move |_, _, _, _| Ok(Some(user.clone())));
+        mock_db
+    }
+
+    #[tokio::test]
+    async fn test_get_user_by_id_handler_sets_last_modified() {
+        let app = build_router(test_state(get_user_by_id_mock()));
+        let req = Request::builder()
+            .uri("/user/5")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let last_modified = res.headers().get(header::LAST_MODIFIED).unwrap();
+        assert_eq!(
+            last_modified,
+            &httpdate::fmt_http_date(NOW.to_system_time())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_user_by_id_handler_not_modified() {
+        let app = build_router(test_state(get_user_by_id_mock()));
+        let since = NOW.to_system_time() + Duration::from_secs(60);
+        let req = Request::builder()
+            .uri("/user/5")
+            .header(header::IF_MODIFIED_SINCE, httpdate::fmt_http_date(since))
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert!(body.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_get_user_by_id_handler_modified_since() {
+        let app = build_router(test_state(get_user_by_id_mock()));
+        let since = NOW.to_system_time() - Duration::from_secs(60);
+        let req = Request::builder()
+            .uri("/user/5")
+            .header(header::IF_MODIFIED_SINCE, httpdate::fmt_http_date(since))
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    // the first read of user 5 succeeds, every later one finds the database gone
+    async fn get_user_while_database_goes_away(serve_stale_on_error: bool) -> Vec<Response> {
+        let user = User {
+            id: 5,
+            name: "Sibaprasad".to_string(),
+            ..Default::default()
+        };
+        let mut mock_db = AppDatabase::default();
+        let mut seq = mockall::Sequence::new();
+        mock_db
+            .expect_find_one::<User>()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(move |_, _, _, _| Ok(Some(user.clone())));
+        mock_db
+            .expect_find_one::<User>()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_, _, _, _| {
+                let refused = std::io::Error::from(std::io::ErrorKind::ConnectionRefused);
+                Err(refused.into())
+            });
+        let state = AppState {
+            config: Arc::new(Config {
+                serve_stale_on_error,
+                retry_reads: false,
+                ..Default::default()
+            }),
+            ..test_state(mock_db)
+        };
+        let app = build_router(state);
+        let mut responses = Vec::new();
+        for _ in 0..2 {
+            let req = Request::builder()
+                .uri("/user/5")
+                .body(Body::empty())
+                .unwrap();
+            responses.push(app.clone().oneshot(req).await.unwrap());
+        }
+        responses
+    }
+
+    #[tokio::test]
+    async fn test_get_user_by_id_serves_stale_copy_when_database_unavailable() {
+        let responses = get_user_while_database_goes_away(true).await;
+        assert_eq!(responses[0].status(), StatusCode::OK);
+        assert!(responses[0].headers().get(header::WARNING).is_none());
+        let res = responses.into_iter().nth(1).unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[header::WARNING], STALE_WARNING);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["data"]["name"], "Sibaprasad");
+    }
+
+    #[tokio::test]
+    async fn test_get_user_by_id_unavailable_without_stale_copies() {
+        let responses = get_user_while_database_goes_away(false).await;
+        assert_eq!(responses[0].status(), StatusCode::OK);
+        assert_eq!(responses[1].status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(responses[1].headers().get(header::WARNING).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_create_and_get_user_with_id_above_u32_max() {
+        let id = u32::MAX as i64 + 1;
+        let user = User {
+            id,
+            name: "Sibaprasad".to_string(),
+            phone: "56565656".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            to_document(&user).unwrap().get("id"),
+            Some(&Bson::Int64(id))
+        );
+        let mut mock_db = create_user_mock(id);
+        let stored = user.clone();
+        mock_db
+            .expect_find_one::<User>()
+            .with(
+                eq(DB_NAME),
+                eq("users"),
+                eq(Some(live(doc! {"id": id}))),
+                always(),
+            )
+            .times(1)
+            .returning(move |_, _, _, _| Ok(Some(stored.clone())));
+        let app = build_router(test_state(mock_db));
+        let req = Request::builder()
+            .method("POST")
+            .uri("/user")
+            .header("Content-Type", "application/json")
+            .body(Body::from(serde_json::to_string(&user).unwrap()))
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let req = Request::builder()
+            .uri(format!("/user/{id}"))
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["data"]["id"], 4_294_967_296_i64);
+    }
+}
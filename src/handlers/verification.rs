@@ -0,0 +1,464 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    extract::{Path, State},
+    response::IntoResponse,
+};
+use mongodb::bson::{doc, DateTime};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    clock::Clock,
+    config::Config,
+    error::AppError,
+    extract::{AppJson, KnownFields},
+    repo::UserRepo,
+    response::ApiResponse,
+    validation::Validator,
+};
+
+// how long an email verification token stays valid
+pub const VERIFY_TOKEN_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+// the verification related fields stored on a user document
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+pub struct EmailVerification {
+    pub email: Option<String>,
+    pub verify_token: Option<String>,
+    pub verify_expires: Option<DateTime>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ConfirmEmailPayload {
+    pub token: String,
+}
+
+impl KnownFields for ConfirmEmailPayload {
+    const FIELDS: &'static [&'static str] = &["token"];
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateEmailPayload {
+    pub email: String,
+}
+
+impl KnownFields for UpdateEmailPayload {
+    const FIELDS: &'static [&'static str] = &["email"];
+}
+
+fn generate_verify_token() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect()
+}
+
+pub async fn request_email_verification_handler(
+    repo: UserRepo,
+    State(clock): State<Arc<dyn Clock>>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    let verification = repo
+        .get_as::<EmailVerification>(id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("user not found".to_string()))?;
+    if verification.email.is_none() {
+        return Err(AppError::BadRequest("user has no email".to_string()));
+    }
+    let token = generate_verify_token();
+    let expires =
+        DateTime::from_millis(clock.now().timestamp_millis() + VERIFY_TOKEN_TTL.as_millis() as i64);
+    let update = doc! {"$set": {"verify_token": &token, "verify_expires": expires}};
+    if repo.update(id, update).await?.matched_count == 0 {
+        return Err(AppError::NotFound("user not found".to_string()));
+    }
+    // the token is meant to be delivered by email, which is not wired up yet
+    tracing::debug!("verification token generated for user {id}");
+    Ok(ApiResponse::ok(
+        json!({"message": "verification requested"}),
+    ))
+}
+
+pub async fn confirm_email_verification_handler(
+    repo: UserRepo,
+    State(clock): State<Arc<dyn Clock>>,
+    Path(id): Path<i64>,
+    AppJson(payload): AppJson<ConfirmEmailPayload>,
+) -> Result<impl IntoResponse, AppError> {
+    let verification = repo
+        .get_as::<EmailVerification>(id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("user not found".to_string()))?;
+    if verification.verify_token.as_deref() != Some(payload.token.as_str()) {
+        return Err(AppError::BadRequest(
+            "invalid verification token".to_string(),
+        ));
+    }
+    let now = clock.now();
+    if !matches!(verification.verify_expires, Some(expires) if expires >= now) {
+        return Err(AppError::BadRequest(
+            "verification token expired".to_string(),
+        ));
+    }
+    let update = doc! {
+        "$set": {"email_verified": true},
+        "$unset": {"verify_token": "", "verify_expires": ""},
+    };
+    if repo.update(id, update).await?.matched_count == 0 {
+        return Err(AppError::NotFound("user not found".to_string()));
+    }
+    Ok(ApiResponse::ok(json!({"message": "email verified"})))
+}
+
+// a new email is not verified yet, the single update also drops any
+// pending token so it cannot confirm the new address
+fn update_email_document(email: &str, now: DateTime) -> mongodb::bson::Document {
+    doc! {
+        "$set": {"email": email, "email_verified": false, "updated_at": now},
+        "$unset": {"verify_token": "", "verify_expires": ""},
+    }
+}
+
+pub async fn update_email_handler(
+    repo: UserRepo,
+    State(config): State<Arc<Config>>,
+    State(clock): State<Arc<dyn Clock>>,
+    Path(id): Path<i64>,
+    AppJson(payload): AppJson<UpdateEmailPayload>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut validator = Validator::default();
+    validator.check_email(&payload.email, &config.allowed_email_domains);
+    validator.finish()?;
+    let now = clock.now();
+    let update = update_email_document(&payload.email, now);
+    let result = repo.audited(id, now, repo.update(id, update)).await?;
+    if result.matched_count == 0 {
+        return Err(AppError::NotFound("user not found".to_string()));
+    }
+    Ok(ApiResponse::ok(
+        json!({"email": payload.email, "email_verified": false}),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build_router;
+    use crate::database::{UpdateResult, DB_NAME};
+    use crate::models::User;
+    use crate::test_support::{allow_audit, allow_user_reads, live, test_state, NOW};
+    use crate::{AppDatabase, AppState};
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use mockall::predicate::function;
+    use mockall::predicate::{always, eq};
+    use mongodb::bson::Document;
+    use mongodb::options::FindOneOptions;
+    use mongodb::options::UpdateOptions;
+    use tower::ServiceExt;
+
+    fn confirm_request(token: &str) -> Request<Body> {
+        Request::builder()
+            .method("POST")
+            .uri("/user/7/verify/confirm")
+            .header("Content-Type", "application/json")
+            .body(Body::from(json!({ "token": token }).to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_request_email_verification_handler() {
+        let coll_name = "users";
+        let verification = EmailVerification {
+            email: Some("sibu@example.com".to_string()),
+            ..Default::default()
+        };
+        let expires =
+            DateTime::from_millis(NOW.timestamp_millis() + VERIFY_TOKEN_TTL.as_millis() as i64);
+        let is_verify_update = function(move |update: &Document| {
+            let set = update.get_document("$set").unwrap();
+            set.get_str("verify_token").unwrap().len() == 32
+                && set.get_datetime("verify_expires").unwrap() == &expires
+        });
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_find_one::<EmailVerification>()
+            .with(
+                eq(DB_NAME),
+                eq(coll_name),
+                eq(Some(live(doc! {"id": 7_i64}))),
+                function(|x: &Option<FindOneOptions>| x.is_none()),
+            )
+            .times(1)
+            .returning(move |_, _, _, _| Ok(Some(verification.clone())));
+        mock_db
+            .expect_update_one()
+            .with(
+                eq(DB_NAME),
+                eq(coll_name),
+                eq(live(doc! {"id": 7_i64})),
+                is_verify_update,
+                function(|x: &Option<UpdateOptions>| x.is_none()),
+            )
+            .times(1)
+            .returning(|_, _, _, _, _| {
+                Ok(UpdateResult {
+                    matched_count: 1,
+                    modified_count: 1,
+                })
+            });
+        let app = build_router(test_state(mock_db));
+        let req = Request::builder()
+            .method("POST")
+            .uri("/user/7/verify/request")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_confirm_email_verification_handler() {
+        let verification = EmailVerification {
+            email: Some("sibu@example.com".to_string()),
+            verify_token: Some("abc123".to_string()),
+            verify_expires: Some(DateTime::from_millis(NOW.timestamp_millis() + 1000)),
+        };
+        let expected_update = doc! {
+            "$set": {"email_verified": true},
+            "$unset": {"verify_token": "", "verify_expires": ""},
+        };
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_find_one::<EmailVerification>()
+            .times(1)
+            .returning(move |_, _, _, _| Ok(Some(verification.clone())));
+        mock_db
+            .expect_update_one()
+            .with(
+                eq(DB_NAME),
+                eq("users"),
+                eq(live(doc! {"id": 7_i64})),
+                eq(expected_update),
+                function(|x: &Option<UpdateOptions>| x.is_none()),
+            )
+            .times(1)
+            .returning(|_, _, _, _, _| {
+                Ok(UpdateResult {
+                    matched_count: 1,
+                    modified_count: 1,
+                })
+            });
+        let app = build_router(test_state(mock_db));
+        let res = app.oneshot(confirm_request("abc123")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    // a soft deleted user is left out of the read, so it gets no token and
+    // cannot confirm one either
+    #[tokio::test]
+    async fn test_email_verification_of_deleted_user_not_found() {
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_find_one::<EmailVerification>()
+            .with(
+                eq(DB_NAME),
+                eq("users"),
+                eq(Some(live(doc! {"id": 7_i64}))),
+                always(),
+            )
+            .times(2)
+            .returning(|_, _, _, _| Ok(None));
+        mock_db.expect_update_one().times(0);
+        let app = build_router(test_state(mock_db));
+        let request = Request::builder()
+            .method("POST")
+            .uri("/user/7/verify/request")
+            .body(Body::empty())
+            .unwrap();
+        for req in [request, confirm_request("abc123")] {
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_confirm_email_verification_strict_json_rejects_unknown_field() {
+        let mut mock_db = AppDatabase::default();
+        mock_db.expect_find_one::<EmailVerification>().times(0);
+        let state = AppState {
+            config: Arc::new(Config {
+                strict_json: true,
+                ..Default::default()
+            }),
+            ..test_state(mock_db)
+        };
+        let app = build_router(state);
+        let req = Request::builder()
+            .method("POST")
+            .uri("/user/7/verify/confirm")
+            .header("Content-Type", "application/json")
+            .body(Body::from(
+                json!({"token": "abc123", "tokn": "x"}).to_string(),
+            ))
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    fn update_email_request(email: &str) -> Request<Body> {
+        Request::builder()
+            .method("PATCH")
+            .uri("/user/7/email")
+            .header("Content-Type", "application/json")
+            .body(Body::from(json!({ "email": email }).to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_update_email_handler_resets_verification() {
+        let expected_update = doc! {
+            "$set": {"email": "new@example.com", "email_verified": false, "updated_at": NOW},
+            "$unset": {"verify_token": "", "verify_expires": ""},
+        };
+        let mut mock_db = AppDatabase::default();
+        allow_user_reads(
+            &mut mock_db,
+            User {
+                id: 7,
+                ..Default::default()
+            },
+        );
+        allow_audit(&mut mock_db);
+        mock_db
+            .expect_update_one()
+            .with(
+                eq(DB_NAME),
+                eq("users"),
+                eq(live(doc! {"id": 7_i64})),
+                eq(expected_update),
+                function(|x: &Option<UpdateOptions>| x.is_none()),
+            )
+            .times(1)
+            .returning(|_, _, _, _, _| {
+                Ok(UpdateResult {
+                    matched_count: 1,
+                    modified_count: 1,
+                })
+            });
+        let app = build_router(test_state(mock_db));
+        let res = app
+            .oneshot(update_email_request("new@example.com"))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["data"]["email_verified"], false);
+    }
+
+    #[tokio::test]
+    async fn test_update_email_handler_strict_json_rejects_unknown_field() {
+        let mut mock_db = AppDatabase::default();
+        mock_db.expect_update_one().times(0);
+        let state = AppState {
+            config: Arc::new(Config {
+                strict_json: true,
+                ..Default::default()
+            }),
+            ..test_state(mock_db)
+        };
+        let req = Request::builder()
+            .method("PATCH")
+            .uri("/user/7/email")
+            .header("Content-Type", "application/json")
+            .body(Body::from(
+                json!({"email": "new@example.com", "verified": true}).to_string(),
+            ))
+            .unwrap();
+        let res = build_router(state).oneshot(req).await.unwrap();
+        assert!(res.status().is_client_error(), "{}", res.status());
+    }
+
+    #[tokio::test]
+    async fn test_update_email_handler_invalid_email() {
+        let mut mock_db = AppDatabase::default();
+        mock_db.expect_update_one().times(0);
+        let app = build_router(test_state(mock_db));
+        let res = app.oneshot(update_email_request("sibu")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn test_update_email_handler_missing_user() {
+        let mut mock_db = AppDatabase::default();
+        allow_user_reads(
+            &mut mock_db,
+            User {
+                id: 7,
+                ..Default::default()
+            },
+        );
+        allow_audit(&mut mock_db);
+        mock_db
+            .expect_update_one()
+            .times(1)
+            .returning(|_, _, _, _, _| {
+                Ok(UpdateResult {
+                    matched_count: 0,
+                    modified_count: 0,
+                })
+            });
+        let app = build_router(test_state(mock_db));
+        let res = app
+            .oneshot(update_email_request("new@example.com"))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_confirm_email_verification_handler_expired_token() {
+        let verification = EmailVerification {
+            email: Some("sibu@example.com".to_string()),
+            verify_token: Some("abc123".to_string()),
+            verify_expires: Some(DateTime::from_millis(NOW.timestamp_millis() - 1000)),
+        };
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_find_one::<EmailVerification>()
+            .times(1)
+            .returning(move |_, _, _, _| Ok(Some(verification.clone())));
+        mock_db.expect_update_one().times(0);
+        let app = build_router(test_state(mock_db));
+        let res = app.oneshot(confirm_request("abc123")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["message"], "verification token expired");
+    }
+
+    #[tokio::test]
+    async fn test_confirm_email_verification_handler_wrong_token() {
+        let verification = EmailVerification {
+            email: Some("sibu@example.com".to_string()),
+            verify_token: Some("abc123".to_string()),
+            verify_expires: Some(DateTime::from_millis(NOW.timestamp_millis() + 1000)),
+        };
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_find_one::<EmailVerification>()
+            .times(1)
+            .returning(move |_, _, _, _| Ok(Some(verification.clone())));
+        mock_db.expect_update_one().times(0);
+        let app = build_router(test_state(mock_db));
+        let res = app.oneshot(confirm_request("xyz789")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["message"], "invalid verification token");
+    }
+}
@@ -1,22 +1,67 @@
-use database::DB_NAME;
+use access_log::SampledOnResponse;
+use auth::require_admin_token;
+use clock::{Clock, SystemClock};
+use config::{Config, LogFormat};
+use content_type::require_utf8_json;
+use crypto::FieldCipher;
+use database_ext::AppDatabaseExt;
+use deadline::response_deadline;
+use decompression::decompress_request;
+use dedup::{swallow_duplicate_submissions, RecentSubmissions};
+use display_tz::display_timezone;
+use features::inject_features;
+use handlers::{
+    admin::{
+        db_stats_handler, list_collections_handler, list_indexes_handler, server_status_handler,
+    },
+    health::{detailed_health_handler, readiness_handler},
+    history::user_history_handler,
+    schema::user_schema_handler,
+    user::{
+        bulk_update_users_handler, clone_user_handler, create_user_handler, create_users_handler,
+        delete_users_handler, estimate_user_count_handler, export_users_handler,
+        get_user_by_id_handler, get_user_handler, get_user_v2_handler, get_users_by_ids_handler,
+        list_users_handler, merge_user_handler, patch_user_handler, put_user_handler,
+        restore_user_handler, set_user_fields_handler, touch_user_handler,
+        update_user_element_handler, user_facets_handler,
+    },
+    verification::{
+        confirm_email_verification_handler, request_email_verification_handler,
+        update_email_handler,
+    },
+};
+use maintenance::{maintenance_guard, MaintenanceMode};
+use metrics::{count_requests, json_metrics_handler, prometheus_metrics_handler, Metrics};
 use mockall_double::double;
-use serde::{Deserialize, Serialize};
-use serde_json::json;
-use std::{net::SocketAddr, sync::Arc, time::Duration};
+use query_limit::limit_query_length;
+use quota::{enforce_daily_quota, DailyQuota};
+use repo::UserRepo;
+use response::{canonical_json, envelope_opt_out};
+use response_time::record_response_time;
+use select::select_fields;
+use shutdown::{shutdown_signal, ConnectionTracker};
+use stale_cache::StaleUsers;
+use std::{convert::Infallible, sync::Arc, time::Duration};
 
 use axum::{
     body::Body,
-    extract::State,
-    http::{header, HeaderValue, StatusCode},
-    response::IntoResponse,
-    routing::{get, IntoMakeService},
-    Json, Router,
+    extract::{ConnectInfo, FromRef},
+    handler::Handler,
+    http::{header, HeaderName, HeaderValue, Request},
+    middleware,
+    routing::{get, patch, post},
+    Router,
 };
 use dotenvy::dotenv;
-use mongodb::bson::doc;
-use tower::ServiceBuilder;
+use hyper::server::conn::AddrStream;
+use tokio::sync::Notify;
+use tower::{limit::GlobalConcurrencyLimitLayer, ServiceBuilder, ServiceExt};
 use tower_http::{
-    cors::CorsLayer, set_header::SetResponseHeaderLayer, timeout::TimeoutLayer, trace::TraceLayer,
+    cors::{AllowHeaders, AllowMethods, AllowOrigin, Any, CorsLayer},
+    request_id::MakeRequestUuid,
+    set_header::SetResponseHeaderLayer,
+    timeout::TimeoutLayer,
+    trace::TraceLayer,
     ServiceBuilderExt,
 };
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
@@ -24,160 +69,965 @@ use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 #[double]
 use database::AppDatabase;
 
+mod access_log;
+mod audit;
+mod auth;
+mod bson_json;
+mod buffer;
+mod client_ip;
+mod clock;
+mod config;
+mod content_type;
+mod crypto;
 mod database;
+mod database_ext;
+mod deadline;
+mod decompression;
+mod dedup;
+mod display_tz;
+mod error;
+mod extract;
+mod features;
+mod handlers;
+mod maintenance;
+mod metrics;
+mod migrations;
+mod models;
+mod pagination;
+mod query_limit;
+mod quota;
+mod repo;
+mod response;
+mod response_time;
+mod select;
+mod shutdown;
+mod stale_cache;
+#[cfg(test)]
+mod test_support;
+mod validation;
 
 #[tokio::main]
 async fn main() {
     dotenv().ok();
 
+    let config = Config::from_env().unwrap_or_else(|err| {
+        eprintln!("invalid configuration: {err}");
+        std::process::exit(1);
+    });
+    if !config.disable_tracing {
+        init_tracing(config.log_format);
+    }
+    config.log_redacted();
+
+    let addr = config.bind_addr;
+    let shutdown_timeout = config.shutdown_timeout;
+    let builder = configure_server(axum::Server::bind(&addr), &config);
+    let app = create_app(config).await;
+
+    // every connection holds a guard so the ones still open at forced close can be reported
+    let tracker = ConnectionTracker::default();
+    let make_service = hyper::service::make_service_fn(|conn: &AddrStream| {
+        let guard = tracker.track();
+        let remote_addr = conn.remote_addr();
+        let app = app.clone().map_request(move |mut req: Request<Body>| {
+            let _guard = &guard;
+            req.extensions_mut().insert(ConnectInfo(remote_addr));
+            req
+        });
+        async move { Ok::<_, Infallible>(app) }
+    });
+
+    let signaled = Arc::new(Notify::new());
+    let notifier = signaled.clone();
+    let server = builder
+        .serve(make_service)
+        .with_graceful_shutdown(async move {
+            shutdown_signal().await;
+            notifier.notify_one();
+        });
+    let drain_deadline = async {
+        signaled.notified().await;
+        tokio::time::sleep(shutdown_timeout).await;
+    };
+
+    tracing::debug!("Starting the app in: {addr}");
+    tokio::select! {
+        result = server => result.unwrap(),
+        _ = drain_deadline => {
+            tracing::warn!(
+                "shutdown timeout of {shutdown_timeout:?} elapsed, forcing close of {} active connections",
+                tracker.active()
+            );
+        }
+    }
+}
+
+// apply the HTTP settings of the configuration to the server
+fn configure_server<I>(
+    builder: hyper::server::Builder<I>,
+    config: &Config,
+) -> hyper::server::Builder<I> {
+    let builder = builder
+        .http1_keepalive(config.http1_keep_alive)
+        .http2_only(config.http2_only);
+    match config.max_header_bytes {
+        Some(bytes) => builder.http1_max_buf_size(bytes),
+        None => builder,
+    }
+}
+
+// install the global subscriber, a no-op when one is already set, e.g. by
+// a test harness, so calling it again does not panic
+fn init_tracing(log_format: LogFormat) {
     let env_filter = tracing_subscriber::EnvFilter::try_from_default_env()
         .unwrap_or("axum-testing=debug".into());
-    tracing_subscriber::registry()
-        .with(env_filter)
-        .with(tracing_subscriber::fmt::layer())
-        .init();
-    let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
-    let app = create_app().await;
-    tracing::debug!("Starting the app in: {addr}");
-    axum::Server::bind(&addr).serve(app).await.unwrap();
+    let fmt_layer = tracing_subscriber::fmt::layer();
+    let registry = tracing_subscriber::registry().with(env_filter);
+    let result = match log_format {
+        LogFormat::Full => registry.with(fmt_layer).try_init(),
+        LogFormat::Compact => registry.with(fmt_layer.compact()).try_init(),
+        LogFormat::Pretty => registry.with(fmt_layer.pretty()).try_init(),
+    };
+    if result.is_err() {
+        tracing::debug!("a tracing subscriber is already set, keeping it");
+    }
+}
+
+// any origin is allowed when none is configured, credentials rule out the
+// `*` wildcards so the requested methods and headers are mirrored instead
+fn cors_layer(config: &Config) -> CorsLayer {
+    if config.cors_origins.is_empty() {
+        return CorsLayer::permissive().max_age(config.cors_max_age);
+    }
+    let layer = CorsLayer::new()
+        .allow_origin(AllowOrigin::list(config.cors_origins.clone()))
+        // so browser clients can read the timing
+        .expose_headers([HeaderName::from_static(response_time::RESPONSE_TIME_HEADER)])
+        .max_age(config.cors_max_age);
+    if config.cors_allow_credentials {
+        layer
+            .allow_methods(AllowMethods::mirror_request())
+            .allow_headers(AllowHeaders::mirror_request())
+            .allow_credentials(true)
+    } else {
+        layer.allow_methods(Any).allow_headers(Any)
+    }
 }
 
-async fn create_app() -> IntoMakeService<Router> {
-    let timeout_layer = TimeoutLayer::new(Duration::from_secs(10));
-    let cors_layer = CorsLayer::permissive();
+// the first requests would otherwise wait for the driver to connect, a
+// failure is only logged as the pool fills up on demand anyway
+async fn prime_pool(db: &AppDatabase) {
+    let Some(connections) = db.min_pool_size().filter(|&size| size > 0) else {
+        tracing::warn!("PRIME_POOL is set but MONGODB_URI has no minPoolSize");
+        return;
+    };
+    match db.prime_pool(connections).await {
+        Ok(()) => tracing::info!("opened {connections} database connections"),
+        Err(err) => tracing::warn!("failed to prime the connection pool: {err:?}"),
+    }
+}
+
+async fn create_app(mut config: Config) -> Router {
+    let tls = database::tls_options(
+        config.mongodb_tls_ca_file.as_deref(),
+        config.mongodb_tls_insecure,
+    );
+    let uris: Vec<&str> = std::iter::once(config.mongodb_uri.as_str())
+        .chain(config.mongodb_uri_fallback.as_deref())
+        .collect();
+    // creating the client does not connect, the ping checks the server is there
+    let db = database::connect_with_fallback(&uris, |uri| {
+        let (uri, write_concern, tls) =
+            (uri.to_string(), config.write_concern.clone(), tls.clone());
+        let slow_query_threshold = config.slow_query_threshold;
+        let writable_collections = config.writable_collections.clone();
+        let app_name = config.mongodb_app_name.clone();
+        let operation_timeout = config.db_operation_timeout;
+        let read_uri = config.mongodb_uri_read.clone();
+        async move {
+            let db = AppDatabase::new(
+                &uri,
+                read_uri,
+                write_concern,
+                tls,
+                slow_query_threshold,
+                &app_name,
+            )
+            .await?
+            .with_writable_collections(writable_collections)
+            .with_operation_timeout(operation_timeout);
+            db.ping().await?;
+            Ok::<_, mongodb::error::Error>(db)
+        }
+    })
+    .await
+    .unwrap();
+    config.prefer_uri_db_name(db.default_db_name());
+    tracing::info!("using the {} database", config.db_name);
+    if config.prime_pool {
+        prime_pool(&db).await;
+    }
+    if config.run_migrations {
+        migrations::run_pending(&db, &config.db_name, SystemClock.now())
+            .await
+            .unwrap();
+    }
+    let state = AppState {
+        db: Arc::new(db),
+        maintenance: MaintenanceMode::new(config.maintenance_mode),
+        clock: Arc::new(SystemClock),
+        phone_cipher: config
+            .phone_encryption_key
+            .as_ref()
+            .map(|key| Arc::new(FieldCipher::new(key))),
+        config: Arc::new(config),
+        quota: DailyQuota::default(),
+        submissions: RecentSubmissions::default(),
+        stale_users: StaleUsers::default(),
+        metrics: Metrics::default(),
+    };
+    build_app(state)
+}
+
+// the routes wrapped in the outer middleware: CORS, the `Server` header,
+// the access log and the response compression
+fn build_app(state: AppState) -> Router {
+    let cors_layer = cors_layer(&state.config);
     let server_header_value = HeaderValue::from_static("axum_testing");
     let set_res_header_layer =
         SetResponseHeaderLayer::if_not_present(header::SERVER, server_header_value);
+    // the access log names the client, see `client_ip` for when proxies are trusted
+    let trust_proxy = state.config.trust_proxy;
+    let trace_layer = TraceLayer::new_for_http()
+        .make_span_with(move |req: &Request<Body>| {
+            let client_ip = client_ip::client_ip(req, trust_proxy);
+            tracing::debug_span!(
+                "request",
+                method = %req.method(),
+                uri = %req.uri(),
+                version = ?req.version(),
+                client_ip = client_ip.map(tracing::field::display),
+            )
+        })
+        .on_response(SampledOnResponse::new(state.config.log_sample_rate));
     let middleware = ServiceBuilder::new()
-        .layer(timeout_layer)
         .layer(cors_layer)
         .layer(set_res_header_layer)
         .map_response_body(axum::body::boxed)
-        .layer(TraceLayer::new_for_http())
+        // a client supplied `x-request-id` is kept, the repo tags its reads with it
+        .set_x_request_id(MakeRequestUuid)
+        .propagate_x_request_id()
+        .layer(trace_layer)
         .compression()
         .into_inner();
+    build_router(state).layer(middleware)
+}
 
-    let uri = std::env::var("MONGODB_URI").expect("MONGODB_URI not found in .env file");
-    let db = AppDatabase::new(uri.as_str()).await.unwrap();
-    let db = Arc::new(db);
-    let app: Router<(), Body> = Router::new()
-        .route("/user", get(get_user_handler).post(create_user_handler))
-        .layer(middleware)
-        .with_state(db);
-
-    app.into_make_service()
-}
-
-#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
-struct User {
-    id: u32,
-    name: String,
-    phone: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    email: Option<String>,
-    #[serde(rename = "isActive")]
-    is_active: bool,
-}
-
-async fn get_user_handler(State(database): State<Arc<AppDatabase>>) -> impl IntoResponse {
-    let coll_name = "users";
-    let filter = Some(doc! {"id": 76});
-    let result = database
-        .find_one::<User>(DB_NAME, coll_name, filter, None)
-        .await
-        .unwrap();
-    (StatusCode::OK, Json(result.unwrap()))
-}
-
-async fn create_user_handler(
-    State(database): State<Arc<AppDatabase>>,
-    Json(payload): Json<User>,
-) -> impl IntoResponse {
-    println!("create_user_handler called");
-    let coll_name = "users";
-    let result = database
-        .insert_one(DB_NAME, coll_name, &payload, None)
-        .await;
-    if result.is_err() {
-        tracing::debug!("{:?}", result.err().unwrap());
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({"success": false, "message": "Unexpected error"})),
-        );
+// state shared by all the handlers. the quota, the recent submissions, the
+// stale users and the metrics live in memory, so every instance of the app
+// keeps its own
+#[derive(Clone)]
+struct AppState {
+    db: Arc<AppDatabase>,
+    maintenance: MaintenanceMode,
+    clock: Arc<dyn Clock>,
+    config: Arc<Config>,
+    phone_cipher: Option<Arc<FieldCipher>>,
+    quota: DailyQuota,
+    submissions: RecentSubmissions,
+    stale_users: StaleUsers,
+    metrics: Metrics,
+}
+
+impl FromRef<AppState> for Arc<AppDatabase> {
+    fn from_ref(state: &AppState) -> Self {
+        state.db.clone()
+    }
+}
+
+impl FromRef<AppState> for MaintenanceMode {
+    fn from_ref(state: &AppState) -> Self {
+        state.maintenance.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<dyn Clock> {
+    fn from_ref(state: &AppState) -> Self {
+        state.clock.clone()
     }
-    let result = result.unwrap();
-    (
-        StatusCode::OK,
-        Json(json!({"success": true, "insertedID": result.inserted_id })),
+}
+
+impl FromRef<AppState> for Arc<Config> {
+    fn from_ref(state: &AppState) -> Self {
+        state.config.clone()
+    }
+}
+
+impl FromRef<AppState> for DailyQuota {
+    fn from_ref(state: &AppState) -> Self {
+        state.quota.clone()
+    }
+}
+
+impl FromRef<AppState> for RecentSubmissions {
+    fn from_ref(state: &AppState) -> Self {
+        state.submissions.clone()
+    }
+}
+
+impl FromRef<AppState> for Metrics {
+    fn from_ref(state: &AppState) -> Self {
+        state.metrics.clone()
+    }
+}
+
+impl FromRef<AppState> for UserRepo {
+    fn from_ref(state: &AppState) -> Self {
+        UserRepo::new(
+            state.db.clone(),
+            state.config.clone(),
+            state.phone_cipher.clone(),
+        )
+        .with_stale_users(state.stale_users.clone())
+    }
+}
+
+// register all the routes and attach the state to them
+fn build_router(state: AppState) -> Router {
+    let max_concurrent_requests = state.config.max_concurrent_requests;
+    let read_timeout = TimeoutLayer::new(state.config.read_timeout);
+    let admin = Router::new()
+        .route("/collections", get(list_collections_handler))
+        .route("/collections/:name/indexes", get(list_indexes_handler))
+        .route("/stats", get(db_stats_handler))
+        .route("/server-status", get(server_status_handler))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            require_admin_token,
+        ));
+    let router = Router::new()
+        .route("/readyz", get(readiness_handler))
+        .route("/metrics", get(prometheus_metrics_handler))
+        .route("/metrics/json", get(json_metrics_handler))
+        // the server version is nobody else's business
+        .route(
+            "/health/detailed",
+            get(detailed_health_handler).route_layer(middleware::from_fn_with_state(
+                state.clone(),
+                require_admin_token,
+            )),
+        )
+        .route("/schema/user", get(user_schema_handler))
+        // `/user` keeps serving the v1 shape for the existing clients
+        .route("/v1/user", get(get_user_handler).layer(read_timeout))
+        .route("/v2/user", get(get_user_v2_handler).layer(read_timeout))
+        .route(
+            "/user",
+            get(get_user_handler)
+                .layer(read_timeout)
+                .post(create_user_handler),
+        )
+        .route(
+            "/user/:id",
+            get(get_user_by_id_handler)
+                .layer(read_timeout)
+                .put(put_user_handler)
+                .patch(patch_user_handler),
+        )
+        .route(
+            "/user/count/estimate",
+            get(estimate_user_count_handler).layer(read_timeout),
+        )
+        .route("/user/facets", get(user_facets_handler).layer(read_timeout))
+        .route("/user/:id/clone", post(clone_user_handler))
+        .route("/user/:id/touch", post(touch_user_handler))
+        .route("/user/:id/restore", post(restore_user_handler))
+        .route("/user/:id/merge", post(merge_user_handler))
+        .route("/user/:id/set-fields", post(set_user_fields_handler))
+        .route(
+            "/user/:id/elements/:field",
+            patch(update_user_element_handler),
+        )
+        .route(
+            "/user/:id/history",
+            get(user_history_handler).layer(read_timeout),
+        )
+        .route(
+            "/users",
+            get(list_users_handler)
+                .layer(read_timeout)
+                .post(create_users_handler)
+                .patch(
+                    bulk_update_users_handler.layer(middleware::from_fn_with_state(
+                        state.clone(),
+                        require_admin_token,
+                    )),
+                ),
+        )
+        // the same all-or-nothing validation as `POST /users`
+        .route("/users/batch", post(create_users_handler))
+        .route(
+            "/users/delete",
+            post(delete_users_handler).route_layer(middleware::from_fn_with_state(
+                state.clone(),
+                require_admin_token,
+            )),
+        )
+        .route("/user/:id/email", patch(update_email_handler))
+        .route(
+            "/user/:id/verify/request",
+            post(request_email_verification_handler),
+        )
+        .route(
+            "/user/:id/verify/confirm",
+            post(confirm_email_verification_handler),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            maintenance_guard,
+        ))
+        // batch lookup is a read even though it uses POST,
+        // so it is registered after the maintenance guard
+        .route("/users/by-ids", post(get_users_by_ids_handler))
+        .nest("/admin", admin)
+        // registered before the export route, which has a longer budget
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            response_deadline,
+        ));
+    let export = Router::new().route("/users/export", get(export_users_handler));
+    let router = apply_timeouts(
+        router,
+        export,
+        state.config.request_timeout,
+        state.config.export_timeout,
     )
+    .layer(middleware::from_fn_with_state(
+        state.clone(),
+        display_timezone,
+    ))
+    // runs before the envelope is stripped, it expects the enveloped body
+    .layer(middleware::from_fn(select_fields))
+    .layer(middleware::from_fn(envelope_opt_out))
+    // last of the body rewrites, what it writes out is final
+    .layer(middleware::from_fn_with_state(
+        state.clone(),
+        canonical_json,
+    ))
+    .layer(middleware::from_fn(require_utf8_json))
+    // inside the decompression, so the same payload hashes the same however it was sent
+    .layer(middleware::from_fn_with_state(
+        state.clone(),
+        swallow_duplicate_submissions,
+    ))
+    .layer(middleware::from_fn(decompress_request))
+    // applied to the whole router so it runs before routing
+    .layer(middleware::from_fn_with_state(
+        state.clone(),
+        limit_query_length,
+    ))
+    .layer(middleware::from_fn_with_state(
+        state.clone(),
+        enforce_daily_quota,
+    ))
+    .layer(middleware::from_fn_with_state(
+        state.clone(),
+        inject_features,
+    ))
+    .layer(middleware::from_fn(record_response_time))
+    .layer(middleware::from_fn_with_state(
+        state.clone(),
+        count_requests,
+    ));
+    let router = limit_concurrency(router, max_concurrent_requests);
+    match &state.config.base_path {
+        Some(base_path) => Router::new().nest(base_path, router),
+        None => router,
+    }
+    .with_state(state)
+}
+
+// the global timeout covers the regular routes only, so the export routes
+// can run past it up to their own timeout. a route with an extra timeout
+// of its own, like the reads, is cut off by whichever of them is shorter
+fn apply_timeouts<S>(
+    regular: Router<S>,
+    export: Router<S>,
+    request_timeout: Duration,
+    export_timeout: Duration,
+) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    regular
+        .route_layer(TimeoutLayer::new(request_timeout))
+        .merge(export.route_layer(TimeoutLayer::new(export_timeout)))
+}
+
+// requests beyond the limit are queued, the request timeout bounds the wait.
+// the layer is cloned into every route, the global variant makes them
+// share a single semaphore so the limit covers the whole app
+fn limit_concurrency<S>(router: Router<S>, max: usize) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    router.layer(GlobalConcurrencyLimitLayer::new(max))
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::database::InsertOneResult;
+    use std::io::Write;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
 
     use super::*;
+    use crate::database::{InsertOneResult, DB_NAME};
+    use crate::decompression::MAX_DECOMPRESSED_BODY_BYTES;
+    use crate::models::User;
+    use crate::response::canonicalize;
+    use crate::test_support::{allow_audit, test_app, test_state};
     use axum::http::Request;
     use axum::http::StatusCode;
+    use flate2::{write::GzEncoder, Compression};
+    use mockall::predicate::always;
     use mockall::predicate::eq;
     use mockall::predicate::function;
-    use mongodb::bson::oid::ObjectId;
-    use mongodb::options::FindOneOptions;
-    use mongodb::options::InsertOneOptions;
+    use serde_json::json;
     use tower::ServiceExt;
 
+    async fn preflight(config: &Config) -> axum::http::HeaderMap {
+        let app = Router::new()
+            .route("/user", get(|| async {}))
+            .layer(cors_layer(config));
+        let req = Request::builder()
+            .method("OPTIONS")
+            .uri("/user")
+            .header(header::ORIGIN, "http://a.com")
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "PATCH")
+            .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "authorization")
+            .body(Body::empty())
+            .unwrap();
+        app.oneshot(req).await.unwrap().headers().clone()
+    }
+
     #[tokio::test]
-    async fn test_create_user_handler() {
-        let user = User {
-            id: 200075,
-            name: "Sibaprasad".to_string(),
-            phone: "56565656".to_string(),
-            email: None,
-            is_active: true,
+    async fn test_cors_preflight_max_age_and_credentials() {
+        let config = Config {
+            cors_max_age: Duration::from_secs(3600),
+            ..Default::default()
         };
-        let coll_name = "users";
-        let insert_one_result = InsertOneResult {
-            inserted_id: ObjectId::new().to_hex(),
+        let headers = preflight(&config).await;
+        assert_eq!(headers[header::ACCESS_CONTROL_MAX_AGE], "3600");
+        assert!(headers
+            .get(header::ACCESS_CONTROL_ALLOW_CREDENTIALS)
+            .is_none());
+
+        let config = Config {
+            cors_origins: vec![HeaderValue::from_static("http://a.com")],
+            cors_allow_credentials: true,
+            ..Default::default()
         };
-        let is_none = function(|x: &Option<InsertOneOptions>| x.is_none());
+        let headers = preflight(&config).await;
+        assert_eq!(headers[header::ACCESS_CONTROL_MAX_AGE], "600");
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], "http://a.com");
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_METHODS], "PATCH");
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_mode_rejects_writes_but_allows_reads() {
+        let user = User::default();
         let mut mock_db = AppDatabase::default();
         mock_db
-            .expect_insert_one::<User>()
-            .with(eq(DB_NAME), eq(coll_name), eq(user.clone()), is_none)
+            .expect_find_one::<User>()
             .times(1)
-            .returning(move |_, _, _, _| Ok(insert_one_result.clone()));
-        let db = Arc::new(mock_db);
-        let app = Router::new()
-            .route("/", get(create_user_handler))
-            .with_state(db);
-        let stringified = serde_json::to_string(&user).unwrap();
+            .returning(move |_, _, _, _| Ok(Some(user.clone())));
+        mock_db.expect_insert_one::<User>().times(0);
+        let state = AppState {
+            maintenance: MaintenanceMode::new(true),
+            ..test_state(mock_db)
+        };
+        let app = build_router(state);
+
+        let stringified = serde_json::to_string(&User::default()).unwrap();
         let req = Request::builder()
-            .uri("/")
+            .method("POST")
+            .uri("/user")
             .header("Content-Type", "application/json")
-            .body(stringified)
+            .body(Body::from(stringified))
             .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            json!({"success": false, "message": "maintenance in progress"})
+        );
+
+        let req = Request::builder().uri("/user").body(Body::empty()).unwrap();
         let res = app.oneshot(req).await.unwrap();
         assert_eq!(res.status(), StatusCode::OK);
     }
 
     #[tokio::test]
-    async fn test_get_user_handler() {
-        let user = User::default();
-        let coll_name = "users";
-        let filter = Some(doc! {"id": 76});
-        let is_none = function(|x: &Option<FindOneOptions>| x.is_none());
+    async fn test_concurrency_limit_queues_excess_requests() {
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let slow = {
+            let (in_flight, peak) = (in_flight.clone(), peak.clone());
+            move || async move {
+                let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+            }
+        };
+        let app = limit_concurrency(
+            Router::new()
+                .route("/a", get(slow.clone()))
+                .route("/b", get(slow)),
+            2,
+        );
+
+        let requests = ["/a", "/b", "/a", "/b", "/a"].map(|uri| {
+            let req = Request::builder().uri(uri).body(Body::empty()).unwrap();
+            app.clone().oneshot(req)
+        });
+        for res in futures::future::join_all(requests).await {
+            assert_eq!(res.unwrap().status(), StatusCode::OK);
+        }
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_export_route_outlives_global_timeout() {
+        async fn slow() {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        let app = apply_timeouts(
+            Router::new().route("/users", get(slow)),
+            Router::new().route("/users/export", get(slow)),
+            Duration::from_millis(20),
+            Duration::from_secs(5),
+        );
+
+        let req = Request::builder()
+            .uri("/users")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::REQUEST_TIMEOUT);
+
+        let req = Request::builder()
+            .uri("/users/export")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_over_length_query_string_rejected() {
+        let state = AppState {
+            config: Arc::new(Config {
+                max_query_bytes: 16,
+                ..Default::default()
+            }),
+            ..test_state(AppDatabase::default())
+        };
+        let app = build_router(state);
+        let uri = format!("/user?ids={}", "1,".repeat(16));
+        let req = Request::builder().uri(uri).body(Body::empty()).unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::URI_TOO_LONG);
+    }
+
+    fn get_user_mock() -> AppDatabase {
+        let user = User {
+            id: 76,
+            name: "Sibaprasad".to_string(),
+            ..Default::default()
+        };
         let mut mock_db = AppDatabase::default();
         mock_db
             .expect_find_one::<User>()
-            .with(eq(DB_NAME), eq(coll_name), eq(filter), is_none)
             .times(1)
             .returning(move |_, _, _, _| Ok(Some(user.clone())));
-        let db = Arc::new(mock_db);
-        let app = Router::new()
-            .route("/", get(get_user_handler))
-            .with_state(db);
-        let req = Request::builder().uri("/").body(Body::empty()).unwrap();
+        mock_db
+    }
+
+    #[tokio::test]
+    async fn test_success_response_is_enveloped() {
+        let app = build_router(test_state(get_user_mock()));
+        let req = Request::builder().uri("/user").body(Body::empty()).unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["success"], true);
+        assert_eq!(body["data"]["id"], 76);
+        assert_eq!(body["data"]["name"], "Sibaprasad");
+    }
+
+    // serves the router over a real connection, the way `main` does, so a
+    // route left without its state shows up as a failed request
+    #[tokio::test]
+    async fn test_router_serves_user_over_http() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = build_router(test_state(get_user_mock()));
+        let server = axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(app.into_make_service_with_connect_info::<std::net::SocketAddr>());
+        tokio::spawn(server);
+        let uri = format!("http://{addr}/user").parse().unwrap();
+        let res = hyper::Client::new().get(uri).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["data"]["id"], 76);
+    }
+
+    #[tokio::test]
+    async fn test_router_nested_under_base_path() {
+        let mut state = test_state(get_user_mock());
+        state.config = Arc::new(Config {
+            base_path: Some("/api/v1".to_string()),
+            ..Default::default()
+        });
+        let app = build_router(state);
+        let req = Request::builder().uri("/user").body(Body::empty()).unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        let req = Request::builder()
+            .uri("/api/v1/user")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_app_sets_server_header() {
+        let app = test_app(Arc::new(get_user_mock()));
+        let req = Request::builder().uri("/user").body(Body::empty()).unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[header::SERVER], "axum_testing");
+    }
+
+    #[tokio::test]
+    async fn test_reads_carry_request_id_comment() {
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_find_one::<User>()
+            .withf(|_, _, _, options| {
+                options
+                    .as_ref()
+                    .and_then(|options| options.comment.as_deref())
+                    == Some("request req-42")
+            })
+            .times(1)
+            .returning(|_, _, _, _| Ok(Some(User::default())));
+        let app = test_app(Arc::new(mock_db));
+        let req = Request::builder()
+            .uri("/user")
+            .header("x-request-id", "req-42")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["x-request-id"], "req-42");
+    }
+
+    #[test]
+    fn test_init_tracing_twice() {
+        init_tracing(LogFormat::Compact);
+        init_tracing(LogFormat::Full);
+    }
+
+    #[tokio::test]
+    async fn test_response_time_header() {
+        let app = build_router(test_state(get_user_mock()));
+        let req = Request::builder().uri("/user").body(Body::empty()).unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let elapsed_ms: f64 = res.headers()[response_time::RESPONSE_TIME_HEADER]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(elapsed_ms >= 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_select_response_fields() {
+        let app = build_router(test_state(get_user_mock()));
+        let req = Request::builder()
+            .uri("/user?select=id,name")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({"success": true, "data": {"id": 76, "name": "Sibaprasad"}})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_select_unknown_field_rejected() {
+        let app = build_router(test_state(get_user_mock()));
+        let req = Request::builder()
+            .uri("/user?select=id,password")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_success_response_envelope_opt_out() {
+        let app = build_router(test_state(get_user_mock()));
+        let req = Request::builder()
+            .uri("/user?envelope=false")
+            .body(Body::empty())
+            .unwrap();
         let res = app.oneshot(req).await.unwrap();
         assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: User = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body.id, 76);
+        assert_eq!(body.name, "Sibaprasad");
+    }
+
+    #[tokio::test]
+    async fn test_canonical_json_routes_sort_keys() {
+        let state = AppState {
+            config: Arc::new(Config {
+                canonical_json_routes: vec!["/user".to_string()],
+                ..Default::default()
+            }),
+            ..test_state(AppDatabase::default())
+        };
+        let mut bodies = Vec::new();
+        for _ in 0..2 {
+            let state = AppState {
+                db: Arc::new(get_user_mock()),
+                ..state.clone()
+            };
+            let req = Request::builder().uri("/user").body(Body::empty()).unwrap();
+            let res = build_router(state).oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+            bodies.push(hyper::body::to_bytes(res.into_body()).await.unwrap());
+        }
+        assert_eq!(bodies[0], bodies[1]);
+        let body = std::str::from_utf8(&bodies[0]).unwrap();
+        assert!(body.starts_with(r#"{"data":{"display_name":"Sibaprasad ()","id":76,"#));
+        assert!(body.ends_with(r#""phone":""},"success":true}"#));
+    }
+
+    #[test]
+    fn test_canonicalize_sorts_nested_keys() {
+        let value: serde_json::Value =
+            serde_json::from_str(r#"{"b": [{"z": 1, "a": 2}], "a": {"y": true, "x": null}}"#)
+                .unwrap();
+        assert_eq!(
+            canonicalize(value).to_string(),
+            r#"{"a":{"x":null,"y":true},"b":[{"a":2,"z":1}]}"#
+        );
+    }
+
+    fn create_user_mock() -> AppDatabase {
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_insert_one::<User>()
+            .with(
+                eq(DB_NAME),
+                eq("users"),
+                function(|x: &User| x.id == 9 && x.name == "Sibaprasad"),
+                always(),
+            )
+            .times(1)
+            .returning(|_, _, _, _| {
+                Ok(InsertOneResult {
+                    inserted_id: String::new(),
+                })
+            });
+        allow_audit(&mut mock_db);
+        mock_db
+    }
+
+    fn compressed_create_request(encoding: &str, body: Vec<u8>) -> Request<Body> {
+        Request::builder()
+            .method("POST")
+            .uri("/user")
+            .header("Content-Type", "application/json")
+            .header("Content-Encoding", encoding)
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    fn user_json() -> Vec<u8> {
+        let user = User {
+            id: 9,
+            name: "Sibaprasad".to_string(),
+            phone: "56565656".to_string(),
+            ..Default::default()
+        };
+        serde_json::to_vec(&user).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_create_user_with_gzip_body() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&user_json()).unwrap();
+        let body = encoder.finish().unwrap();
+        let app = build_router(test_state(create_user_mock()));
+        let res = app
+            .oneshot(compressed_create_request("gzip", body))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn test_create_user_with_brotli_body() {
+        let mut body = Vec::new();
+        {
+            let mut encoder = brotli::CompressorWriter::new(&mut body, 4096, 5, 22);
+            encoder.write_all(&user_json()).unwrap();
+        }
+        let app = build_router(test_state(create_user_mock()));
+        let res = app
+            .oneshot(compressed_create_request("br", body))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+    }
+
+    // refused while reading it, before any of it is decoded
+    #[tokio::test]
+    async fn test_create_user_with_oversized_compressed_body() {
+        let body = vec![0; MAX_DECOMPRESSED_BODY_BYTES as usize + 1];
+        let app = build_router(test_state(AppDatabase::default()));
+        let res = app
+            .oneshot(compressed_create_request("gzip", body))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn test_create_user_with_unsupported_encoding() {
+        let app = build_router(test_state(AppDatabase::default()));
+        let res = app
+            .oneshot(compressed_create_request("compress", user_json()))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
     }
 }
@@ -0,0 +1,51 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use axum::{
+    extract::State,
+    http::{Method, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::error::AppError;
+
+// shared flag telling whether the app is in maintenance mode,
+// while it is set all writes are rejected but reads still go through
+#[derive(Debug, Clone, Default)]
+pub struct MaintenanceMode(Arc<AtomicBool>);
+
+impl MaintenanceMode {
+    pub fn new(enabled: bool) -> Self {
+        Self(Arc::new(AtomicBool::new(enabled)))
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+// how long clients are told to wait before retrying a rejected write
+pub const MAINTENANCE_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+fn is_mutating(method: &Method) -> bool {
+    !matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+// middleware rejecting mutating requests with 503 while maintenance mode is on
+pub async fn maintenance_guard<B>(
+    State(mode): State<MaintenanceMode>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if mode.is_enabled() && is_mutating(req.method()) {
+        let message = "maintenance in progress".to_string();
+        return AppError::ServiceUnavailable(message, MAINTENANCE_RETRY_AFTER).into_response();
+    }
+    next.run(req).await
+}
@@ -0,0 +1,171 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    extract::{MatchedPath, State},
+    http::{header, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+
+use crate::{config::Config, response::ApiResponse};
+
+// requests answered whose path matched no route
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+struct RequestLabels {
+    method: String,
+    route: String,
+    status: u16,
+}
+
+#[derive(Debug, Serialize)]
+struct RequestCount {
+    #[serde(flatten)]
+    labels: RequestLabels,
+    count: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct MetricsSnapshot {
+    http_requests_total: Vec<RequestCount>,
+}
+
+// the request counters, read by both `/metrics` and `/metrics/json`
+#[derive(Debug, Clone, Default)]
+pub struct Metrics(Arc<Mutex<BTreeMap<RequestLabels, u64>>>);
+
+impl Metrics {
+    fn record(&self, labels: RequestLabels) {
+        *self.0.lock().unwrap().entry(labels).or_default() += 1;
+    }
+
+    fn snapshot(&self) -> MetricsSnapshot {
+        let counters = self.0.lock().unwrap();
+        let http_requests_total = counters
+            .iter()
+            .map(|(labels, count)| RequestCount {
+                labels: labels.clone(),
+                count: *count,
+            })
+            .collect();
+        MetricsSnapshot {
+            http_requests_total,
+        }
+    }
+
+    // the counters in the Prometheus text exposition format
+    fn render(&self) -> String {
+        let mut text = String::from(
+            "# HELP http_requests_total Requests answered, by method, route and status.\n\
+             # TYPE http_requests_total counter\n",
+        );
+        for RequestCount { labels, count } in self.snapshot().http_requests_total {
+            let _ = writeln!(
+                text,
+                "http_requests_total{{method=\"{}\",route=\"{}\",status=\"{}\"}} {count}",
+                labels.method, labels.route, labels.status
+            );
+        }
+        text
+    }
+}
+
+// middleware counting the answered requests by route template, so
+// `/user/5` and `/user/6` share a counter
+pub async fn count_requests<B>(
+    State(metrics): State<Metrics>,
+    State(config): State<Arc<Config>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let method = req.method().to_string();
+    let base_path = config.base_path.as_deref().unwrap_or("");
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or(UNMATCHED_ROUTE, |path| {
+            path.as_str()
+                .strip_prefix(base_path)
+                .unwrap_or(path.as_str())
+        })
+        .to_string();
+    let res = next.run(req).await;
+    metrics.record(RequestLabels {
+        method,
+        route,
+        status: res.status().as_u16(),
+    });
+    res
+}
+
+pub async fn prometheus_metrics_handler(State(metrics): State<Metrics>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics.render(),
+    )
+}
+
+// the counters of `/metrics` for the deployments without Prometheus
+pub async fn json_metrics_handler(State(metrics): State<Metrics>) -> impl IntoResponse {
+    ApiResponse::ok(metrics.snapshot())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build_router;
+    use crate::test_support::test_state;
+    use crate::AppDatabase;
+    use axum::{body::Body, http::StatusCode};
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    async fn get(app: &axum::Router, uri: &str) -> Response {
+        let req = Request::builder().uri(uri).body(Body::empty()).unwrap();
+        app.clone().oneshot(req).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_json_metrics_count_requests_by_route() {
+        let app = build_router(test_state(AppDatabase::default()));
+        for _ in 0..2 {
+            let res = get(&app, "/schema/user").await;
+            assert_eq!(res.status(), StatusCode::OK);
+        }
+        let res = get(&app, "/metrics/json").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        let counters = body["data"]["http_requests_total"].as_array().unwrap();
+        assert!(
+            counters.contains(&json!({
+                "method": "GET",
+                "route": "/schema/user",
+                "status": 200,
+                "count": 2,
+            })),
+            "{counters:?}"
+        );
+    }
+
+    #[test]
+    fn test_render_prometheus_text() {
+        let metrics = Metrics::default();
+        let labels = RequestLabels {
+            method: "GET".to_string(),
+            route: "/user/:id".to_string(),
+            status: 404,
+        };
+        metrics.record(labels.clone());
+        metrics.record(labels);
+        assert!(metrics.render().ends_with(
+            "http_requests_total{method=\"GET\",route=\"/user/:id\",status=\"404\"} 2\n"
+        ));
+    }
+}
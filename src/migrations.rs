@@ -0,0 +1,163 @@
+use futures::future::BoxFuture;
+use mockall_double::double;
+use mongodb::{
+    bson::{doc, DateTime},
+    error::Result as MongoResult,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::repo::{name_index, USERS_COLLECTION};
+
+#[double]
+use crate::database::AppDatabase;
+
+pub const MIGRATIONS_COLLECTION: &str = "migrations";
+
+// a named schema change, run once and then recorded so it is skipped on the
+// next startups. migrations are never renamed or removed once shipped
+pub struct Migration {
+    pub name: &'static str,
+    pub run: for<'a> fn(&'a AppDatabase, &'a str) -> BoxFuture<'a, MongoResult<()>>,
+}
+
+// the record of an applied migration
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MigrationRecord {
+    pub name: String,
+    pub applied_at: DateTime,
+}
+
+// every migration, in the order they run
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        name: "0001_backfill_is_active",
+        run: backfill_is_active,
+    },
+    Migration {
+        name: "0002_name_collation_index",
+        run: create_name_index,
+    },
+];
+
+// users stored before `isActive` existed were active
+fn backfill_is_active<'a>(
+    database: &'a AppDatabase,
+    db: &'a str,
+) -> BoxFuture<'a, MongoResult<()>> {
+    Box::pin(async move {
+        let result = database
+            .update_many(
+                db,
+                USERS_COLLECTION,
+                doc! {"isActive": {"$exists": false}},
+                doc! {"$set": {"isActive": true}},
+                None,
+            )
+            .await?;
+        tracing::info!("set isActive on {} users", result.modified_count);
+        Ok(())
+    })
+}
+
+// backs the case-insensitive name lookups of the user list
+fn create_name_index<'a>(database: &'a AppDatabase, db: &'a str) -> BoxFuture<'a, MongoResult<()>> {
+    Box::pin(async move {
+        let name = database
+            .create_index(db, USERS_COLLECTION, name_index())
+            .await?;
+        tracing::info!("created index {name} on {USERS_COLLECTION}");
+        Ok(())
+    })
+}
+
+// the migrations without a record, in their declared order
+pub fn pending<'m>(migrations: &'m [Migration], applied: &[MigrationRecord]) -> Vec<&'m Migration> {
+    migrations
+        .iter()
+        .filter(|migration| !applied.iter().any(|record| record.name == migration.name))
+        .collect()
+}
+
+// run the migrations not applied yet, each is recorded as soon as it
+// succeeds so a failure leaves the earlier ones applied
+pub async fn run_pending(database: &AppDatabase, db: &str, now: DateTime) -> MongoResult<()> {
+    let applied: Vec<MigrationRecord> = database
+        .find_many(db, MIGRATIONS_COLLECTION, None, None)
+        .await?;
+    for migration in pending(MIGRATIONS, &applied) {
+        tracing::info!("running migration {}", migration.name);
+        (migration.run)(database, db).await?;
+        let record = MigrationRecord {
+            name: migration.name.to_string(),
+            applied_at: now,
+        };
+        database
+            .insert_one(db, MIGRATIONS_COLLECTION, &record, None)
+            .await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::NOW;
+
+    fn noop<'a>(_: &'a AppDatabase, _: &'a str) -> BoxFuture<'a, MongoResult<()>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    const FIXTURE: &[Migration] = &[
+        Migration {
+            name: "0001_first",
+            run: noop,
+        },
+        Migration {
+            name: "0002_second",
+            run: noop,
+        },
+        Migration {
+            name: "0003_third",
+            run: noop,
+        },
+    ];
+
+    fn applied(names: &[&str]) -> Vec<MigrationRecord> {
+        names
+            .iter()
+            .map(|name| MigrationRecord {
+                name: name.to_string(),
+                applied_at: NOW,
+            })
+            .collect()
+    }
+
+    fn pending_names(applied: &[MigrationRecord]) -> Vec<&'static str> {
+        pending(FIXTURE, applied)
+            .into_iter()
+            .map(|migration| migration.name)
+            .collect()
+    }
+
+    #[test]
+    fn test_pending_migrations() {
+        assert_eq!(
+            pending_names(&[]),
+            vec!["0001_first", "0002_second", "0003_third"]
+        );
+        assert_eq!(
+            pending_names(&applied(&["0002_second"])),
+            vec!["0001_first", "0003_third"]
+        );
+        // records of migrations no longer declared are ignored
+        assert_eq!(
+            pending_names(&applied(&[
+                "0001_first",
+                "0002_second",
+                "0003_third",
+                "0000_gone"
+            ])),
+            Vec::<&str>::new()
+        );
+    }
+}
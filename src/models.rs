@@ -0,0 +1,278 @@
+use mongodb::bson::{DateTime, Decimal128};
+use serde::{ser::Error as _, Deserialize, Serialize, Serializer};
+use serde_json::json;
+
+use crate::{bson_json::to_plain_json, extract::KnownFields, validation::MAX_NAME_LEN};
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+pub struct User {
+    pub id: i64,
+    pub name: String,
+    pub phone: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(rename = "isActive")]
+    pub is_active: bool,
+    // stored as a BSON decimal, sent to and by clients as a decimal string
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "crate::bson_json::decimal_string"
+    )]
+    pub balance: Option<Decimal128>,
+    // set by the server when the user gets created
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime>,
+    // set by the server whenever the user changes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<DateTime>,
+    // set by the server when the user is soft deleted, e.g. merged into another one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime>,
+}
+
+impl KnownFields for User {
+    const FIELDS: &'static [&'static str] = &[
+        "id",
+        "name",
+        "phone",
+        "email",
+        "isActive",
+        "balance",
+        "created_at",
+        "updated_at",
+        "deleted_at",
+    ];
+}
+
+impl User {
+    // JSON Schema of the payload, written by hand as schemars is not among the
+    // dependencies; the tests check it lists exactly the fields of `FIELDS`
+    pub fn json_schema() -> serde_json::Value {
+        let timestamp = json!({
+            "type": "object",
+            "description": "set by the server, in extended JSON",
+            "properties": {
+                "$date": {
+                    "type": "object",
+                    "properties": {"$numberLong": {"type": "string"}},
+                },
+            },
+        });
+        json!({
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "title": "User",
+            "type": "object",
+            "required": ["id", "name", "phone", "isActive"],
+            "properties": {
+                "id": {"type": "integer", "format": "int64", "minimum": 1},
+                "name": {"type": "string", "minLength": 1, "maxLength": MAX_NAME_LEN},
+                "phone": {"type": "string", "pattern": "^\\+?[0-9]{6,15}$"},
+                "email": {"type": "string", "format": "email"},
+                "isActive": {"type": "boolean"},
+                "balance": {"type": "string", "description": "decimal number"},
+                "created_at": timestamp,
+                "updated_at": timestamp,
+                "deleted_at": timestamp,
+            },
+        })
+    }
+}
+
+// a user as presented to clients, with the fields computed from the stored ones
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserResponse {
+    pub user: User,
+    pub display_name: String,
+}
+
+// the user goes through BSON so its BSON specific values, like the
+// decimals, come out as plain JSON rather than extended JSON
+impl Serialize for UserResponse {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let user = mongodb::bson::to_bson(&self.user).map_err(S::Error::custom)?;
+        let mut value = to_plain_json(user);
+        if let Some(object) = value.as_object_mut() {
+            object.insert("display_name".to_string(), self.display_name.clone().into());
+        }
+        value.serialize(serializer)
+    }
+}
+
+impl KnownFields for UserResponse {
+    const FIELDS: &'static [&'static str] = &[
+        "id",
+        "name",
+        "phone",
+        "email",
+        "isActive",
+        "balance",
+        "created_at",
+        "updated_at",
+        "deleted_at",
+        "display_name",
+    ];
+}
+
+// the v2 shape of a user, the phone and email are grouped under `contact`
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserResponseV2(pub UserResponse);
+
+impl Serialize for UserResponseV2 {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut value = serde_json::to_value(&self.0).map_err(S::Error::custom)?;
+        if let Some(object) = value.as_object_mut() {
+            let mut contact = serde_json::Map::new();
+            for field in ["phone", "email"] {
+                if let Some(value) = object.remove(field) {
+                    contact.insert(field.to_string(), value);
+                }
+            }
+            object.insert("contact".to_string(), contact.into());
+        }
+        value.serialize(serializer)
+    }
+}
+
+impl KnownFields for UserResponseV2 {
+    const FIELDS: &'static [&'static str] = &[
+        "id",
+        "name",
+        "contact",
+        "isActive",
+        "balance",
+        "created_at",
+        "updated_at",
+        "deleted_at",
+        "display_name",
+    ];
+}
+
+impl From<User> for UserResponseV2 {
+    fn from(user: User) -> Self {
+        Self(UserResponse::from(user))
+    }
+}
+
+// only the last four digits of the phone are shown
+fn mask_phone(phone: &str) -> String {
+    let count = phone.chars().count();
+    phone
+        .chars()
+        .enumerate()
+        .map(|(i, c)| if i + 4 < count { '*' } else { c })
+        .collect()
+}
+
+impl From<User> for UserResponse {
+    fn from(user: User) -> Self {
+        let display_name = format!("{} ({})", user.name, mask_phone(&user.phone));
+        Self { user, display_name }
+    }
+}
+
+// kind of change recorded in the audit log
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditOperation {
+    Create,
+    Update,
+    Delete,
+}
+
+// a change made to a user, `old` is missing for creations and `new` for deletions
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AuditEntry {
+    pub user_id: i64,
+    pub operation: AuditOperation,
+    pub old: Option<User>,
+    pub new: Option<User>,
+    pub timestamp: DateTime,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::decimal;
+    use mongodb::bson::doc;
+
+    #[test]
+    fn test_json_schema_lists_every_field() {
+        let schema = User::json_schema();
+        let mut properties: Vec<&str> = schema["properties"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect();
+        let mut fields = User::FIELDS.to_vec();
+        properties.sort_unstable();
+        fields.sort_unstable();
+        assert_eq!(properties, fields);
+        assert_eq!(
+            schema["properties"]["phone"]["pattern"],
+            "^\\+?[0-9]{6,15}$"
+        );
+    }
+
+    #[test]
+    fn test_user_known_fields_match_serialized_fields() {
+        let user = User {
+            email: Some("sibu@example.com".to_string()),
+            balance: Some(decimal(false, 1, 0)),
+            created_at: Some(DateTime::from_millis(0)),
+            updated_at: Some(DateTime::from_millis(0)),
+            deleted_at: Some(DateTime::from_millis(0)),
+            ..Default::default()
+        };
+        let value = serde_json::to_value(user).unwrap();
+        let mut fields: Vec<&str> = value
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect();
+        fields.sort_unstable();
+        let mut known = User::FIELDS.to_vec();
+        known.sort_unstable();
+        assert_eq!(fields, known);
+    }
+
+    #[test]
+    fn test_user_response_display_name() {
+        let user = User {
+            id: 3,
+            name: "Sibaprasad".to_string(),
+            phone: "+9156565656".to_string(),
+            ..Default::default()
+        };
+        let value = serde_json::to_value(UserResponse::from(user)).unwrap();
+        assert_eq!(value["display_name"], "Sibaprasad (*******5656)");
+        assert_eq!(value["name"], "Sibaprasad");
+        assert_eq!(value["phone"], "+9156565656");
+    }
+
+    #[test]
+    fn test_user_response_renders_decimal_as_plain_json() {
+        let stored = doc! {
+            "id": 3,
+            "name": "Sibaprasad",
+            "phone": "56565656",
+            "isActive": true,
+            "balance": decimal(false, 1250, -2),
+        };
+        let user: User = mongodb::bson::from_document(stored).unwrap();
+        assert_eq!(user.balance, Some(decimal(false, 1250, -2)));
+        let value = serde_json::to_value(UserResponse::from(user)).unwrap();
+        assert_eq!(value["balance"], "12.50");
+        assert_eq!(value["id"], 3);
+        assert_eq!(value["isActive"], true);
+    }
+
+    #[test]
+    fn test_user_response_known_fields() {
+        let mut known = User::FIELDS.to_vec();
+        known.push("display_name");
+        assert_eq!(UserResponse::FIELDS, known.as_slice());
+    }
+}
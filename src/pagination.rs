@@ -0,0 +1,170 @@
+use mockall_double::double;
+use mongodb::{
+    bson::{doc, Document},
+    error::Result as MongoResult,
+    options::{CountOptions, FindOptions},
+};
+use serde::{de::DeserializeOwned, Deserialize};
+
+use crate::error::AppError;
+
+#[double]
+use crate::database::AppDatabase;
+
+// page size used when the client does not send `limit`, unless configured otherwise
+pub const DEFAULT_PAGE_LIMIT: i64 = 20;
+// largest page size a client can ask for
+pub const MAX_PAGE_LIMIT: i64 = 100;
+
+// `?limit=&skip=&with_total=&sort=` query parameters of the list endpoints
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct PageParams {
+    pub limit: Option<i64>,
+    pub skip: Option<u64>,
+    // counting a huge collection is expensive, clients can opt out of it
+    pub with_total: Option<bool>,
+    // comma separated fields, a leading `-` sorts that field descending
+    pub sort: Option<String>,
+}
+
+impl PageParams {
+    // the requested page size, `default_limit` when missing, capped either way
+    pub fn limit(&self, default_limit: i64) -> i64 {
+        self.limit.unwrap_or(default_limit).clamp(1, MAX_PAGE_LIMIT)
+    }
+
+    pub fn with_total(&self) -> bool {
+        self.with_total.unwrap_or(true)
+    }
+
+    // the requested sort, restricted to `fields`; without one the documents
+    // come back in natural order, which shifts between pages, so callers
+    // pass a default sort on a unique field
+    pub fn sort(&self, fields: &[&str], default: Document) -> Result<Document, AppError> {
+        let Some(sort) = self.sort.as_deref().filter(|sort| !sort.trim().is_empty()) else {
+            return Ok(default);
+        };
+        let mut document = Document::new();
+        for field in sort.split(',').map(str::trim) {
+            let (name, direction) = match field.strip_prefix('-') {
+                Some(name) => (name, -1),
+                None => (field, 1),
+            };
+            if !fields.contains(&name) {
+                return Err(AppError::BadRequest(format!("cannot sort by `{name}`")));
+            }
+            document.insert(name, direction);
+        }
+        Ok(document)
+    }
+
+    pub fn find_options(&self, default_limit: i64, sort: Document) -> FindOptions {
+        FindOptions::builder()
+            .limit(self.limit(default_limit))
+            .skip(self.skip.unwrap_or(0))
+            .sort(sort)
+            .build()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    // missing when the count was skipped
+    pub total: Option<u64>,
+}
+
+// the total of a page counts under the collation of its find, if any
+pub fn count_options(options: &FindOptions) -> Option<CountOptions> {
+    let collation = options.collation.clone()?;
+    Some(CountOptions::builder().collation(collation).build())
+}
+
+// fetch one page of documents and, with `with_total`, the total number of
+// matching documents, both queries run concurrently to save a round trip
+pub async fn find_page<T>(
+    database: &AppDatabase,
+    db: &str,
+    coll: &str,
+    filter: Document,
+    options: FindOptions,
+    with_total: bool,
+) -> MongoResult<Page<T>>
+where
+    T: DeserializeOwned + Unpin + Send + Sync + 'static,
+{
+    let count_options = count_options(&options);
+    let find = database.find_many::<T>(db, coll, Some(filter.clone()), Some(options));
+    if !with_total {
+        return Ok(Page {
+            items: find.await?,
+            total: None,
+        });
+    }
+    let (items, total) = tokio::join!(
+        find,
+        database.count_documents(db, coll, Some(filter), count_options)
+    );
+    Ok(Page {
+        items: items?,
+        total: Some(total?),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_page_params_limit() {
+        let default_limit = crate::config::Config::default().default_page_limit;
+        assert_eq!(default_limit, DEFAULT_PAGE_LIMIT);
+        assert_eq!(
+            PageParams::default().limit(default_limit),
+            DEFAULT_PAGE_LIMIT
+        );
+        let params = PageParams {
+            limit: Some(1000),
+            ..Default::default()
+        };
+        assert_eq!(params.limit(default_limit), MAX_PAGE_LIMIT);
+        let params = PageParams {
+            limit: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(params.limit(default_limit), 1);
+    }
+
+    #[test]
+    fn test_page_params_configured_default_limit() {
+        assert_eq!(PageParams::default().limit(50), 50);
+        // a configured default is capped like a requested limit
+        assert_eq!(PageParams::default().limit(500), MAX_PAGE_LIMIT);
+        let params = PageParams {
+            limit: Some(5),
+            ..Default::default()
+        };
+        assert_eq!(params.limit(50), 5);
+    }
+
+    #[test]
+    fn test_page_params_sort() {
+        let fields = &["id", "name"];
+        let default = doc! {"id": 1};
+        let params = PageParams::default();
+        assert_eq!(params.sort(fields, default.clone()).unwrap(), default);
+        let params = PageParams {
+            sort: Some("-name, id".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            params.sort(fields, default.clone()).unwrap(),
+            doc! {"name": -1, "id": 1}
+        );
+        let params = PageParams {
+            sort: Some("phone".to_string()),
+            ..Default::default()
+        };
+        assert!(params.sort(fields, default).is_err());
+    }
+}
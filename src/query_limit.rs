@@ -0,0 +1,27 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::State,
+    http::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::{config::Config, error::AppError};
+
+// middleware rejecting requests whose query string exceeds the configured length
+pub async fn limit_query_length<B>(
+    State(config): State<Arc<Config>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let query_len = req.uri().query().map_or(0, str::len);
+    if query_len > config.max_query_bytes {
+        let message = format!(
+            "query string must not exceed {} bytes",
+            config.max_query_bytes
+        );
+        return AppError::UriTooLong(message).into_response();
+    }
+    next.run(req).await
+}
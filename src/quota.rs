@@ -0,0 +1,156 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    extract::State,
+    http::{HeaderName, HeaderValue, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use mongodb::bson::DateTime;
+
+use crate::{client_ip::client_ip, clock::Clock, config::Config, error::AppError};
+
+pub const QUOTA_LIMIT_HEADER: &str = "x-quota-limit";
+// unix time, in seconds, at which the quota is available again
+pub const QUOTA_RESET_HEADER: &str = "x-quota-reset";
+
+const MILLIS_PER_DAY: i64 = 24 * 60 * 60 * 1000;
+
+// requests counted per client address for the current UTC day
+#[derive(Debug, Clone, Default)]
+pub struct DailyQuota(Arc<Mutex<QuotaUsage>>);
+
+#[derive(Debug, Default)]
+struct QuotaUsage {
+    day: i64,
+    counts: HashMap<IpAddr, u64>,
+}
+
+impl DailyQuota {
+    // count a request of `ip`, once the quota is used up it returns the
+    // time at which it is reset instead
+    pub fn hit(&self, ip: IpAddr, now: DateTime, quota: u64) -> Result<(), DateTime> {
+        let day = now.timestamp_millis().div_euclid(MILLIS_PER_DAY);
+        let mut usage = self.0.lock().unwrap();
+        // a new day starts every client over, which also drops the
+        // addresses of the days before
+        if usage.day != day {
+            usage.day = day;
+            usage.counts.clear();
+        }
+        let count = usage.counts.entry(ip).or_default();
+        if *count >= quota {
+            return Err(DateTime::from_millis((day + 1) * MILLIS_PER_DAY));
+        }
+        *count += 1;
+        Ok(())
+    }
+}
+
+// middleware answering 429 once a client used up the daily request quota,
+// requests whose client address is unknown are let through
+pub async fn enforce_daily_quota<B>(
+    State(quota): State<DailyQuota>,
+    State(config): State<Arc<Config>>,
+    State(clock): State<Arc<dyn Clock>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let Some(limit) = config.daily_request_quota else {
+        return next.run(req).await;
+    };
+    let Some(ip) = client_ip(&req, config.trust_proxy) else {
+        return next.run(req).await;
+    };
+    let now = clock.now();
+    let Err(reset) = quota.hit(ip, now, limit) else {
+        return next.run(req).await;
+    };
+    let message = format!("daily quota of {limit} requests exceeded");
+    let wait = Duration::from_millis((reset.timestamp_millis() - now.timestamp_millis()) as u64);
+    let mut res = AppError::TooManyRequests(message, wait).into_response();
+    let headers = res.headers_mut();
+    headers.insert(
+        HeaderName::from_static(QUOTA_LIMIT_HEADER),
+        HeaderValue::from(limit),
+    );
+    headers.insert(
+        HeaderName::from_static(QUOTA_RESET_HEADER),
+        HeaderValue::from(reset.timestamp_millis() / 1000),
+    );
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::*;
+    use crate::test_support::{test_state, NOW};
+    use crate::AppDatabase;
+    use axum::{
+        body::Body,
+        extract::ConnectInfo,
+        http::{header, StatusCode},
+        routing::get,
+        Router,
+    };
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_daily_quota_resets_next_day() {
+        let quota = DailyQuota::default();
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let other: IpAddr = "10.0.0.2".parse().unwrap();
+        assert!(quota.hit(ip, NOW, 2).is_ok());
+        assert!(quota.hit(ip, NOW, 2).is_ok());
+        let reset = quota.hit(ip, NOW, 2).unwrap_err();
+        assert_eq!(
+            reset,
+            DateTime::parse_rfc3339_str("2023-02-11T00:00:00Z").unwrap()
+        );
+        assert!(quota.hit(other, NOW, 2).is_ok());
+        assert!(quota.hit(ip, reset, 2).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_daily_quota_exceeded() {
+        let mut state = test_state(AppDatabase::default());
+        state.config = Arc::new(Config {
+            daily_request_quota: Some(2),
+            ..Default::default()
+        });
+        let app = Router::new()
+            .route("/", get(|| async { "ok" }))
+            .layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                enforce_daily_quota,
+            ))
+            .with_state(state);
+        let peer = SocketAddr::from(([10, 0, 0, 1], 4000));
+        let request = || {
+            let mut req = Request::builder().uri("/").body(Body::empty()).unwrap();
+            req.extensions_mut().insert(ConnectInfo(peer));
+            req
+        };
+        for _ in 0..2 {
+            let res = app.clone().oneshot(request()).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+        }
+        let res = app.oneshot(request()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        let headers = res.headers();
+        // NOW is 2023-02-10T03:33:20Z, the quota resets at midnight
+        assert_eq!(headers[header::RETRY_AFTER], "73600");
+        assert_eq!(headers[QUOTA_LIMIT_HEADER], "2");
+        assert_eq!(headers[QUOTA_RESET_HEADER], "1676073600");
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["message"], "daily quota of 2 requests exceeded");
+    }
+}
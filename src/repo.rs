@@ -0,0 +1,1098 @@
+use std::{convert::Infallible, future::Future, sync::Arc, time::Duration};
+
+use anyhow::Context;
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts},
+    http::request::Parts,
+};
+use futures::{stream::BoxStream, StreamExt};
+use mockall_double::double;
+use mongodb::{
+    bson::{doc, DateTime, Document},
+    error::{Error as MongoError, ErrorKind, Result as MongoResult},
+    options::{
+        AggregateOptions, Collation, CollationStrength, FindOneOptions, FindOptions, IndexOptions,
+        UpdateOptions,
+    },
+    IndexModel,
+};
+use serde::{de::DeserializeOwned, Deserialize};
+use tokio::time::{timeout_at, Instant};
+use tower_http::request_id::RequestId;
+
+use crate::{
+    audit,
+    config::Config,
+    crypto::FieldCipher,
+    database::{is_reconnecting, InsertOneResult, UpdateResult},
+    error::AppError,
+    models::{AuditEntry, AuditOperation, User},
+    pagination::{count_options, find_page, Page},
+    stale_cache::StaleUsers,
+};
+
+#[double]
+use crate::database::AppDatabase;
+
+// a document which does not deserialize into a `User` is reported as such,
+// naming what was looked up, rather than as a generic driver error
+fn read_error(err: MongoError, lookup: impl FnOnce() -> String) -> AppError {
+    match err.kind.as_ref() {
+        ErrorKind::BsonDeserialization(cause) => {
+            AppError::StoredDocument(format!("{}: {cause}", lookup()))
+        }
+        _ => err.into(),
+    }
+}
+
+pub const USERS_COLLECTION: &str = "users";
+pub const NAME_INDEX: &str = "name_case_insensitive";
+
+// compares names ignoring their case, `strength` 2 looks at the letters and
+// their accents but not at the case
+pub fn name_collation() -> Collation {
+    Collation::builder()
+        .locale("en")
+        .strength(CollationStrength::Secondary)
+        .build()
+}
+
+// the index serving the name lookups, a query only uses it when it runs
+// under the same collation
+pub fn name_index() -> IndexModel {
+    let options = IndexOptions::builder()
+        .name(NAME_INDEX.to_string())
+        .collation(name_collation())
+        .build();
+    IndexModel::builder()
+        .keys(doc! {"name": 1})
+        .options(options)
+        .build()
+}
+
+// output document of the facets aggregation
+#[derive(Debug, Deserialize)]
+struct FacetResult {
+    #[serde(default)]
+    items: Vec<User>,
+    #[serde(default)]
+    by_active: Vec<ActiveGroup>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ActiveGroup {
+    // missing on documents without `isActive`
+    #[serde(rename = "_id")]
+    is_active: Option<bool>,
+    count: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserFacets {
+    pub items: Vec<User>,
+    pub active_count: u64,
+    pub inactive_count: u64,
+}
+
+// access to the users collection, so the handlers do not have to
+// repeat the database and collection names on every call. with a phone
+// cipher configured the phone numbers are encrypted on the way in and
+// decrypted on the way out, the handlers only ever see plaintext
+#[derive(Clone)]
+pub struct UserRepo {
+    database: Arc<AppDatabase>,
+    config: Arc<Config>,
+    phone_cipher: Option<Arc<FieldCipher>>,
+    // sent as the `comment` of the reads so they can be traced back to the
+    // request in the server logs, see `request_comment`
+    comment: Option<String>,
+    stale_users: StaleUsers,
+    // the soft deleted users are left out of the reads and writes unless
+    // this is set, see `with_deleted`
+    include_deleted: bool,
+}
+
+// a page cut short by `UserRepo::page_within`
+#[derive(Debug)]
+pub struct PartialPage<T> {
+    pub page: Page<T>,
+    pub partial: bool,
+}
+
+// result of a read that may be served from `StaleUsers`
+#[derive(Debug)]
+pub struct Fetched<T> {
+    pub value: T,
+    // the value is the last known copy, the database could not be reached
+    pub stale: bool,
+}
+
+// the handlers take the repo as an extractor to get it tagged with the
+// request id set by the `x-request-id` layer
+#[async_trait]
+impl<S> FromRequestParts<S> for UserRepo
+where
+    UserRepo: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let mut repo = UserRepo::from_ref(state);
+        repo.comment = request_comment(parts);
+        Ok(repo)
+    }
+}
+
+fn request_comment(parts: &Parts) -> Option<String> {
+    let request_id = parts.extensions.get::<RequestId>()?;
+    let request_id = request_id.header_value().to_str().ok()?;
+    Some(format!("request {request_id}"))
+}
+
+impl UserRepo {
+    pub fn new(
+        database: Arc<AppDatabase>,
+        config: Arc<Config>,
+        phone_cipher: Option<Arc<FieldCipher>>,
+    ) -> Self {
+        Self {
+            database,
+            config,
+            phone_cipher,
+            comment: None,
+            stale_users: StaleUsers::default(),
+            include_deleted: false,
+        }
+    }
+
+    pub fn with_stale_users(mut self, stale_users: StaleUsers) -> Self {
+        self.stale_users = stale_users;
+        self
+    }
+
+    // the repo seeing the soft deleted users as well, for restoring and
+    // merging them
+    pub fn with_deleted(mut self) -> Self {
+        self.include_deleted = true;
+        self
+    }
+
+    // `filter` restricted to the users which are not soft deleted, a
+    // `deleted_at` condition of the caller is replaced
+    fn live(&self, mut filter: Document) -> Document {
+        if !self.include_deleted {
+            filter.insert("deleted_at", doc! {"$exists": false});
+        }
+        filter
+    }
+
+    fn find_options(&self, options: Option<FindOptions>) -> Option<FindOptions> {
+        let Some(comment) = &self.comment else {
+            return options;
+        };
+        let mut options = options.unwrap_or_default();
+        options.comment = Some(comment.clone());
+        Some(options)
+    }
+
+    fn db(&self) -> &str {
+        &self.config.db_name
+    }
+
+    // run a read, and once more when the connection dropped under it and
+    // the driver is reconnecting, so the client does not see the hiccup
+    async fn read<T, F, Fut>(&self, operation: &str, query: F) -> MongoResult<T>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = MongoResult<T>>,
+    {
+        match query().await {
+            Err(err) if self.config.retry_reads && is_reconnecting(&err) => {
+                tracing::warn!(
+                    "{operation} on {USERS_COLLECTION} lost its connection, retrying: {err}"
+                );
+                query().await
+            }
+            result => result,
+        }
+    }
+
+    fn seal(&self, user: &User) -> User {
+        let mut user = user.clone();
+        if let Some(cipher) = &self.phone_cipher {
+            user.phone = cipher.encrypt(&user.phone);
+        }
+        user
+    }
+
+    fn unseal(&self, mut user: User) -> Result<User, AppError> {
+        if let Some(cipher) = &self.phone_cipher {
+            user.phone = cipher
+                .decrypt(&user.phone)
+                .with_context(|| format!("failed to decrypt the phone of user {}", user.id))
+                .map_err(AppError::Internal)?;
+        }
+        Ok(user)
+    }
+
+    pub async fn get(&self, id: i64) -> Result<Option<User>, AppError> {
+        self.find_one_with(doc! {"id": id}, || format!("user {id}"))
+            .await
+    }
+
+    // `get`, falling back to the last copy of the user while the database is
+    // unavailable when `serve_stale_on_error` is set
+    pub async fn get_allow_stale(&self, id: i64) -> Result<Fetched<Option<User>>, AppError> {
+        if !self.config.serve_stale_on_error {
+            let value = self.get(id).await?;
+            return Ok(Fetched {
+                value,
+                stale: false,
+            });
+        }
+        match self.get(id).await {
+            Ok(value) => {
+                self.stale_users.update(id, value.as_ref());
+                Ok(Fetched {
+                    value,
+                    stale: false,
+                })
+            }
+            Err(err @ AppError::ServiceUnavailable(..)) => match self.stale_users.get(id) {
+                Some(user) => {
+                    tracing::warn!("serving a stale copy of user {id}: {}", err.message(false));
+                    Ok(Fetched {
+                        value: Some(user),
+                        stale: true,
+                    })
+                }
+                None => Err(err),
+            },
+            Err(err) => Err(err),
+        }
+    }
+
+    pub async fn find_one(&self, filter: Document) -> Result<Option<User>, AppError> {
+        let lookup = format!("a user matching {filter:?}");
+        self.find_one_with(filter, || lookup).await
+    }
+
+    // the user `id` read as `T`, for the handlers needing fields stored on
+    // the user document which are not part of `User`
+    pub async fn get_as<T>(&self, id: i64) -> Result<Option<T>, AppError>
+    where
+        T: DeserializeOwned + Unpin + Send + Sync + 'static,
+    {
+        self.find_document(doc! {"id": id}, || format!("user {id}"))
+            .await
+    }
+
+    async fn find_one_with(
+        &self,
+        filter: Document,
+        lookup: impl FnOnce() -> String,
+    ) -> Result<Option<User>, AppError> {
+        let user = self.find_document::<User>(filter, lookup).await?;
+        user.map(|user| self.unseal(user)).transpose()
+    }
+
+    async fn find_document<T>(
+        &self,
+        filter: Document,
+        lookup: impl FnOnce() -> String,
+    ) -> Result<Option<T>, AppError>
+    where
+        T: DeserializeOwned + Unpin + Send + Sync + 'static,
+    {
+        let options = self.comment.as_ref().map(|comment| {
+            let mut options = FindOneOptions::default();
+            options.comment = Some(comment.clone());
+            options
+        });
+        let filter = self.live(filter);
+        self.read("find_one", || {
+            self.database.find_one::<T>(
+                self.db(),
+                USERS_COLLECTION,
+                Some(filter.clone()),
+                options.clone(),
+            )
+        })
+        .await
+        .map_err(|err| read_error(err, lookup))
+    }
+
+    pub async fn find(
+        &self,
+        filter: Option<Document>,
+        options: Option<FindOptions>,
+    ) -> Result<Vec<User>, AppError> {
+        let options = self.find_options(options);
+        let filter = Some(self.live(filter.unwrap_or_default()));
+        let users = self
+            .read("find_many", || {
+                self.database.find_many::<User>(
+                    self.db(),
+                    USERS_COLLECTION,
+                    filter.clone(),
+                    options.clone(),
+                )
+            })
+            .await
+            .map_err(|err| read_error(err, || format!("users matching {filter:?}")))?;
+        users.into_iter().map(|user| self.unseal(user)).collect()
+    }
+
+    // see `AppDatabase::find_stream`
+    pub async fn stream(
+        &self,
+        filter: Option<Document>,
+        options: Option<FindOptions>,
+    ) -> Result<BoxStream<'static, Result<User, AppError>>, AppError> {
+        let options = self.find_options(options);
+        let filter = Some(self.live(filter.unwrap_or_default()));
+        let users = self
+            .database
+            .find_stream::<User>(self.db(), USERS_COLLECTION, filter.clone(), options)
+            .await
+            .map_err(|err| read_error(err, || format!("users matching {filter:?}")))?;
+        let repo = self.clone();
+        let users = users.map(move |user| {
+            let user =
+                user.map_err(|err| read_error(err, || format!("a user matching {filter:?}")))?;
+            repo.unseal(user)
+        });
+        Ok(users.boxed())
+    }
+
+    pub async fn page(
+        &self,
+        filter: Document,
+        options: FindOptions,
+        with_total: bool,
+    ) -> Result<Page<User>, AppError> {
+        let options = self.find_options(Some(options)).unwrap_or_default();
+        let page = find_page::<User>(
+            &self.database,
+            self.db(),
+            USERS_COLLECTION,
+            self.live(filter),
+            options,
+            with_total,
+        )
+        .await
+        .map_err(|err| read_error(err, || "a page of users".to_string()))?;
+        Ok(Page {
+            items: page
+                .items
+                .into_iter()
+                .map(|user| self.unseal(user))
+                .collect::<Result<_, _>>()?,
+            total: page.total,
+        })
+    }
+
+    // `page`, reading the cursor for at most `budget`. once it runs out the
+    // users read so far are returned with `partial` set; the total is only
+    // given when it was counted in time as well
+    pub async fn page_within(
+        &self,
+        filter: Document,
+        options: FindOptions,
+        with_total: bool,
+        budget: Duration,
+    ) -> Result<PartialPage<User>, AppError> {
+        let deadline = Instant::now() + budget;
+        let count_options = count_options(&options);
+        let filter = self.live(filter);
+        let read = async {
+            let mut items = Vec::new();
+            let Ok(users) =
+                timeout_at(deadline, self.stream(Some(filter.clone()), Some(options))).await
+            else {
+                return Ok::<_, AppError>((items, true));
+            };
+            let mut users = users?;
+            loop {
+                match timeout_at(deadline, users.next()).await {
+                    Ok(Some(user)) => items.push(user?),
+                    Ok(None) => return Ok((items, false)),
+                    Err(_) => return Ok((items, true)),
+                }
+            }
+        };
+        let count = async {
+            if !with_total {
+                return Ok(None);
+            }
+            let count = self.database.count_documents(
+                self.db(),
+                USERS_COLLECTION,
+                Some(filter.clone()),
+                count_options,
+            );
+            match timeout_at(deadline, count).await {
+                Ok(total) => total.map(Some),
+                Err(_) => Ok(None),
+            }
+        };
+        let (read, total) = tokio::join!(read, count);
+        let (items, partial) = read?;
+        let total = total.map_err(|err| read_error(err, || "the number of users".to_string()))?;
+        Ok(PartialPage {
+            page: Page { items, total },
+            partial: partial || (with_total && total.is_none()),
+        })
+    }
+
+    // one page of users and the number of active and inactive users,
+    // computed by a single `$facet` aggregation
+    pub async fn facets(
+        &self,
+        sort: Document,
+        skip: u64,
+        limit: i64,
+    ) -> Result<UserFacets, AppError> {
+        let pipeline = vec![
+            doc! {"$match": self.live(Document::new())},
+            doc! {
+                "$facet": {
+                    "items": [{"$sort": sort}, {"$skip": skip as i64}, {"$limit": limit}],
+                    "by_active": [{"$group": {"_id": "$isActive", "count": {"$sum": 1}}}],
+                }
+            },
+        ];
+        let options = self.comment.as_ref().map(|comment| {
+            let mut options = AggregateOptions::default();
+            options.comment = Some(comment.clone());
+            options
+        });
+        let mut results = self
+            .read("aggregate", || {
+                self.database.aggregate(
+                    self.db(),
+                    USERS_COLLECTION,
+                    pipeline.clone(),
+                    options.clone(),
+                )
+            })
+            .await?;
+        // `$facet` always outputs exactly one document
+        let result = results.pop().unwrap_or_default();
+        let result: FacetResult = mongodb::bson::from_document(result)
+            .map_err(|err| AppError::StoredDocument(format!("user facets: {err}")))?;
+        let count = |active: bool| {
+            result
+                .by_active
+                .iter()
+                .filter(|group| group.is_active == Some(active))
+                .map(|group| group.count)
+                .sum()
+        };
+        Ok(UserFacets {
+            active_count: count(true),
+            inactive_count: count(false),
+            items: result
+                .items
+                .into_iter()
+                .map(|user| self.unseal(user))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    pub async fn estimated_count(&self) -> MongoResult<u64> {
+        self.read("estimated_document_count", || {
+            self.database
+                .estimated_document_count(self.db(), USERS_COLLECTION)
+        })
+        .await
+    }
+
+    // one more than the highest id in use, the soft deleted users still hold
+    // on to theirs
+    pub async fn next_id(&self) -> Result<i64, AppError> {
+        let options = FindOptions::builder()
+            .sort(doc! {"id": -1})
+            .limit(1)
+            .build();
+        let highest = self
+            .clone()
+            .with_deleted()
+            .find(None, Some(options))
+            .await?;
+        Ok(highest.first().map_or(1, |user| user.id + 1))
+    }
+
+    pub async fn insert(&self, user: &User) -> MongoResult<InsertOneResult> {
+        self.database
+            .insert_one(self.db(), USERS_COLLECTION, &self.seal(user), None)
+            .await
+    }
+
+    // see `AppDatabase::insert_many`, the documents are inserted unordered
+    pub async fn insert_many(&self, users: &[User]) -> MongoResult<()> {
+        let sealed: Vec<User> = users.iter().map(|user| self.seal(user)).collect();
+        self.database
+            .insert_many(self.db(), USERS_COLLECTION, &sealed, false)
+            .await
+    }
+
+    // see `AppDatabase::insert_or_get`
+    pub async fn insert_or_get(&self, user: &User) -> MongoResult<bool> {
+        let filter = doc! {"id": user.id};
+        self.database
+            .insert_or_get(self.db(), USERS_COLLECTION, filter, &self.seal(user))
+            .await
+    }
+
+    // a phone set by the update is encrypted before it is written
+    fn seal_update(&self, update: &mut Document) {
+        if let (Some(cipher), Ok(set)) = (&self.phone_cipher, update.get_document_mut("$set")) {
+            if let Ok(phone) = set.get_str("phone") {
+                let encrypted = cipher.encrypt(phone);
+                set.insert("phone", encrypted);
+            }
+        }
+    }
+
+    pub async fn update(&self, id: i64, mut update: Document) -> MongoResult<UpdateResult> {
+        self.seal_update(&mut update);
+        let filter = self.live(doc! {"id": id});
+        self.database
+            .update_one(self.db(), USERS_COLLECTION, filter, update, None)
+            .await
+    }
+
+    // `update` which only writes when a field of its `$set` other than
+    // `updated_at` differs from the stored value, or a field of its `$unset`
+    // is stored, so the modified count tells whether the user changed. an
+    // encrypted phone never equals the plaintext and always counts as changed
+    pub async fn update_changed(&self, id: i64, update: Document) -> MongoResult<UpdateResult> {
+        let mut differs: Vec<Document> = Vec::new();
+        if let Ok(set) = update.get_document("$set") {
+            for (field, value) in set.iter().filter(|(field, _)| *field != "updated_at") {
+                differs.push(doc! {field: {"$ne": value}});
+            }
+        }
+        if let Ok(unset) = update.get_document("$unset") {
+            for field in unset.keys() {
+                differs.push(doc! {field: {"$exists": true}});
+            }
+        }
+        // `$or` refuses an empty list, such an update cannot change anything
+        if !differs.is_empty() {
+            let mut update = update;
+            self.seal_update(&mut update);
+            let filter = self.live(doc! {"id": id, "$or": differs});
+            let result = self
+                .database
+                .update_one(self.db(), USERS_COLLECTION, filter, update, None)
+                .await?;
+            if result.matched_count > 0 {
+                return Ok(result);
+            }
+        }
+        // nothing matched, the user is either missing or already as patched
+        let filter = self.live(doc! {"id": id});
+        let count = self
+            .database
+            .count_documents(self.db(), USERS_COLLECTION, Some(filter), None)
+            .await?;
+        Ok(UpdateResult {
+            matched_count: count.min(1),
+            modified_count: 0,
+        })
+    }
+
+    // undo a soft delete, users which are not deleted are not matched
+    pub async fn restore(&self, id: i64, now: DateTime) -> MongoResult<UpdateResult> {
+        let filter = doc! {"id": id, "deleted_at": {"$ne": null}};
+        let update = doc! {"$unset": {"deleted_at": ""}, "$set": {"updated_at": now}};
+        self.database
+            .update_one(self.db(), USERS_COLLECTION, filter, update, None)
+            .await
+    }
+
+    // `update` of the elements of an array field picked by `array_filters`,
+    // e.g. `{"$set": {"roles.$[role]": ..}}` with the filter `{"role": ..}`
+    pub async fn update_elements(
+        &self,
+        filter: Document,
+        mut update: Document,
+        array_filters: Vec<Document>,
+    ) -> MongoResult<UpdateResult> {
+        self.seal_update(&mut update);
+        let options = UpdateOptions::builder()
+            .array_filters(array_filters)
+            .build();
+        let filter = self.live(filter);
+        self.database
+            .update_one(self.db(), USERS_COLLECTION, filter, update, Some(options))
+            .await
+    }
+
+    // see `AppDatabase::update_in_transaction`, the updates are keyed by user id
+    pub async fn update_in_transaction(
+        &self,
+        updates: Vec<(i64, Document)>,
+    ) -> MongoResult<Vec<UpdateResult>> {
+        let updates = updates
+            .into_iter()
+            .map(|(id, mut update)| {
+                self.seal_update(&mut update);
+                (self.live(doc! {"id": id}), update)
+            })
+            .collect();
+        self.database
+            .update_in_transaction(self.db(), USERS_COLLECTION, updates)
+            .await
+    }
+
+    pub async fn update_many(
+        &self,
+        filter: Document,
+        mut update: Document,
+    ) -> MongoResult<UpdateResult> {
+        self.seal_update(&mut update);
+        let filter = self.live(filter);
+        self.database
+            .update_many(self.db(), USERS_COLLECTION, filter, update, None)
+            .await
+    }
+
+    // hard delete of the live users, the stale copies of the users go as
+    // well, soft deleted users are left alone like by every other read
+    pub async fn delete_by_ids(&self, ids: &[i64]) -> MongoResult<u64> {
+        let filter = self.live(doc! {"id": {"$in": ids}});
+        let deleted_count = self
+            .database
+            .delete_many(self.db(), USERS_COLLECTION, filter, None)
+            .await?;
+        for id in ids {
+            self.stale_users.update(*id, None);
+        }
+        Ok(deleted_count)
+    }
+
+    // the audit log holds user documents as well, so they get sealed too
+    pub async fn record_audit(&self, entry: &AuditEntry) {
+        let entry = AuditEntry {
+            old: entry.old.as_ref().map(|user| self.seal(user)),
+            new: entry.new.as_ref().map(|user| self.seal(user)),
+            ..entry.clone()
+        };
+        audit::record(&self.database, self.db(), &entry).await;
+    }
+
+    // run a write changing the user `id` and record it in the audit log, the
+    // user is read before and after the write so the entry holds both versions
+    pub async fn audited<F>(
+        &self,
+        id: i64,
+        now: DateTime,
+        write: F,
+    ) -> Result<UpdateResult, AppError>
+    where
+        F: Future<Output = MongoResult<UpdateResult>>,
+    {
+        let old = self.get(id).await?;
+        let result = write.await?;
+        if result.modified_count > 0 {
+            let entry = AuditEntry {
+                user_id: id,
+                operation: AuditOperation::Update,
+                old,
+                new: self.get(id).await?,
+                timestamp: now,
+            };
+            self.record_audit(&entry).await;
+        }
+        Ok(result)
+    }
+
+    // every recorded change of the user, oldest first, with the users unsealed
+    pub async fn history(&self, id: i64) -> Result<Vec<AuditEntry>, AppError> {
+        let options = FindOptions::builder().sort(doc! {"timestamp": 1}).build();
+        let entries = self
+            .database
+            .find_many::<AuditEntry>(
+                self.db(),
+                audit::AUDIT_COLLECTION,
+                Some(doc! {"user_id": id}),
+                self.find_options(Some(options)),
+            )
+            .await?;
+        entries
+            .into_iter()
+            .map(|entry| {
+                Ok(AuditEntry {
+                    old: entry.old.map(|user| self.unseal(user)).transpose()?,
+                    new: entry.new.map(|user| self.unseal(user)).transpose()?,
+                    ..entry
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::EncryptionKey;
+    use crate::database::DB_NAME;
+    use crate::test_support::live;
+    use mockall::predicate::{always, eq};
+
+    #[test]
+    fn test_name_index_carries_collation() {
+        let index = name_index();
+        assert_eq!(index.keys, doc! {"name": 1});
+        let options = index.options.unwrap();
+        assert_eq!(options.name.as_deref(), Some(NAME_INDEX));
+        let collation = mongodb::bson::to_document(&options.collation.unwrap()).unwrap();
+        assert_eq!(collation, doc! {"locale": "en", "strength": 2});
+    }
+
+    fn repo(mock_db: AppDatabase) -> UserRepo {
+        UserRepo::new(Arc::new(mock_db), Arc::new(Config::default()), None)
+    }
+
+    fn encrypting_repo(mock_db: AppDatabase) -> UserRepo {
+        let cipher = FieldCipher::new(&test_key());
+        UserRepo::new(
+            Arc::new(mock_db),
+            Arc::new(Config::default()),
+            Some(Arc::new(cipher)),
+        )
+    }
+
+    fn test_key() -> EncryptionKey {
+        base64::encode([3; 32]).parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_get_retries_once_after_connection_loss() {
+        let calls = std::sync::atomic::AtomicUsize::new(0);
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_find_one::<User>()
+            .with(
+                eq(DB_NAME),
+                eq("users"),
+                eq(Some(live(doc! {"id": 4_i64}))),
+                always(),
+            )
+            .times(2)
+            .returning(move |_, _, _, _| {
+                if calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst) == 0 {
+                    let lost = std::io::Error::from(std::io::ErrorKind::ConnectionReset);
+                    Err(lost.into())
+                } else {
+                    Ok(Some(User {
+                        id: 4,
+                        ..Default::default()
+                    }))
+                }
+            });
+        let user = repo(mock_db).get(4).await.unwrap().unwrap();
+        assert_eq!(user.id, 4);
+    }
+
+    #[tokio::test]
+    async fn test_get_does_not_retry_other_errors() {
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_find_one::<User>()
+            .times(1)
+            .returning(|_, _, _, _| Err(crate::test_support::mongo_error("boom")));
+        assert!(repo(mock_db).get(4).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_insert_encrypts_phone_and_get_decrypts_it() {
+        let user = User {
+            id: 4,
+            phone: "56565656".to_string(),
+            ..Default::default()
+        };
+        let stored = Arc::new(std::sync::Mutex::new(None::<User>));
+        let mut mock_db = AppDatabase::default();
+        let written = stored.clone();
+        mock_db
+            .expect_insert_one::<User>()
+            .withf(|_, _, user: &User, _| user.phone.starts_with("enc:v1:"))
+            .times(1)
+            .returning(move |_, _, user, _| {
+                *written.lock().unwrap() = Some(user.clone());
+                Ok(InsertOneResult {
+                    inserted_id: String::new(),
+                })
+            });
+        let read = stored.clone();
+        mock_db
+            .expect_find_one::<User>()
+            .times(1)
+            .returning(move |_, _, _, _| Ok(read.lock().unwrap().clone()));
+        let repo = encrypting_repo(mock_db);
+        repo.insert(&user).await.unwrap();
+        assert_eq!(repo.get(4).await.unwrap(), Some(user));
+    }
+
+    #[tokio::test]
+    async fn test_audited_records_both_versions() {
+        let mut mock_db = AppDatabase::default();
+        let mut seq = mockall::Sequence::new();
+        for name in ["before", "after"] {
+            mock_db
+                .expect_find_one::<User>()
+                .times(1)
+                .in_sequence(&mut seq)
+                .returning(move |_, _, _, _| {
+                    Ok(Some(User {
+                        id: 4,
+                        name: name.to_string(),
+                        ..Default::default()
+                    }))
+                });
+        }
+        mock_db
+            .expect_insert_one::<AuditEntry>()
+            .withf(|_, _, entry: &AuditEntry, _| {
+                entry.operation == AuditOperation::Update
+                    && entry.old.as_ref().is_some_and(|user| user.name == "before")
+                    && entry.new.as_ref().is_some_and(|user| user.name == "after")
+            })
+            .times(1)
+            .returning(|_, _, _, _| {
+                Ok(InsertOneResult {
+                    inserted_id: String::new(),
+                })
+            });
+        let repo = repo(mock_db);
+        let write = async {
+            Ok(UpdateResult {
+                matched_count: 1,
+                modified_count: 1,
+            })
+        };
+        let result = repo
+            .audited(4, crate::test_support::NOW, write)
+            .await
+            .unwrap();
+        assert_eq!(result.modified_count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_history_decrypts_audited_phones() {
+        let user = User {
+            id: 4,
+            phone: "56565656".to_string(),
+            ..Default::default()
+        };
+        let stored = Arc::new(std::sync::Mutex::new(Vec::<AuditEntry>::new()));
+        let mut mock_db = AppDatabase::default();
+        let written = stored.clone();
+        mock_db
+            .expect_insert_one::<AuditEntry>()
+            .withf(|_, _, entry: &AuditEntry, _| {
+                entry
+                    .new
+                    .as_ref()
+                    .is_some_and(|user| user.phone.starts_with("enc:v1:"))
+            })
+            .times(1)
+            .returning(move |_, _, entry, _| {
+                written.lock().unwrap().push(entry.clone());
+                Ok(InsertOneResult {
+                    inserted_id: String::new(),
+                })
+            });
+        let read = stored.clone();
+        mock_db
+            .expect_find_many::<AuditEntry>()
+            .with(
+                eq(DB_NAME),
+                eq(audit::AUDIT_COLLECTION),
+                eq(Some(doc! {"user_id": 4_i64})),
+                always(),
+            )
+            .times(1)
+            .returning(move |_, _, _, _| Ok(read.lock().unwrap().clone()));
+        let repo = encrypting_repo(mock_db);
+        let entry = AuditEntry {
+            user_id: 4,
+            operation: crate::models::AuditOperation::Create,
+            old: None,
+            new: Some(user.clone()),
+            timestamp: crate::test_support::NOW,
+        };
+        repo.record_audit(&entry).await;
+        let history = repo.history(4).await.unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].new, Some(user));
+    }
+
+    #[tokio::test]
+    async fn test_get_fails_on_tampered_phone() {
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_find_one::<User>()
+            .times(1)
+            .returning(|_, _, _, _| {
+                Ok(Some(User {
+                    id: 4,
+                    phone: "enc:v1:AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA".to_string(),
+                    ..Default::default()
+                }))
+            });
+        let err = encrypting_repo(mock_db).get(4).await.unwrap_err();
+        assert_eq!(err.status(), axum::http::StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn test_get_reports_unparsable_document() {
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_find_one::<User>()
+            .times(1)
+            .returning(|_, _, _, _| {
+                let drifted = mongodb::bson::from_document::<User>(doc! {"id": "four"});
+                Err(MongoError::from(drifted.unwrap_err()))
+            });
+        let err = repo(mock_db).get(4).await.unwrap_err();
+        let AppError::StoredDocument(details) = &err else {
+            panic!("expected a stored document error, got {err:?}");
+        };
+        assert!(details.starts_with("user 4: "), "{details}");
+        assert_eq!(err.status(), axum::http::StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(
+            err.message(false),
+            crate::error::STORED_DOCUMENT_MESSAGE.to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get() {
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_find_one::<User>()
+            .with(
+                eq(DB_NAME),
+                eq(USERS_COLLECTION),
+                eq(Some(live(doc! {"id": 4_i64}))),
+                always(),
+            )
+            .times(1)
+            .returning(|_, _, _, _| {
+                Ok(Some(User {
+                    id: 4,
+                    ..Default::default()
+                }))
+            });
+        let user = repo(mock_db).get(4).await.unwrap().unwrap();
+        assert_eq!(user.id, 4);
+    }
+
+    #[test]
+    fn test_live_filter_leaves_out_deleted_users() {
+        let mock_db = AppDatabase::default();
+        let repo = repo(mock_db);
+        let filter = doc! {"name": "Sibu", "deleted_at": {"$exists": true}};
+        assert_eq!(
+            repo.live(filter.clone()),
+            doc! {"name": "Sibu", "deleted_at": {"$exists": false}}
+        );
+        assert_eq!(repo.with_deleted().live(filter.clone()), filter);
+    }
+
+    #[tokio::test]
+    async fn test_with_deleted_reads_deleted_users() {
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_find_one::<User>()
+            .with(
+                eq(DB_NAME),
+                eq(USERS_COLLECTION),
+                eq(Some(doc! {"id": 4_i64})),
+                always(),
+            )
+            .times(1)
+            .returning(|_, _, _, _| {
+                Ok(Some(User {
+                    id: 4,
+                    deleted_at: Some(crate::test_support::NOW),
+                    ..Default::default()
+                }))
+            });
+        let user = repo(mock_db).with_deleted().get(4).await.unwrap().unwrap();
+        assert!(user.deleted_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_next_id_counts_deleted_users() {
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_find_many::<User>()
+            .with(
+                eq(DB_NAME),
+                eq(USERS_COLLECTION),
+                eq(Some(doc! {})),
+                always(),
+            )
+            .times(1)
+            .returning(|_, _, _, _| {
+                Ok(vec![User {
+                    id: 9,
+                    deleted_at: Some(crate::test_support::NOW),
+                    ..Default::default()
+                }])
+            });
+        assert_eq!(repo(mock_db).next_id().await.unwrap(), 10);
+    }
+
+    #[tokio::test]
+    async fn test_insert_or_get_filters_by_id() {
+        let user = User {
+            id: 8,
+            ..Default::default()
+        };
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_insert_or_get::<User>()
+            .with(
+                eq(DB_NAME),
+                eq(USERS_COLLECTION),
+                eq(doc! {"id": 8_i64}),
+                eq(user.clone()),
+            )
+            .times(1)
+            .returning(|_, _, _, _| Ok(true));
+        assert!(repo(mock_db).insert_or_get(&user).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_update() {
+        let update = doc! {"$set": {"name": "Sibu"}};
+        let mut mock_db = AppDatabase::default();
+        mock_db
+            .expect_update_one()
+            .with(
+                eq(DB_NAME),
+                eq(USERS_COLLECTION),
+                eq(live(doc! {"id": 8_i64})),
+                eq(update.clone()),
+                always(),
+            )
+            .times(1)
+            .returning(|_, _, _, _, _| {
+                Ok(UpdateResult {
+                    matched_count: 1,
+                    modified_count: 1,
+                })
+            });
+        let result = repo(mock_db).update(8, update).await.unwrap();
+        assert_eq!(result.matched_count, 1);
+    }
+}
@@ -0,0 +1,213 @@
+use std::sync::Arc;
+
+use axum::{
+    body::{self, Bytes, Full, HttpBody, StreamBody},
+    extract::{MatchedPath, Query, State},
+    http::{header, HeaderValue, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use futures::{stream, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{buffer::buffer_response, config::Config, error::AppError, select::SelectableFields};
+
+// successful response rendered as `{"success": true, "data": ...}`,
+// mirroring the `{"success": false, "message": ...}` shape of AppError
+#[derive(Debug)]
+pub struct ApiResponse<T> {
+    status: StatusCode,
+    data: T,
+    total: Option<u64>,
+    partial: bool,
+    selectable: Option<SelectableFields>,
+}
+
+impl<T: Serialize> ApiResponse<T> {
+    pub fn ok(data: T) -> Self {
+        Self::with_status(StatusCode::OK, data)
+    }
+
+    pub fn with_status(status: StatusCode, data: T) -> Self {
+        Self {
+            status,
+            data,
+            total: None,
+            partial: false,
+            selectable: None,
+        }
+    }
+
+    // total number of matching items, for the paginated list responses
+    pub fn with_total(mut self, total: u64) -> Self {
+        self.total = Some(total);
+        self
+    }
+
+    // the data is incomplete, e.g. a list cut short by its deadline
+    pub fn partial(mut self) -> Self {
+        self.partial = true;
+        self
+    }
+
+    // let clients pick some of these fields of the data with `?select=`
+    pub fn selectable(mut self, fields: &'static [&'static str]) -> Self {
+        self.selectable = Some(SelectableFields(fields));
+        self
+    }
+}
+
+#[derive(Serialize)]
+struct Envelope<'a, T> {
+    success: bool,
+    data: &'a T,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    total: Option<u64>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    partial: bool,
+}
+
+// marker set on responses whose body is wrapped in an envelope
+#[derive(Debug, Clone, Copy)]
+struct Enveloped;
+
+impl<T: Serialize> IntoResponse for ApiResponse<T> {
+    fn into_response(self) -> Response {
+        let envelope = Envelope {
+            success: true,
+            data: &self.data,
+            total: self.total,
+            partial: self.partial,
+        };
+        let mut res = (self.status, Json(envelope)).into_response();
+        res.extensions_mut().insert(Enveloped);
+        if let Some(selectable) = self.selectable {
+            res.extensions_mut().insert(selectable);
+        }
+        res
+    }
+}
+
+// the items of a stream as the data of an envelope, each one is written
+// out as soon as the stream yields it. the status is sent before the
+// first item, a failing item can only cut the response short
+pub fn streamed_array<S, T>(items: S) -> Response
+where
+    S: Stream<Item = Result<T, AppError>> + Send + 'static,
+    T: Serialize,
+{
+    let open = stream::once(async {
+        Ok::<_, std::io::Error>(Bytes::from_static(b"{\"success\":true,\"data\":["))
+    });
+    let items = items.enumerate().map(|(index, item)| {
+        let item = item.map_err(|err| {
+            tracing::error!("failed to stream response: {:?}", err);
+            std::io::Error::other("failed to stream response")
+        })?;
+        let mut bytes = if index == 0 {
+            Vec::new()
+        } else {
+            b",".to_vec()
+        };
+        serde_json::to_writer(&mut bytes, &item)?;
+        Ok::<_, std::io::Error>(Bytes::from(bytes))
+    });
+    let close = stream::once(async { Ok::<_, std::io::Error>(Bytes::from_static(b"]}")) });
+    let body = StreamBody::new(open.chain(items).chain(close));
+    let mut res = (
+        [(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        )],
+        body,
+    )
+        .into_response();
+    res.extensions_mut().insert(Enveloped);
+    res
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EnvelopeParams {
+    envelope: Option<bool>,
+}
+
+// middleware stripping the envelope when the client asks for `?envelope=false`
+pub async fn envelope_opt_out<B>(
+    Query(params): Query<EnvelopeParams>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let res = next.run(req).await;
+    if params.envelope != Some(false) || res.extensions().get::<Enveloped>().is_none() {
+        return res;
+    }
+    let (mut parts, bytes) = match buffer_response(res).await {
+        Ok(buffered) => buffered,
+        Err(res) => return res,
+    };
+    let data = serde_json::from_slice::<serde_json::Value>(&bytes)
+        .ok()
+        .and_then(|mut value| value.get_mut("data").map(serde_json::Value::take));
+    let Some(data) = data else {
+        return Response::from_parts(parts, body::boxed(Full::from(bytes)));
+    };
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts.headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    Response::from_parts(parts, body::boxed(Full::from(data.to_string())))
+}
+
+// the value with the keys of every object sorted, serde_json keeps them in
+// insertion order as `preserve_order` is enabled through bson
+pub fn canonicalize(value: Value) -> Value {
+    match value {
+        Value::Array(items) => Value::Array(items.into_iter().map(canonicalize).collect()),
+        Value::Object(object) => {
+            let mut entries: Vec<(String, Value)> = object.into_iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key, canonicalize(value)))
+                    .collect(),
+            )
+        }
+        other => other,
+    }
+}
+
+// middleware sorting the keys of the JSON bodies of the routes listed in
+// `canonical_json_routes`, and writing them without whitespace, so the
+// same data always comes out as the same bytes. streamed bodies pass as is
+pub async fn canonical_json<B>(
+    State(config): State<Arc<Config>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let base_path = config.base_path.as_deref().unwrap_or("");
+    let canonical = req.extensions().get::<MatchedPath>().is_some_and(|path| {
+        let route = path
+            .as_str()
+            .strip_prefix(base_path)
+            .unwrap_or(path.as_str());
+        config.canonical_json_routes.iter().any(|r| r == route)
+    });
+    let res = next.run(req).await;
+    if !canonical || res.body().size_hint().exact().is_none() {
+        return res;
+    }
+    let (mut parts, bytes) = match buffer_response(res).await {
+        Ok(buffered) => buffered,
+        Err(res) => return res,
+    };
+    let Ok(value) = serde_json::from_slice::<Value>(&bytes) else {
+        return Response::from_parts(parts, body::boxed(Full::from(bytes)));
+    };
+    parts.headers.remove(header::CONTENT_LENGTH);
+    let body = canonicalize(value).to_string();
+    Response::from_parts(parts, body::boxed(Full::from(body)))
+}
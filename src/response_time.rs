@@ -0,0 +1,21 @@
+use std::time::Instant;
+
+use axum::{
+    http::{HeaderValue, Request},
+    middleware::Next,
+    response::Response,
+};
+
+pub const RESPONSE_TIME_HEADER: &str = "x-response-time-ms";
+
+// middleware telling the client how long the request took to handle, in
+// milliseconds with a fractional part; the body may still be streaming
+pub async fn record_response_time<B>(req: Request<B>, next: Next<B>) -> Response {
+    let started = Instant::now();
+    let mut res = next.run(req).await;
+    let elapsed_ms = started.elapsed().as_secs_f64() * 1000.0;
+    let value = HeaderValue::from_str(&format!("{elapsed_ms:.3}"))
+        .expect("a number is a valid header value");
+    res.headers_mut().insert(RESPONSE_TIME_HEADER, value);
+    res
+}
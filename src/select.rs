@@ -0,0 +1,115 @@
+use std::collections::BTreeMap;
+
+use axum::{
+    body::{self, Full},
+    extract::Query,
+    http::{header, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{buffer::buffer_response, error::AppError};
+
+// top level fields of the data of a response which `?select=` can pick
+// from, responses without it are left alone
+#[derive(Debug, Clone, Copy)]
+pub struct SelectableFields(pub &'static [&'static str]);
+
+// fields to keep, `a.b` keeps only `b` of the object in `a`; an empty
+// selection below a field keeps the whole of it
+#[derive(Debug, Default, PartialEq)]
+struct Selection(BTreeMap<String, Selection>);
+
+impl Selection {
+    fn parse(select: &str) -> Self {
+        let mut selection = Selection::default();
+        let paths = select.split(',').map(str::trim).filter(|p| !p.is_empty());
+        for path in paths {
+            let mut node = &mut selection;
+            for name in path.split('.') {
+                node = node.0.entry(name.to_string()).or_default();
+            }
+        }
+        selection
+    }
+
+    fn apply(&self, value: &mut Value) {
+        if self.0.is_empty() {
+            return;
+        }
+        match value {
+            Value::Array(items) => items.iter_mut().for_each(|item| self.apply(item)),
+            Value::Object(object) => {
+                object.retain(|key, _| self.0.contains_key(key));
+                for (key, value) in object.iter_mut() {
+                    self.0[key].apply(value);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SelectParams {
+    select: Option<String>,
+}
+
+// middleware keeping only the `?select=id,name` fields of the response
+// data; it works on the serialized JSON so every handler gets it for free
+pub async fn select_fields<B>(
+    Query(params): Query<SelectParams>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let res = next.run(req).await;
+    let (Some(select), Some(SelectableFields(fields))) = (
+        params.select,
+        res.extensions().get::<SelectableFields>().copied(),
+    ) else {
+        return res;
+    };
+    let selection = Selection::parse(&select);
+    if let Some(unknown) = selection
+        .0
+        .keys()
+        .find(|name| !fields.contains(&name.as_str()))
+    {
+        return AppError::BadRequest(format!("cannot select unknown field `{unknown}`"))
+            .into_response();
+    }
+    let (mut parts, bytes) = match buffer_response(res).await {
+        Ok(buffered) => buffered,
+        Err(res) => return res,
+    };
+    let Ok(mut envelope) = serde_json::from_slice::<Value>(&bytes) else {
+        return Response::from_parts(parts, body::boxed(Full::from(bytes)));
+    };
+    if let Some(data) = envelope.get_mut("data") {
+        selection.apply(data);
+    }
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, body::boxed(Full::from(envelope.to_string())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_selection_keeps_nested_fields() {
+        let selection = Selection::parse("id, address.city");
+        let mut value = json!([
+            {"id": 1, "name": "Sibu", "address": {"city": "Puri", "zip": "752001"}},
+            {"id": 2, "name": "Sibaprasad"},
+        ]);
+        selection.apply(&mut value);
+        assert_eq!(
+            value,
+            json!([{"id": 1, "address": {"city": "Puri"}}, {"id": 2}])
+        );
+    }
+}
@@ -0,0 +1,72 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+// resolves when the process receives Ctrl+C or SIGTERM
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+    tracing::debug!("shutdown signal received");
+}
+
+// keeps count of the connections which are currently open
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionTracker(Arc<AtomicUsize>);
+
+impl ConnectionTracker {
+    // register a new connection, it is released when the guard is dropped
+    pub fn track(&self) -> ConnectionGuard {
+        self.0.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard(self.0.clone())
+    }
+
+    pub fn active(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug)]
+pub struct ConnectionGuard(Arc<AtomicUsize>);
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_connection_tracker() {
+        let tracker = ConnectionTracker::default();
+        let first = tracker.track();
+        let second = tracker.track();
+        assert_eq!(tracker.active(), 2);
+        drop(first);
+        assert_eq!(tracker.active(), 1);
+        drop(second);
+        assert_eq!(tracker.active(), 0);
+    }
+}
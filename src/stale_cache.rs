@@ -0,0 +1,132 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+};
+
+use crate::models::User;
+
+// most users kept by `StaleUsers`, the least recently used one makes room
+// for a new one past it
+const STALE_USERS_CAPACITY: usize = 10_000;
+
+#[derive(Debug)]
+struct Entries {
+    capacity: usize,
+    // the user and the tick it was last used at
+    users: HashMap<i64, (u64, User)>,
+    // the ids by the tick they were last used at, oldest first
+    by_use: BTreeMap<u64, i64>,
+    tick: u64,
+}
+
+impl Entries {
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    fn remove(&mut self, id: i64) {
+        if let Some((used, _)) = self.users.remove(&id) {
+            self.by_use.remove(&used);
+        }
+    }
+}
+
+// last copy of the users read by id, served instead of an error while the
+// database is unavailable when `serve_stale_on_error` is set
+#[derive(Debug, Clone)]
+pub struct StaleUsers(Arc<Mutex<Entries>>);
+
+impl Default for StaleUsers {
+    fn default() -> Self {
+        Self::with_capacity(STALE_USERS_CAPACITY)
+    }
+}
+
+impl StaleUsers {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self(Arc::new(Mutex::new(Entries {
+            capacity,
+            users: HashMap::new(),
+            by_use: BTreeMap::new(),
+            tick: 0,
+        })))
+    }
+
+    pub fn get(&self, id: i64) -> Option<User> {
+        let mut entries = self.0.lock().unwrap();
+        let tick = entries.next_tick();
+        let (used, user) = entries.users.get_mut(&id)?;
+        let last_used = std::mem::replace(used, tick);
+        let user = user.clone();
+        entries.by_use.remove(&last_used);
+        entries.by_use.insert(tick, id);
+        Some(user)
+    }
+
+    // remember the result of a successful read, a user that is gone is
+    // forgotten so it is not served again
+    pub fn update(&self, id: i64, user: Option<&User>) {
+        let mut entries = self.0.lock().unwrap();
+        entries.remove(id);
+        let Some(user) = user else {
+            return;
+        };
+        if entries.users.len() >= entries.capacity {
+            match entries.by_use.pop_first() {
+                Some((_, oldest)) => {
+                    entries.users.remove(&oldest);
+                }
+                None => return,
+            }
+        }
+        let tick = entries.next_tick();
+        entries.users.insert(id, (tick, user.clone()));
+        entries.by_use.insert(tick, id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(id: i64) -> User {
+        User {
+            id,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_stale_users_forget_missing_user() {
+        let users = StaleUsers::default();
+        users.update(7, Some(&user(7)));
+        assert_eq!(users.get(7).map(|user| user.id), Some(7));
+        users.update(7, None);
+        assert!(users.get(7).is_none());
+    }
+
+    #[test]
+    fn test_stale_users_evict_least_recently_used() {
+        let users = StaleUsers::with_capacity(2);
+        users.update(1, Some(&user(1)));
+        users.update(2, Some(&user(2)));
+        // reading 1 makes 2 the least recently used
+        assert!(users.get(1).is_some());
+        users.update(3, Some(&user(3)));
+        assert!(users.get(2).is_none());
+        assert!(users.get(1).is_some());
+        assert!(users.get(3).is_some());
+        // refreshing a user already kept evicts nothing
+        users.update(3, Some(&user(3)));
+        assert!(users.get(1).is_some());
+        assert_eq!(users.0.lock().unwrap().users.len(), 2);
+    }
+
+    #[test]
+    fn test_stale_users_zero_capacity_keeps_nothing() {
+        let users = StaleUsers::with_capacity(0);
+        users.update(1, Some(&user(1)));
+        assert!(users.get(1).is_none());
+    }
+}
@@ -0,0 +1,95 @@
+use std::sync::Arc;
+
+use axum::Router;
+use mongodb::{
+    bson::{doc, DateTime, Decimal128, Document},
+    error::Error as MongoError,
+};
+
+use crate::{
+    bson_json::DECIMAL_EXPONENT_BIAS,
+    build_app,
+    clock::FixedClock,
+    config::Config,
+    database::InsertOneResult,
+    dedup::RecentSubmissions,
+    maintenance::MaintenanceMode,
+    metrics::Metrics,
+    models::{AuditEntry, User},
+    quota::DailyQuota,
+    stale_cache::StaleUsers,
+    AppDatabase, AppState,
+};
+
+pub const NOW: DateTime = DateTime::from_millis(1_676_000_000_000);
+
+// `filter` as the repo sends it, restricted to the users not soft deleted
+pub fn live(mut filter: Document) -> Document {
+    filter.insert("deleted_at", doc! {"$exists": false});
+    filter
+}
+
+pub fn test_state(mock_db: AppDatabase) -> AppState {
+    AppState {
+        db: Arc::new(mock_db),
+        maintenance: MaintenanceMode::default(),
+        clock: Arc::new(FixedClock(NOW)),
+        config: Arc::new(Config::default()),
+        phone_cipher: None,
+        quota: DailyQuota::default(),
+        submissions: RecentSubmissions::default(),
+        stale_users: StaleUsers::default(),
+        metrics: Metrics::default(),
+    }
+}
+
+// the whole app, outer middleware included, on top of the given database
+pub fn test_app(db: Arc<AppDatabase>) -> Router {
+    let state = AppState {
+        db,
+        ..test_state(AppDatabase::default())
+    };
+    build_app(state)
+}
+
+// state with `s3cret` as the admin token
+pub fn admin_state(mock_db: AppDatabase) -> AppState {
+    AppState {
+        config: Arc::new(Config {
+            admin_token: Some("s3cret".to_string()),
+            ..Default::default()
+        }),
+        ..test_state(mock_db)
+    }
+}
+
+// build a decimal from its coefficient and exponent
+pub fn decimal(negative: bool, coefficient: u128, exponent: i32) -> Decimal128 {
+    let biased = (exponent + DECIMAL_EXPONENT_BIAS) as u128;
+    let sign = if negative { 1 << 127 } else { 0 };
+    Decimal128::from_bytes((sign | biased << 113 | coefficient).to_le_bytes())
+}
+
+// an arbitrary driver error for the mocks to return
+pub fn mongo_error(message: &str) -> MongoError {
+    MongoError::from(std::io::Error::other(message))
+}
+
+// answer every read by id with `user`, for the writes reading the user
+// before and after to audit the change
+pub fn allow_user_reads(mock_db: &mut AppDatabase, user: User) {
+    mock_db
+        .expect_find_one::<User>()
+        .returning(move |_, _, _, _| Ok(Some(user.clone())));
+}
+
+// accept any number of audit entries, for the tests not about auditing
+pub fn allow_audit(mock_db: &mut AppDatabase) {
+    mock_db
+        .expect_insert_one::<AuditEntry>()
+        .returning(|_, _, _, _| {
+            Ok(InsertOneResult {
+                inserted_id: String::new(),
+            })
+        });
+}
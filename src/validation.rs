@@ -0,0 +1,205 @@
+use serde::Serialize;
+
+use crate::{error::AppError, models::User};
+
+// a single invalid field of a request payload
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+// collects every failed check so they can be reported all at once
+#[derive(Debug, Default)]
+pub struct Validator {
+    errors: Vec<FieldError>,
+}
+
+impl Validator {
+    pub fn check(&mut self, valid: bool, field: &str, message: &str) {
+        if !valid {
+            self.errors.push(FieldError {
+                field: field.to_string(),
+                message: message.to_string(),
+            });
+        }
+    }
+
+    // a well-formed email using one of the allowed domains
+    pub fn check_email(&mut self, email: &str, allowed_domains: &[String]) {
+        if !is_valid_email(email) {
+            self.check(false, "email", "must be a valid email");
+            return;
+        }
+        self.check(
+            is_allowed_email_domain(email, allowed_domains),
+            "email",
+            &format!("domain must be one of: {}", allowed_domains.join(", ")),
+        );
+    }
+
+    pub fn finish(self) -> Result<(), AppError> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(AppError::Validation(self.errors))
+        }
+    }
+}
+
+// maximum length of the user name, in characters
+pub const MAX_NAME_LEN: usize = 100;
+
+pub fn is_valid_phone(phone: &str) -> bool {
+    let digits = phone.strip_prefix('+').unwrap_or(phone);
+    (6..=15).contains(&digits.len()) && digits.chars().all(|c| c.is_ascii_digit())
+}
+
+pub fn is_valid_email(email: &str) -> bool {
+    let Some((local, domain)) = email.split_once('@') else {
+        return false;
+    };
+    !local.is_empty()
+        && !domain.contains('@')
+        && domain.contains('.')
+        && !domain.starts_with('.')
+        && !domain.ends_with('.')
+        && !email.chars().any(char::is_whitespace)
+}
+
+// true when the domain of a well-formed email is in the allowlist, an
+// empty allowlist accepts every domain
+pub fn is_allowed_email_domain(email: &str, allowed_domains: &[String]) -> bool {
+    let domain = email.rsplit_once('@').map_or("", |(_, domain)| domain);
+    allowed_domains.is_empty()
+        || allowed_domains
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(domain))
+}
+
+impl User {
+    // validate the client supplied fields, the field names match the JSON payload
+    pub fn validate(
+        &self,
+        allowed_email_domains: &[String],
+        require_email: bool,
+    ) -> Result<(), AppError> {
+        let mut validator = Validator::default();
+        validator.check(self.id > 0, "id", "must be greater than 0");
+        validator.check(!self.name.trim().is_empty(), "name", "must not be empty");
+        validator.check(
+            self.name.chars().count() <= MAX_NAME_LEN,
+            "name",
+            &format!("must be at most {MAX_NAME_LEN} characters"),
+        );
+        validator.check(
+            is_valid_phone(&self.phone),
+            "phone",
+            "must contain 6 to 15 digits",
+        );
+        match &self.email {
+            Some(email) => validator.check_email(email, allowed_email_domains),
+            None => validator.check(!require_email, "email", "is required"),
+        }
+        validator.check(
+            self.deleted_at.is_none(),
+            "deleted_at",
+            "is set by the server",
+        );
+        validator.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_valid_email() {
+        assert!(is_valid_email("sibu@example.com"));
+        assert!(!is_valid_email("sibu"));
+        assert!(!is_valid_email("@example.com"));
+        assert!(!is_valid_email("sibu@example"));
+        assert!(!is_valid_email("sibu@@example.com"));
+        assert!(!is_valid_email("si bu@example.com"));
+    }
+
+    #[test]
+    fn test_is_valid_phone() {
+        assert!(is_valid_phone("56565656"));
+        assert!(is_valid_phone("+9156565656"));
+        assert!(!is_valid_phone("5656"));
+        assert!(!is_valid_phone("5656-5656"));
+    }
+
+    #[test]
+    fn test_validate_collects_all_errors() {
+        let user = User {
+            id: 0,
+            name: String::new(),
+            phone: "abc".to_string(),
+            ..Default::default()
+        };
+        let Err(AppError::Validation(errors)) = user.validate(&[], false) else {
+            panic!("expected validation errors");
+        };
+        let fields: Vec<&str> = errors.iter().map(|e| e.field.as_str()).collect();
+        assert_eq!(fields, vec!["id", "name", "phone"]);
+    }
+
+    fn user_with_email(email: &str) -> User {
+        User {
+            id: 1,
+            name: "Sibu".to_string(),
+            phone: "56565656".to_string(),
+            email: Some(email.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_validate_email_domain_allowlist() {
+        let allowed = vec!["example.com".to_string(), "corp.example.com".to_string()];
+        assert!(user_with_email("sibu@Example.com")
+            .validate(&allowed, false)
+            .is_ok());
+        let Err(AppError::Validation(errors)) =
+            user_with_email("sibu@other.com").validate(&allowed, false)
+        else {
+            panic!("expected validation errors");
+        };
+        assert_eq!(
+            errors,
+            vec![FieldError {
+                field: "email".to_string(),
+                message: "domain must be one of: example.com, corp.example.com".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_validate_required_email() {
+        let user = User {
+            email: None,
+            ..user_with_email("sibu@example.com")
+        };
+        assert!(user.validate(&[], false).is_ok());
+        let Err(AppError::Validation(errors)) = user.validate(&[], true) else {
+            panic!("expected validation errors");
+        };
+        assert_eq!(
+            errors,
+            vec![FieldError {
+                field: "email".to_string(),
+                message: "is required".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_validate_any_email_domain_when_unset() {
+        assert!(user_with_email("sibu@other.com")
+            .validate(&[], false)
+            .is_ok());
+    }
+}